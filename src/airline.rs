@@ -0,0 +1,205 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::File;
+use crate::lookups;
+use crate::watchlist::Pattern;
+
+/// Optional ICAO designator table: designator,telephony,name,country,military
+pub const DEFAULT_TABLE: &str = "airlines.csv";
+
+/// Optional file with our own entries (same columns), these win over everything else
+pub const DEFAULT_OVERRIDES: &str = "airlines_local.csv";
+
+// Designators everybody sees, so the Operator column works without a table.
+// (designator or callsign pattern, telephony, name, country, military)
+const BUILT_IN: [(&str, &str, &str, &str, bool); 23] = [
+    // Military
+    ("RCH", "REACH", "US Air Force (AMC)", "United States", true),
+    ("RRR", "ASCOT", "Royal Air Force", "United Kingdom", true),
+    ("RRF", "", "Royal Air Force", "United Kingdom", true),
+    ("CNV", "CONVOY", "US Navy", "United States", true),
+    ("GAF", "GERMAN AIR FORCE", "German Air Force", "Germany", true),
+    ("IAM", "ITALIAN AIRFORCE", "Italian Air Force", "Italy", true),
+    ("FAF", "FRENCH AIR FORCE", "French Air Force", "France", true),
+    ("SUI", "SWISS AIR FORCE", "Swiss Air Force", "Switzerland", true),
+    ("HVK", "TURKISH AIRFORCE", "Turkish Air Force", "Turkey", true), // (Hava Kuvvetleri)
+    // Military callsigns that aren't ICAO designators
+    ("NATO*", "NATO", "NATO E-3A Component", "NATO", true),
+    ("TUAF*", "TURKISH AIRFORCE", "Turkish Air Force", "Turkey", true),
+    ("FORTE*", "FORTE", "US Air Force (RQ-4 Global Hawk)", "United States", true),
+    // Civil Airlines
+    ("DLH", "LUFTHANSA", "Lufthansa", "Germany", false),
+    ("RYR", "RYANAIR", "Ryanair", "Ireland", false),
+    ("EZY", "EASY", "EasyJet", "United Kingdom", false),
+    ("BAW", "SPEEDBIRD", "British Airways", "United Kingdom", false),
+    ("AFR", "AIRFRANS", "Air France", "France", false),
+    ("KLM", "KLM", "KLM Royal Dutch Airlines", "Netherlands", false),
+    ("SAS", "SCANDINAVIAN", "Scandinavian Airlines", "Sweden", false),
+    ("SVA", "SAUDIA", "Saudia", "Saudi Arabia", false),
+    ("UAE", "EMIRATES", "Emirates", "United Arab Emirates", false),
+    ("QTR", "QATARI", "Qatar Airways", "Qatar", false),
+    ("THY", "TURKISH", "Turkish Airlines", "Turkey", false),
+];
+
+/// Who flies under a callsign.
+#[derive(Debug, Clone)]
+pub struct Airline {
+    pub name: String,
+    pub telephony: String, // what they say on the radio, e.g. "REACH" for RCH (may be empty)
+    pub country: String,
+    pub military: bool,
+}
+
+// One file (or the built-in list): three letter designators and callsign patterns
+#[derive(Default)]
+struct Layer {
+    designators: HashMap<String, Airline>,
+    patterns: Vec<(Pattern, Airline)>,
+}
+
+impl Layer {
+    // A 3 letter code is a designator, anything else a pattern on the whole callsign ("NATO*", "/^FORTE\d+$/")
+    fn add(&mut self, key: &str, airline: Airline) -> Result<(), Box<dyn Error>> {
+        let key = key.trim();
+        if key.len() == 3 && key.chars().all(|c| c.is_ascii_alphabetic()) {
+            self.designators.insert(key.to_uppercase(), airline);
+        } else {
+            self.patterns.push((Pattern::parse(key)?, airline)); // not uppercased, "\d" has to stay a digit
+        }
+        Ok(())
+    }
+
+    fn lookup(&self, callsign: &str) -> Option<&Airline> {
+        self.patterns.iter()
+            .find(|(pattern, _)| pattern.matches(Some(callsign)))
+            .map(|(_, airline)| airline)
+            .or_else(|| designator(callsign).and_then(|d| self.designators.get(d)))
+    }
+
+    fn len(&self) -> usize {
+        self.designators.len() + self.patterns.len()
+    }
+}
+
+/// The designator of an ICAO callsign: 3 letters and a flight number that starts with a digit ("RCH4A", "BAW12AB").
+/// Registrations ("DEABC") and other callsigns ("NATO01") have none.
+pub fn designator(callsign: &str) -> Option<&str> {
+    let (prefix, flight) = (callsign.get(..3)?, callsign.get(3..)?);
+    let valid = prefix.chars().all(|c| c.is_ascii_uppercase())
+        && flight.starts_with(|c: char| c.is_ascii_digit())
+        && flight.len() <= 4
+        && flight.chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit());
+    valid.then_some(prefix)
+}
+
+/// Airline designators and military callsigns: the user's overrides first, then the table, then the built-in list.
+pub struct AirlineTable {
+    layers: Vec<Layer>,
+}
+
+impl AirlineTable {
+    /// Stacks the local overrides on the designator table on the built-in callsigns,
+    /// the first layer that knows a callsign answers.
+    pub fn load(table: Option<&str>, overrides: Option<&str>) -> Result<Self, Box<dyn Error>> {
+        let mut layers = Vec::new();
+
+        for (path, default) in [(overrides, DEFAULT_OVERRIDES), (table, DEFAULT_TABLE)] {
+            let file_path = path.unwrap_or(default);
+            let file = lookups::open_optional(path, default).map_err(|e| format!("Airlines '{}': {}", file_path, e))?;
+            if let Some(file) = file {
+                let layer = Self::read(file)?;
+                println!("Loaded {} airline designators from '{}'.", layer.len(), file_path);
+                layers.push(layer);
+            }
+        }
+
+        layers.extend(Self::built_in().layers);
+        Ok(Self { layers })
+    }
+
+    /// Only the designators and callsigns that ship with the tool
+    pub fn built_in() -> Self {
+        let mut built_in = Layer::default();
+        for (key, telephony, name, country, military) in BUILT_IN {
+            built_in.add(key, Airline {
+                name: name.to_string(),
+                telephony: telephony.to_string(),
+                country: country.to_string(),
+                military,
+            }).expect("the built-in callsign patterns parse");
+        }
+        Self { layers: vec![built_in] }
+    }
+
+    // Columns: designator (or callsign pattern),telephony,name,country,military
+    fn read(file: File) -> Result<Layer, Box<dyn Error>> {
+        let mut layer = Layer::default();
+        let mut rdr = csv::ReaderBuilder::new().flexible(true).from_reader(file);
+
+        for record in rdr.records() {
+            let record = match record {
+                Ok(r) => r,
+                Err(e) => {
+                    eprintln!("Skipping broken airline line: {}", e);
+                    continue;
+                }
+            };
+            let get = |i: usize| record.get(i).map(str::trim).unwrap_or("").to_string();
+            let (key, name) = (get(0), get(2));
+            if key.is_empty() || name.is_empty() {
+                eprintln!("Skipping airline line without designator/name: {:?}", record);
+                continue;
+            }
+
+            let military = matches!(get(4).to_lowercase().as_str(), "yes" | "true" | "1" | "military" | "mil");
+            let airline = Airline { name, telephony: get(1).to_uppercase(), country: get(3), military };
+            if let Err(e) = layer.add(&key, airline) {
+                eprintln!("Skipping airline line with bad pattern '{}': {}", key, e);
+            }
+        }
+        Ok(layer)
+    }
+
+    /// Who flies under this callsign, None if we don't know
+    pub fn lookup(&self, callsign: &str) -> Option<&Airline> {
+        let callsign = callsign.trim().to_uppercase();
+        if callsign.is_empty() {
+            return None;
+        }
+        self.layers.iter().find_map(|layer| layer.lookup(&callsign))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn designators_need_a_flight_number() {
+        assert_eq!(designator("RCH123"), Some("RCH"));
+        assert_eq!(designator("BAW12AB"), Some("BAW"));
+        assert_eq!(designator("DLHAB"), None);
+        assert_eq!(designator("NATO01"), None);
+        assert_eq!(designator("RCH12345"), None);
+    }
+
+    #[test]
+    fn sample_table_adds_to_the_built_in_list() {
+        let table = AirlineTable::load(Some(DEFAULT_TABLE), None).unwrap();
+
+        let canforce = table.lookup("cfc4021").unwrap();
+        assert_eq!((canforce.telephony.as_str(), canforce.country.as_str(), canforce.military), ("CANFORCE", "Canada", true));
+        let aal = table.lookup("AAL100").unwrap();
+        assert_eq!((aal.name.as_str(), aal.military), ("American Airlines", false));
+
+        // the built-in entries are still there
+        assert_eq!(table.lookup("RCH123").unwrap().telephony, "REACH");
+        assert!(table.lookup("NATO01").unwrap().military);
+        assert!(table.lookup("XYZ123").is_none());
+    }
+
+    #[test]
+    fn missing_explicit_table_is_an_error() {
+        assert!(AirlineTable::load(Some("no-such-airlines.csv"), None).is_err());
+    }
+}
@@ -0,0 +1,251 @@
+use std::error::Error;
+use serde::{Deserialize, Serialize};
+use crate::geo;
+
+/// Corners of a scan area as (lat, lon), in order around the edge.
+pub type Polygon = Vec<(f64, f64)>;
+
+/// Tiles overlap a bit, so aircraft right between two circles are not missed
+const TILE_OVERLAP: f64 = 0.9;
+
+/// One point query of a tiled area scan (radius in nm).
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Tile {
+    pub lat: f64,
+    pub lon: f64,
+    pub radius: f64,
+}
+
+/// Which part of the sector a scan looked at. Aircraft last seen outside of it haven't left,
+/// we just didn't hear about them this time.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Coverage {
+    All,
+    /// Everything except the tiles that failed
+    AllBut(Vec<Tile>),
+    /// Nothing: an area that came back without a single aircraft is more likely the feed than the sky
+    Nothing,
+}
+
+impl Coverage {
+    /// Did the scan look at this position? Without one we can't tell, so only a scan that saw nothing didn't.
+    /// Tiles overlap, so a failed one may have been covered by its neighbours: we err on the side of "no".
+    pub fn covers(&self, position: Option<(f64, f64)>) -> bool {
+        match (self, position) {
+            (Coverage::All, _) => true,
+            (Coverage::Nothing, _) => false,
+            (Coverage::AllBut(_), None) => true,
+            (Coverage::AllBut(missing), Some((lat, lon))) => {
+                !missing.iter().any(|tile| geo::distance_nm(tile.lat, tile.lon, lat, lon) <= tile.radius)
+            }
+        }
+    }
+}
+
+/// Parses a bounding box "min_lat,min_lon,max_lat,max_lon" into a polygon.
+pub fn parse_bbox(text: &str) -> Result<Polygon, Box<dyn Error>> {
+    let nums: Vec<f64> = text.split(',')
+        .map(|s| s.trim().parse::<f64>())
+        .collect::<Result<_, _>>()?;
+
+    let [min_lat, min_lon, max_lat, max_lon] = nums[..] else {
+        return Err(format!("Bounding box '{}' needs 4 numbers: min_lat,min_lon,max_lat,max_lon", text).into());
+    };
+
+    check_corner(min_lat, min_lon)?;
+    check_corner(max_lat, max_lon)?;
+    if min_lat >= max_lat || min_lon >= max_lon {
+        return Err(format!("Bounding box '{}': min_lat,min_lon has to be south-west of max_lat,max_lon", text).into());
+    }
+
+    Ok(vec![(min_lat, min_lon), (min_lat, max_lon), (max_lat, max_lon), (max_lat, min_lon)])
+}
+
+// Latitude -90..90, longitude -180..180
+fn check_corner(lat: f64, lon: f64) -> Result<(), Box<dyn Error>> {
+    if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lon) {
+        return Err(format!("Corner {},{} is not a valid lat,lon", lat, lon).into());
+    }
+    Ok(())
+}
+
+/// Parses a polygon "lat,lon;lat,lon;..." (at least 3 corners).
+pub fn parse_polygon(text: &str) -> Result<Polygon, Box<dyn Error>> {
+    let mut polygon = Vec::new();
+    for corner in text.split(';').filter(|c| !c.trim().is_empty()) {
+        let Some((lat, lon)) = corner.split_once(',') else {
+            return Err(format!("Polygon corner '{}' should look like 'lat,lon'", corner).into());
+        };
+        let (lat, lon) = (lat.trim().parse::<f64>()?, lon.trim().parse::<f64>()?);
+        check_corner(lat, lon)?;
+        polygon.push((lat, lon));
+    }
+
+    if polygon.len() < 3 {
+        return Err("A polygon needs at least 3 corners".into());
+    }
+    Ok(polygon)
+}
+
+/// Writes the polygon back in the `parse_polygon` format.
+pub fn format_polygon(polygon: &Polygon) -> String {
+    polygon.iter()
+        .map(|(lat, lon)| format!("{},{}", lat, lon))
+        .collect::<Vec<_>>()
+        .join(";")
+}
+
+/// Ray casting test (lon = x, lat = y). Good enough as long as the area doesn't cross the date line.
+pub fn contains(polygon: &Polygon, lat: f64, lon: f64) -> bool {
+    let mut inside = false;
+    let mut j = polygon.len() - 1;
+
+    for i in 0..polygon.len() {
+        let (lat_i, lon_i) = polygon[i];
+        let (lat_j, lon_j) = polygon[j];
+
+        if (lat_i > lat) != (lat_j > lat)
+            && lon < (lon_j - lon_i) * (lat - lat_i) / (lat_j - lat_i) + lon_i {
+            inside = !inside;
+        }
+        j = i;
+    }
+    inside
+}
+
+/// Covers the polygon with circles of the given radius (nm) on a hexagonal grid
+/// and returns their centers. Circles that don't touch the polygon are left out.
+pub fn tile_centers(polygon: &Polygon, radius: f64) -> Vec<(f64, f64)> {
+    let min_lat = polygon.iter().map(|p| p.0).fold(f64::MAX, f64::min);
+    let max_lat = polygon.iter().map(|p| p.0).fold(f64::MIN, f64::max);
+    let min_lon = polygon.iter().map(|p| p.1).fold(f64::MAX, f64::min);
+    let max_lon = polygon.iter().map(|p| p.1).fold(f64::MIN, f64::max);
+
+    // Hex grid: neighbours sqrt(3)*r apart, rows 1.5*r apart (1 nm = 1 arc minute)
+    let col_nm = radius * 3f64.sqrt() * TILE_OVERLAP;
+    let row_deg = radius * 1.5 * TILE_OVERLAP / 60.0;
+
+    let mut centers = Vec::new();
+    let mut row = 0;
+    let mut lat = min_lat;
+
+    while lat <= max_lat + row_deg {
+        let col_deg = col_nm / (60.0 * lat.to_radians().cos().max(0.05));
+        let mut lon = min_lon - if row % 2 == 1 { col_deg / 2.0 } else { 0.0 };
+
+        while lon <= max_lon + col_deg {
+            let center = (lat.clamp(-89.9, 89.9), lon);
+            if touches(polygon, center, radius) {
+                centers.push(center);
+            }
+            lon += col_deg;
+        }
+
+        lat += row_deg;
+        row += 1;
+    }
+    centers
+}
+
+// Does a circle around `center` (radius in nm) overlap the polygon?
+fn touches(polygon: &Polygon, center: (f64, f64), radius: f64) -> bool {
+    contains(polygon, center.0, center.1) || distance_to_edge(polygon, center.0, center.1) <= radius
+}
+
+/// Distance from a point to the nearest edge of the polygon in nm (inside or outside).
+pub fn distance_to_edge(polygon: &Polygon, lat: f64, lon: f64) -> f64 {
+    // Flat projection around the point in nm, fine at tile sizes
+    let scale = lat.to_radians().cos();
+    let project = |(p_lat, p_lon): (f64, f64)| ((p_lon - lon) * 60.0 * scale, (p_lat - lat) * 60.0);
+
+    (0..polygon.len())
+        .map(|i| {
+            let a = project(polygon[i]);
+            let b = project(polygon[(i + 1) % polygon.len()]);
+            distance_to_segment(a, b)
+        })
+        .fold(f64::MAX, f64::min)
+}
+
+// Distance from the origin to the segment a-b
+fn distance_to_segment(a: (f64, f64), b: (f64, f64)) -> f64 {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let len_sq = dx * dx + dy * dy;
+    let t = if len_sq == 0.0 { 0.0 } else { (-(a.0 * dx + a.1 * dy) / len_sq).clamp(0.0, 1.0) };
+    let (x, y) = (a.0 + t * dx, a.1 + t * dy);
+    (x * x + y * y).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bbox_parsing() {
+        assert_eq!(parse_bbox("53, 9, 66, 30").unwrap(), [(53.0, 9.0), (53.0, 30.0), (66.0, 30.0), (66.0, 9.0)]);
+        assert!(parse_bbox("53,9,66").is_err());
+        assert!(parse_bbox("53,9,66,abc").is_err());
+        // flipped
+        assert!(parse_bbox("66,9,53,30").is_err());
+        assert!(parse_bbox("53,30,66,9").is_err());
+        assert!(parse_bbox("53,9,53,30").is_err());
+        // out of range
+        assert!(parse_bbox("53,9,95,30").is_err());
+        assert!(parse_bbox("53,-190,66,30").is_err());
+    }
+
+    #[test]
+    fn polygon_parsing() {
+        let polygon = parse_polygon("54,10; 60,30;66,24;").unwrap();
+        assert_eq!(polygon, [(54.0, 10.0), (60.0, 30.0), (66.0, 24.0)]);
+        assert_eq!(parse_polygon(&format_polygon(&polygon)).unwrap(), polygon);
+        assert!(parse_polygon("54,10;60,30").is_err());
+        assert!(parse_polygon("54,10;60 30;66,24").is_err());
+        assert!(parse_polygon("54,10;60,30;66,x").is_err());
+        assert!(parse_polygon("54,10;60,200;66,24").is_err());
+    }
+
+    #[test]
+    fn point_in_polygon() {
+        let square = parse_bbox("50,0,52,2").unwrap();
+        assert!(contains(&square, 51.0, 1.0));
+        assert!(!contains(&square, 53.0, 1.0));
+        assert!(!contains(&square, 51.0, -0.5));
+
+        // an L: the inner corner is outside
+        let l_shape = parse_polygon("50,0;50,4;52,4;52,2;54,2;54,0").unwrap();
+        assert!(contains(&l_shape, 51.0, 3.0));
+        assert!(contains(&l_shape, 53.0, 1.0));
+        assert!(!contains(&l_shape, 53.0, 3.0));
+    }
+
+    #[test]
+    fn tiles_cover_the_whole_area() {
+        let bbox = parse_bbox("53,9,66,30").unwrap();
+        let centers = tile_centers(&bbox, 250.0);
+        assert!(centers.len() > 1);
+
+        // every corner, edge middle and the center is inside one of the circles
+        let mut points = bbox.clone();
+        points.extend([(53.0, 19.5), (66.0, 19.5), (59.5, 9.0), (59.5, 30.0), (59.5, 19.5)]);
+        for (lat, lon) in points {
+            assert!(centers.iter().any(|&(c_lat, c_lon)| geo::distance_nm(c_lat, c_lon, lat, lon) <= 250.0),
+                "{},{} is not covered", lat, lon);
+        }
+
+        // an area smaller than one tile doesn't need many
+        assert!(tile_centers(&parse_bbox("52,4,52.5,4.5").unwrap(), 250.0).len() <= 3);
+    }
+
+    #[test]
+    fn failed_tiles_are_not_covered() {
+        let missing = Coverage::AllBut(vec![Tile { lat: 52.0, lon: 4.0, radius: 60.0 }]);
+        assert!(!missing.covers(Some((52.5, 4.0))));
+        assert!(missing.covers(Some((54.0, 4.0))));
+        assert!(missing.covers(None));
+
+        assert!(Coverage::All.covers(Some((52.5, 4.0))));
+        assert!(!Coverage::Nothing.covers(Some((54.0, 4.0))));
+        assert!(!Coverage::Nothing.covers(None));
+    }
+}
@@ -0,0 +1,351 @@
+use std::error::Error;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::AsyncReadExt;
+use tokio::net::TcpStream;
+use crate::receiver::SharedReceiver;
+
+/// Beast frames start with this byte, inside a frame it is escaped by doubling it
+const BEAST_ESCAPE: u8 = 0x1A;
+
+/// Beast/AVR timestamps are counts of a 12 MHz clock
+const CLOCK_HZ: f64 = 12_000_000.0;
+
+/// Wire format of a dump1090/readsb raw feed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FrameFormat {
+    /// Binary, usually port 30005
+    Beast,
+    /// Text lines like `*8D4840D6202CC371C32CE0576098;`, usually port 30002
+    Avr,
+}
+
+/// One raw Mode S frame as it came off the wire.
+#[derive(Debug)]
+pub struct Frame {
+    // receiver timestamp in seconds, if the feed had one
+    pub timestamp: Option<f64>,
+    pub data: Vec<u8>,
+}
+
+/// Splits a byte stream into frames. Keeps partial frames between reads.
+pub struct FrameReader {
+    format: FrameFormat,
+    buf: Vec<u8>,
+}
+
+impl FrameReader {
+    pub fn new(format: FrameFormat) -> Self {
+        Self { format, buf: Vec::new() }
+    }
+
+    /// Adds new bytes and returns every complete frame found so far.
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<Frame> {
+        self.buf.extend_from_slice(bytes);
+        match self.format {
+            FrameFormat::Beast => self.read_beast(),
+            FrameFormat::Avr => self.read_avr(),
+        }
+    }
+
+    fn read_beast(&mut self) -> Vec<Frame> {
+        let mut frames = Vec::new();
+        let mut i = 0;
+
+        while i + 1 < self.buf.len() {
+            if self.buf[i] != BEAST_ESCAPE {
+                i += 1;
+                continue;
+            }
+
+            // Message type decides the length: '2' = short Mode S, '3' = long Mode S
+            // ('1' Mode A/C and '4' status frames are skipped)
+            let msg_len = match self.buf[i + 1] {
+                b'1' => 2,
+                b'2' => 7,
+                b'3' => 14,
+                b'4' => 14,
+                _ => {
+                    i += 1;
+                    continue;
+                }
+            };
+
+            // 6 bytes timestamp + 1 byte signal + message, all escaped
+            let needed = 6 + 1 + msg_len;
+            let mut payload = Vec::with_capacity(needed);
+            let mut j = i + 2;
+            let mut broken = false;
+
+            while payload.len() < needed && j < self.buf.len() {
+                let b = self.buf[j];
+                if b == BEAST_ESCAPE {
+                    if j + 1 >= self.buf.len() {
+                        break; // escape sequence not complete yet
+                    }
+                    if self.buf[j + 1] != BEAST_ESCAPE {
+                        broken = true; // a new frame started in the middle of this one
+                        break;
+                    }
+                    j += 2;
+                } else {
+                    j += 1;
+                }
+                payload.push(b);
+            }
+
+            if broken {
+                i = j;
+                continue;
+            }
+            if payload.len() < needed {
+                break; // wait for more bytes
+            }
+
+            if self.buf[i + 1] == b'2' || self.buf[i + 1] == b'3' {
+                let ts = payload[..6].iter().fold(0u64, |acc, &b| (acc << 8) | b as u64);
+                frames.push(Frame {
+                    timestamp: if ts == 0 { None } else { Some(ts as f64 / CLOCK_HZ) },
+                    data: payload[7..].to_vec(),
+                });
+            }
+            i = j;
+        }
+
+        self.buf.drain(..i.min(self.buf.len()));
+        frames
+    }
+
+    fn read_avr(&mut self) -> Vec<Frame> {
+        let mut frames = Vec::new();
+
+        // Only complete lines, the rest stays in the buffer
+        let Some(last_newline) = self.buf.iter().rposition(|&b| b == b'\n') else {
+            return frames;
+        };
+        let complete: Vec<u8> = self.buf.drain(..=last_newline).collect();
+
+        for line in String::from_utf8_lossy(&complete).lines() {
+            if let Some(frame) = parse_avr_line(line) {
+                frames.push(frame);
+            }
+        }
+        frames
+    }
+
+    /// Returns whatever is left in the buffer at the end of a file.
+    pub fn finish(&mut self) -> Vec<Frame> {
+        if self.format == FrameFormat::Avr && !self.buf.is_empty() {
+            self.buf.push(b'\n');
+        }
+        self.push(&[])
+    }
+}
+
+/// Parses one AVR line: `*<hex>;` or `@<12 hex timestamp><hex>;`
+fn parse_avr_line(line: &str) -> Option<Frame> {
+    let line = line.trim();
+    let body = line.strip_suffix(';').unwrap_or(line);
+
+    let (timestamp, hex) = if let Some(rest) = body.strip_prefix('@') {
+        if rest.len() < 12 {
+            return None;
+        }
+        let ts = u64::from_str_radix(&rest[..12], 16).ok()?;
+        (if ts == 0 { None } else { Some(ts as f64 / CLOCK_HZ) }, &rest[12..])
+    } else {
+        (None, body.strip_prefix('*')?)
+    };
+
+    let data = hex_to_bytes(hex)?;
+    if data.len() != 7 && data.len() != 14 {
+        return None; // Mode A/C or garbage
+    }
+    Some(Frame { timestamp, data })
+}
+
+fn hex_to_bytes(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Current time in seconds, used as frame time for live feeds.
+pub fn unix_now() -> f64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs_f64()).unwrap_or(0.0)
+}
+
+/// Keeps a TCP connection to a dump1090/readsb raw port open (reconnecting if needed)
+/// and merges every frame into the shared receiver state.
+pub fn spawn_tcp(addr: String, format: FrameFormat, state: SharedReceiver) {
+    tokio::spawn(async move {
+        loop {
+            match TcpStream::connect(&addr).await {
+                Ok(mut stream) => {
+                    let mut reader = FrameReader::new(format);
+                    let mut buf = [0u8; 4096];
+
+                    loop {
+                        match stream.read(&mut buf).await {
+                            Ok(0) => {
+                                eprintln!("Receiver Error: {} closed the connection", addr);
+                                break;
+                            }
+                            Ok(n) => {
+                                let frames = reader.push(&buf[..n]);
+                                let now = unix_now(); // receiver clocks are not synced to anything useful
+                                let mut state = state.lock().unwrap();
+                                for frame in frames {
+                                    state.apply_frame(&frame.data, now);
+                                }
+                            }
+                            Err(e) => {
+                                eprintln!("Receiver Error: {}", e);
+                                break;
+                            }
+                        }
+                    }
+                }
+                Err(e) => eprintln!("Receiver Error: could not connect to {}: {}", addr, e),
+            }
+            tokio::time::sleep(Duration::from_secs(5)).await;
+        }
+    });
+}
+
+/// Loads a recorded frame file (Beast binary or AVR text, detected from the first byte)
+/// into the receiver state.
+pub fn load_file(path: &str, state: &SharedReceiver) -> Result<(), Box<dyn Error>> {
+    let bytes = std::fs::read(path)?;
+    let format = if bytes.first() == Some(&BEAST_ESCAPE) { FrameFormat::Beast } else { FrameFormat::Avr };
+
+    let mut reader = FrameReader::new(format);
+    let mut frames = reader.push(&bytes);
+    frames.extend(reader.finish());
+
+    // Use the recorded timestamps if there are any, otherwise pretend
+    // frames came in at 100 per second so CPR pairs still line up
+    let mut clock = 0.0;
+    let mut state = state.lock().unwrap();
+    for frame in &frames {
+        clock = frame.timestamp.unwrap_or(clock + 0.01);
+        state.apply_frame(&frame.data, clock);
+    }
+
+    println!("Loaded {} frames from '{}'.", frames.len(), path);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::receiver::ReceiverState;
+
+    const POSITION_EVEN: [u8; 14] = [0x8D, 0x40, 0x62, 0x1D, 0x58, 0xC3, 0x82, 0xD6, 0x90, 0xC8, 0xAC, 0x28, 0x63, 0xA7];
+
+    // Wraps a long Mode S message into a Beast frame, escaping 0x1A like dump1090 does
+    fn beast_frame(timestamp: u64, signal: u8, msg: &[u8]) -> Vec<u8> {
+        let mut frame = vec![BEAST_ESCAPE, b'3'];
+        for b in timestamp.to_be_bytes()[2..].iter().chain([signal].iter()).chain(msg) {
+            frame.push(*b);
+            if *b == BEAST_ESCAPE {
+                frame.push(BEAST_ESCAPE);
+            }
+        }
+        frame
+    }
+
+    #[test]
+    fn reads_a_beast_frame() {
+        let mut reader = FrameReader::new(FrameFormat::Beast);
+        let frames = reader.push(&beast_frame(12_000_000, 0x40, &POSITION_EVEN));
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].data, POSITION_EVEN);
+        assert_eq!(frames[0].timestamp, Some(1.0));
+    }
+
+    #[test]
+    fn unescapes_0x1a_in_timestamp_signal_and_message() {
+        let msg: Vec<u8> = POSITION_EVEN.iter().map(|&b| if b == 0x8D { 0x1A } else { b }).collect();
+        let bytes = beast_frame(0x1A1A_0000_001A, 0x1A, &msg);
+        assert!(bytes.len() > 2 + 6 + 1 + 14);
+
+        let frames = FrameReader::new(FrameFormat::Beast).push(&bytes);
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].data, msg);
+        assert_eq!(frames[0].timestamp, Some(0x1A1A_0000_001Au64 as f64 / CLOCK_HZ));
+    }
+
+    #[test]
+    fn frame_split_across_two_reads() {
+        let bytes = beast_frame(0x1A, 0x40, &POSITION_EVEN);
+        let mut reader = FrameReader::new(FrameFormat::Beast);
+
+        // Cut right after the first half of the escaped timestamp byte
+        let cut = bytes.iter().rposition(|&b| b == BEAST_ESCAPE).unwrap();
+        assert!(reader.push(&bytes[..cut]).is_empty());
+        let frames = reader.push(&bytes[cut..]);
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].data, POSITION_EVEN);
+    }
+
+    #[test]
+    fn skips_garbage_and_mode_ac_frames() {
+        let mut bytes = vec![0x00, 0xFF, BEAST_ESCAPE, b'1', 0, 0, 0, 0, 0, 0, 0x20, 0x0A, 0x00];
+        bytes.extend(beast_frame(1, 0x40, &POSITION_EVEN));
+
+        let frames = FrameReader::new(FrameFormat::Beast).push(&bytes);
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].data, POSITION_EVEN);
+    }
+
+    #[test]
+    fn truncated_frame_is_dropped_when_the_next_one_starts() {
+        let mut bytes = beast_frame(1, 0x40, &POSITION_EVEN);
+        bytes.truncate(12);
+        bytes.extend(beast_frame(2, 0x40, &POSITION_EVEN));
+
+        let frames = FrameReader::new(FrameFormat::Beast).push(&bytes);
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].timestamp, Some(2.0 / CLOCK_HZ));
+    }
+
+    #[test]
+    fn reads_avr_lines_split_across_reads() {
+        let mut reader = FrameReader::new(FrameFormat::Avr);
+        assert!(reader.push(b"*8D40621D58C382D6").is_empty());
+        let frames = reader.push(b"90C8AC2863A7;\n@000000B71B008D40621D58C386435CC412692AD6;\n*0A00;\n");
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].data, POSITION_EVEN);
+        assert_eq!(frames[0].timestamp, None);
+        assert_eq!(frames[1].timestamp, Some(1.0));
+
+        // No newline at the end of a file
+        reader.push(b"*8D40621D58C382D690C8AC2863A7;");
+        assert_eq!(reader.finish().len(), 1);
+    }
+
+    #[test]
+    fn loads_a_recorded_beast_dump() {
+        let state = ReceiverState::shared(None);
+        load_file(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/sample.beast"), &state).unwrap();
+
+        let aircraft = state.lock().unwrap().snapshot(f64::MAX, f64::MAX);
+        let find = |icao: &str| aircraft.iter().find(|a| a.icao == icao).unwrap();
+        assert_eq!(aircraft.len(), 3);
+
+        assert_eq!(find("4840d6").callsign.as_deref(), Some("KLM1023"));
+
+        let positioned = find("40621d");
+        assert!((positioned.lat.unwrap() - 52.2572).abs() < 0.0001);
+        assert!((positioned.lon.unwrap() - 3.9194).abs() < 0.0001);
+        assert_eq!(positioned.alt_baro, Some(38000.0));
+
+        let moving = find("485020");
+        assert_eq!(moving.ground_speed, Some(159.2));
+        assert_eq!(moving.track, Some(182.9));
+    }
+}
@@ -0,0 +1,112 @@
+use std::error::Error;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use chrono::{DateTime, FixedOffset};
+use serde::{Deserialize, Serialize};
+use crate::query::Query;
+
+/// One line of a capture file (NDJSON): a raw feed response plus what we asked for.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CaptureRecord {
+    pub time: String, // RFC 3339
+    pub feed: String, // decides how `body` is parsed on replay
+    pub query: String, // v2 path like "point/52.1/4.3/100" or "type/K35R"
+    pub body: String, // untouched response, broken ones included
+}
+
+impl CaptureRecord {
+    /// A record of a response we just got, stamped with the current time.
+    pub fn new(feed: String, query: &Query, body: String) -> Self {
+        Self {
+            time: chrono::Local::now().to_rfc3339(),
+            feed,
+            query: query.path(),
+            body,
+        }
+    }
+
+    /// The query this response answered (None if the path is not one we know)
+    pub fn query(&self) -> Option<Query> {
+        Query::from_path(&self.query)
+    }
+
+    pub fn timestamp(&self) -> Option<DateTime<FixedOffset>> {
+        DateTime::parse_from_rfc3339(&self.time).ok()
+    }
+}
+
+/// Appends feed responses to a capture file.
+pub struct Recorder {
+    file: File,
+}
+
+impl Recorder {
+    pub fn open(path: &str) -> Result<Self, Box<dyn Error>> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self { file })
+    }
+
+    pub fn write(&mut self, record: &CaptureRecord) -> Result<(), Box<dyn Error>> {
+        let line = serde_json::to_string(record)?;
+        writeln!(self.file, "{}", line)?;
+        self.file.flush()?;
+        Ok(())
+    }
+}
+
+/// Reads a capture file. Broken lines are skipped (e.g., the last one after a crash).
+pub fn load(path: &str) -> Result<Vec<CaptureRecord>, Box<dyn Error>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut records = Vec::new();
+
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str::<CaptureRecord>(&line) {
+            Ok(record) if record.query().is_none() => eprintln!("Skipping capture line with unknown query '{}'", record.query),
+            Ok(record) => records.push(record),
+            Err(e) => eprintln!("Skipping broken capture line: {}", e),
+        }
+    }
+
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn record(time: &str, body: &str) -> CaptureRecord {
+        CaptureRecord { time: time.to_string(), feed: "airplanes-live".to_string(), query: "point/52/4/50".to_string(), body: body.to_string() }
+    }
+
+    #[test]
+    fn written_records_load_back_and_broken_lines_are_skipped() {
+        let path = std::env::temp_dir().join(format!("capture_round_trip_{}.ndjson", std::process::id()));
+        let path = path.to_str().unwrap();
+        let _ = fs::remove_file(path);
+
+        let mut recorder = Recorder::open(path).unwrap();
+        recorder.write(&record("2026-01-01T12:00:00Z", r#"{"ac":[]}"#)).unwrap();
+        recorder.write(&record("2026-01-01T12:00:10Z", "<html>502</html>")).unwrap();
+        drop(recorder);
+
+        // a crash mid-write leaves a cut off line, plus an empty one
+        let mut file = OpenOptions::new().append(true).open(path).unwrap();
+        write!(file, "\n{{\"time\":\"2026-01-01T12:00:20Z\",\"feed\":").unwrap();
+        drop(file);
+
+        let records = load(path).unwrap();
+        fs::remove_file(path).unwrap();
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].body, r#"{"ac":[]}"#);
+        assert_eq!(records[1].body, "<html>502</html>");
+        assert_eq!((records[1].feed.as_str(), records[1].query.as_str()), ("airplanes-live", "point/52/4/50"));
+        let gap = records[1].timestamp().unwrap() - records[0].timestamp().unwrap();
+        assert_eq!(gap.num_seconds(), 10);
+    }
+}
@@ -17,6 +17,9 @@ pub struct AircraftInfo {
 
     #[serde(rename = "operator")]
     pub operator: Option<String>,
+
+    #[serde(rename = "registration", default)]
+    pub registration: Option<String>,
 }
 
 pub type AircraftDB = HashMap<String, AircraftInfo>;
@@ -34,13 +37,11 @@ pub fn load_database() -> Result<AircraftDB, Box<dyn Error>> {
     let mut db = HashMap::new();
 
     // We iterate over every row.
-    for result in rdr.deserialize() {
-        // ignoring broken lines (happens for csv sometimes)
-        if let Ok(record) = result {
-            let info: AircraftInfo = record;
-            // use ICAO Code as key for fast finding:
-            db.insert(info.icao.clone(), info);
-        }
+    // ignoring broken lines (happens for csv sometimes)
+    for record in rdr.deserialize().flatten() {
+        let info: AircraftInfo = record;
+        // use ICAO Code as key for fast finding:
+        db.insert(info.icao.clone(), info);
     }
 
     Ok(db)
@@ -0,0 +1,143 @@
+use std::error::Error;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EventKind {
+    /// The callsign changed mid-flight
+    Callsign,
+    /// The position source changed, e.g. ADS-B to MLAT
+    Source,
+    /// The feed sends another registration than the aircraft database has
+    Registration,
+}
+
+/// Something that changed about an airframe between two scans.
+#[derive(Debug, Clone)]
+pub struct TrackEvent {
+    pub time: f64, // unix seconds
+    pub icao: String,
+    pub kind: EventKind,
+    pub from: String,
+    pub to: String,
+}
+
+impl TrackEvent {
+    /// ADS-B (or ADS-R/TIS-B) to MLAT or plain Mode S: the transponder may have stopped sending positions
+    pub fn adsb_lost(&self) -> bool {
+        let broadcast = |s: &str| s.starts_with("adsb") || s.starts_with("adsr") || s.starts_with("tisb");
+        self.kind == EventKind::Source && broadcast(&self.from) && !broadcast(&self.to)
+    }
+
+    /// With the local time in front, e.g. "10:05:12 CALLSIGN RCH123 > RCH456"
+    pub fn stamped(&self) -> String {
+        format!("{} {}", clock(self.time), self)
+    }
+}
+
+impl fmt::Display for TrackEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            EventKind::Callsign => write!(f, "CALLSIGN {} > {}", self.from, self.to),
+            EventKind::Source if self.adsb_lost() => write!(f, "SOURCE {} > {} (ADS-B off?)", self.from, self.to),
+            EventKind::Source => write!(f, "SOURCE {} > {}", self.from, self.to),
+            EventKind::Registration => write!(f, "REG MISMATCH feed {}, DB {}", self.from, self.to),
+        }
+    }
+}
+
+/// Unix seconds as local "HH:MM:SS"
+pub fn clock(time: f64) -> String {
+    chrono::DateTime::from_timestamp(time as i64, 0)
+        .map(|t| t.with_timezone(&chrono::Local).format("%H:%M:%S").to_string())
+        .unwrap_or_default()
+}
+
+/// Appends events to a text file, one per line.
+pub struct EventLog {
+    file: File,
+}
+
+impl EventLog {
+    pub fn open(path: &str) -> Result<Self, Box<dyn Error>> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self { file })
+    }
+
+    pub fn write(&mut self, event: &TrackEvent) -> Result<(), Box<dyn Error>> {
+        let stamp = chrono::DateTime::from_timestamp(event.time as i64, 0)
+            .map(|t| t.with_timezone(&chrono::Local).to_rfc3339())
+            .unwrap_or_default();
+        writeln!(self.file, "{} {} {}", stamp, event.icao, event)?;
+        self.file.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn event(kind: EventKind, from: &str, to: &str) -> TrackEvent {
+        TrackEvent { time: 1_767_268_800.0, icao: "ae1234".to_string(), kind, from: from.to_string(), to: to.to_string() }
+    }
+
+    fn source(from: &str, to: &str) -> TrackEvent {
+        event(EventKind::Source, from, to)
+    }
+
+    #[test]
+    fn adsb_lost_only_when_broadcast_stops() {
+        assert!(source("adsb_icao", "mlat").adsb_lost());
+        assert!(source("adsr_icao", "mode_s").adsb_lost());
+        assert!(source("tisb_other", "mlat").adsb_lost());
+        // back to ADS-B, or one broadcast type to another
+        assert!(!source("mlat", "adsb_icao").adsb_lost());
+        assert!(!source("adsb_icao", "adsb_other").adsb_lost());
+        assert!(!source("mode_s", "mlat").adsb_lost());
+        // only source changes count
+        assert!(!event(EventKind::Callsign, "adsb_icao", "mlat").adsb_lost());
+    }
+
+    #[test]
+    fn display_and_stamped() {
+        assert_eq!(event(EventKind::Callsign, "RCH123", "RCH456").to_string(), "CALLSIGN RCH123 > RCH456");
+        assert_eq!(source("mlat", "adsb_icao").to_string(), "SOURCE mlat > adsb_icao");
+        assert_eq!(source("adsb_icao", "mlat").to_string(), "SOURCE adsb_icao > mlat (ADS-B off?)");
+        assert_eq!(event(EventKind::Registration, "N123AB", "N456CD").to_string(), "REG MISMATCH feed N123AB, DB N456CD");
+
+        // local time, so only the shape is fixed
+        let stamped = source("adsb_icao", "mlat").stamped();
+        let (time, rest) = stamped.split_once(' ').unwrap();
+        assert_eq!(time, clock(1_767_268_800.0));
+        assert_eq!((time.len(), &time[2..3], &time[5..6]), (8, ":", ":"));
+        assert_eq!(rest, "SOURCE adsb_icao > mlat (ADS-B off?)");
+    }
+
+    #[test]
+    fn log_appends_one_line_per_event() {
+        let path = std::env::temp_dir().join(format!("events-{}.log", std::process::id()));
+        let path = path.to_str().unwrap();
+        let _ = fs::remove_file(path);
+
+        let mut log = EventLog::open(path).unwrap();
+        log.write(&event(EventKind::Callsign, "RCH123", "RCH456")).unwrap();
+        drop(log);
+        // opening it again appends
+        let mut log = EventLog::open(path).unwrap();
+        log.write(&source("adsb_icao", "mlat")).unwrap();
+        drop(log);
+
+        let text = fs::read_to_string(path).unwrap();
+        fs::remove_file(path).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 2);
+        for (line, expected) in lines.iter().zip(["ae1234 CALLSIGN RCH123 > RCH456", "ae1234 SOURCE adsb_icao > mlat (ADS-B off?)"]) {
+            let (stamp, rest) = line.split_once(' ').unwrap();
+            assert_eq!(chrono::DateTime::parse_from_rfc3339(stamp).unwrap().timestamp(), 1_767_268_800);
+            assert_eq!(rest, expected);
+        }
+    }
+}
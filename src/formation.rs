@@ -0,0 +1,223 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use crate::geo;
+use crate::lookups::Lookups;
+use crate::models::{Aircraft, Args};
+use crate::role;
+
+/// Tankers. The A330 MRTT, A400M, KC-10 and C-130 share their type code with
+/// civil or transport versions, so those only count when the aircraft is military.
+const TANKERS: [&str; 3] = ["K35R", "K35E", "K46"];
+const MIL_TANKERS: [&str; 4] = ["A332", "A400", "DC10", "C130"];
+
+/// Aircraft flying together have about the same velocity (track in degrees, ground speed in kts)
+const MAX_TRACK_DIFF: f64 = 20.0;
+const MAX_SPEED_DIFF: f64 = 40.0;
+
+/// Aircraft flying close together with the same velocity, in one scan.
+#[derive(Debug, Clone)]
+pub struct Group {
+    pub id: usize, // numbered per scan, only to tell groups apart
+    pub refueling: bool, // a tanker is part of the group
+    pub members: Vec<String>, // hex codes, the tanker first
+    pub types: Vec<String>, // type codes in the same order
+}
+
+impl fmt::Display for Group {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // "K35R + 2x F35": the tanker by name, the rest counted by type ("2x EUFI" without a tanker)
+        let rest = if self.refueling { &self.types[1..] } else { &self.types[..] };
+        let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+        for t in rest {
+            *counts.entry(t.as_str()).or_default() += 1;
+        }
+
+        let mut parts: Vec<String> = counts.into_iter()
+            .map(|(t, n)| if n == 1 { t.to_string() } else { format!("{}x {}", n, t) })
+            .collect();
+        if self.refueling {
+            parts.insert(0, self.types[0].clone());
+        }
+        let label = parts.join(" + ");
+
+        let kind = if self.refueling { "AAR" } else { "FORMATION" };
+        write!(f, "{} #{}: {}", kind, self.id, label)
+    }
+}
+
+/// Clusters the aircraft of one scan that fly within `--formation-distance` (nm) and
+/// `--formation-alt` (ft) of each other with matching track and speed.
+pub fn detect(aircraft: &[Aircraft], args: &Args, lookups: &Lookups) -> Vec<Group> {
+    // Only airborne aircraft with a position and a velocity can fly in formation
+    let flying: Vec<&Aircraft> = aircraft.iter()
+        .filter(|ac| ac.lat.is_some() && ac.lon.is_some() && ac.track.is_some() && ac.ground_speed.is_some())
+        .filter(|ac| ac.alt_baro.is_some_and(|alt| alt > 0.0))
+        .collect();
+
+    // Union-find over all close pairs
+    let mut parent: Vec<usize> = (0..flying.len()).collect();
+    for i in 0..flying.len() {
+        for j in (i + 1)..flying.len() {
+            if together(flying[i], flying[j], args) {
+                let (a, b) = (root(&mut parent, i), root(&mut parent, j));
+                parent[a] = b;
+            }
+        }
+    }
+
+    let mut clusters: HashMap<usize, Vec<&Aircraft>> = HashMap::new();
+    for (i, ac) in flying.iter().enumerate() {
+        clusters.entry(root(&mut parent, i)).or_default().push(ac);
+    }
+
+    let is_tanker = |ac: &Aircraft| {
+        let t = ac.type_code.as_deref().unwrap_or("");
+        TANKERS.contains(&t) || (MIL_TANKERS.contains(&t) && ac.military(lookups))
+    };
+
+    // Airliners on parallel approaches or the same airway look just like a formation,
+    // so one member has to be military, a tanker or a type with a military role
+    let notable = |ac: &Aircraft| is_tanker(ac) || ac.military(lookups) || role::classify(ac, lookups).is_some();
+
+    let mut groups: Vec<Vec<&Aircraft>> = clusters.into_values()
+        .filter(|c| c.len() > 1 && c.iter().any(|ac| notable(ac)))
+        .collect();
+    for members in &mut groups {
+        // Tanker first, then by hex so the label and the KML lines don't jump around
+        members.sort_by(|a, b| is_tanker(b).cmp(&is_tanker(a)).then(a.icao.cmp(&b.icao)));
+    }
+    groups.sort_by(|a, b| a[0].icao.cmp(&b[0].icao));
+
+    groups.into_iter()
+        .enumerate()
+        .map(|(i, members)| Group {
+            id: i + 1,
+            refueling: is_tanker(members[0]),
+            members: members.iter().map(|ac| ac.icao.clone()).collect(),
+            types: members.iter().map(|ac| ac.type_code.clone().unwrap_or("???".to_string())).collect(),
+        })
+        .collect()
+}
+
+// Close enough, at the same height and going the same way?
+fn together(a: &Aircraft, b: &Aircraft, args: &Args) -> bool {
+    let (Some(lat1), Some(lon1), Some(lat2), Some(lon2)) = (a.lat, a.lon, b.lat, b.lon) else {
+        return false;
+    };
+    // cheap check first, most pairs are far apart
+    if (lat1 - lat2).abs() * 60.0 > args.formation_distance {
+        return false;
+    }
+
+    let vertical = (a.alt_baro.unwrap_or(0.0) - b.alt_baro.unwrap_or(0.0)).abs();
+    let track_diff = (a.track.unwrap_or(0.0) - b.track.unwrap_or(0.0) + 540.0) % 360.0 - 180.0;
+    let speed_diff = (a.ground_speed.unwrap_or(0.0) - b.ground_speed.unwrap_or(0.0)).abs();
+
+    vertical <= args.formation_alt
+        && track_diff.abs() <= MAX_TRACK_DIFF
+        && speed_diff <= MAX_SPEED_DIFF
+        && geo::distance_nm(lat1, lon1, lat2, lon2) <= args.formation_distance
+}
+
+fn root(parent: &mut [usize], mut i: usize) -> usize {
+    while parent[i] != i {
+        parent[i] = parent[parent[i]];
+        i = parent[i];
+    }
+    i
+}
+
+/// Which group each hex belongs to
+pub fn by_member(groups: &[Group]) -> HashMap<&str, &Group> {
+    groups.iter()
+        .flat_map(|g| g.members.iter().map(move |icao| (icao.as_str(), g)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    // Aircraft at 52N 4E plus `east_nm`, all at FL250 going north at 450 kts
+    fn flying(icao: &str, type_code: &str, east_nm: f64) -> Aircraft {
+        Aircraft {
+            icao: icao.to_string(),
+            type_code: Some(type_code.to_string()),
+            lat: Some(52.0),
+            lon: Some(4.0 + east_nm / 60.0 / 52.0_f64.to_radians().cos()),
+            alt_baro: Some(25000.0),
+            ground_speed: Some(450.0),
+            track: Some(0.0),
+            ..Default::default()
+        }
+    }
+
+    fn groups(aircraft: &[Aircraft]) -> Vec<Group> {
+        let args = Args::parse_from(["flight_radar_cli"]);
+        let lookups = Lookups::built_in();
+        detect(aircraft, &args, &lookups)
+    }
+
+    #[test]
+    fn tanker_comes_first_in_the_label() {
+        let found = groups(&[flying("ae0003", "F35", 1.0), flying("ae0002", "K35R", 0.5), flying("ae0001", "F35", 0.0)]);
+        assert_eq!(found.len(), 1);
+        assert!(found[0].refueling);
+        assert_eq!(found[0].members, ["ae0002", "ae0001", "ae0003"]);
+        assert_eq!(found[0].to_string(), "AAR #1: K35R + 2x F35");
+    }
+
+    #[test]
+    fn formation_without_a_tanker() {
+        let found = groups(&[flying("3f4001", "EUFI", 0.0), flying("3f4002", "EUFI", 1.0)]);
+        assert_eq!(found.len(), 1);
+        assert!(!found[0].refueling);
+        assert_eq!(found[0].to_string(), "FORMATION #1: 2x EUFI");
+    }
+
+    #[test]
+    fn pairs_chain_into_one_group() {
+        // 1.5nm apart each, the outer two 3nm (more than --formation-distance)
+        let found = groups(&[flying("ae0001", "F16", 0.0), flying("ae0002", "F16", 1.5), flying("ae0003", "F16", 3.0)]);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].members.len(), 3);
+    }
+
+    #[test]
+    fn separate_groups_are_numbered_by_hex() {
+        let found = groups(&[
+            flying("ae0010", "F16", 50.0), flying("ae0011", "F16", 51.0),
+            flying("ae0001", "F35", 0.0), flying("ae0002", "F35", 1.0),
+            flying("ae0020", "F18", 100.0),
+        ]);
+        let members: Vec<&[String]> = found.iter().map(|g| g.members.as_slice()).collect();
+        assert_eq!(members, [["ae0001", "ae0002"], ["ae0010", "ae0011"]]);
+        assert_eq!((found[0].id, found[1].id), (1, 2));
+        assert_eq!(by_member(&found)["ae0011"].id, 2);
+    }
+
+    #[test]
+    fn different_velocity_or_height_is_no_formation() {
+        let crossing = Aircraft { track: Some(90.0), ..flying("ae0002", "F35", 1.0) };
+        let faster = Aircraft { ground_speed: Some(550.0), ..flying("ae0003", "F35", -1.0) };
+        let above = Aircraft { alt_baro: Some(27000.0), ..flying("ae0004", "F35", 0.5) };
+        assert!(groups(&[flying("ae0001", "F35", 0.0), crossing, faster, above]).is_empty());
+    }
+
+    #[test]
+    fn airliners_are_no_formation() {
+        assert!(groups(&[flying("4840d6", "A320", 0.0), flying("4840d7", "B738", 1.0)]).is_empty());
+        // but one military member is enough
+        let mil = Aircraft { is_military: Some(true), ..flying("4840d8", "A320", 0.5) };
+        assert_eq!(groups(&[flying("4840d6", "A320", 0.0), mil]).len(), 1);
+        // or a type with a military role, even from a civil address block
+        assert_eq!(groups(&[flying("4840d6", "EUFI", 0.0), flying("4840d7", "EUFI", 1.0)]).len(), 1);
+    }
+
+    #[test]
+    fn aircraft_on_the_ground_dont_count() {
+        let parked = Aircraft { alt_baro: Some(0.0), ..flying("ae0002", "F35", 0.5) };
+        assert!(groups(&[flying("ae0001", "F35", 0.0), parked]).is_empty());
+    }
+}
@@ -9,7 +9,6 @@ struct NominatimResponse {
     lon: String,
 }
 
- /*
 /// Calculates the Distance between two coords in km.
 /// Uses the Haversine Formula for spherical Geometry.
 pub fn haversine_distance(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
@@ -25,7 +24,22 @@ pub fn haversine_distance(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
 
     EARTH_RADIUS_KM * c
 }
- */
+
+/// Same as `haversine_distance`, but in nautical miles (what the radius is given in).
+pub fn distance_nm(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    haversine_distance(lat1, lon1, lat2, lon2) / 1.852
+}
+
+/// Initial course from the first to the second point in degrees (0 = north, clockwise).
+pub fn bearing(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+    let d_lon = (lon2 - lon1).to_radians();
+
+    let y = d_lon.sin() * lat2.cos();
+    let x = lat1.cos() * lat2.sin() - lat1.sin() * lat2.cos() * d_lon.cos();
+
+    (y.atan2(x).to_degrees() + 360.0) % 360.0
+}
 
 
 // TODO: Make the list extendable by creating a locations.toml instead of the way it currently works. Then users can add their own locations that they want to locally save.
@@ -0,0 +1,169 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// How many of the latest requests count for the error rate
+const OUTCOME_WINDOW: usize = 20;
+/// A feed that failed this many times in a row is skipped in favour of the fallback...
+const FAILOVER_AFTER: u32 = 3;
+/// ...until it had this long to recover
+const RECHECK_AFTER: Duration = Duration::from_secs(120);
+
+/// How a feed has been doing lately (for the scan header and failover).
+#[derive(Debug, Default)]
+pub struct Health {
+    last_success: Option<Instant>,
+    last_failure: Option<Instant>,
+    latency: Option<Duration>, // of the last successful request
+    outcomes: VecDeque<bool>, // true = success
+    failures_in_row: u32,
+}
+
+impl Health {
+    pub fn record_success(&mut self, latency: Duration) {
+        self.last_success = Some(Instant::now());
+        self.latency = Some(latency);
+        self.failures_in_row = 0;
+        self.push(true);
+    }
+
+    pub fn record_failure(&mut self) {
+        self.last_failure = Some(Instant::now());
+        self.failures_in_row += 1;
+        self.push(false);
+    }
+
+    fn push(&mut self, ok: bool) {
+        if self.outcomes.len() == OUTCOME_WINDOW {
+            self.outcomes.pop_front();
+        }
+        self.outcomes.push_back(ok);
+    }
+
+    /// Share of failed requests in the window (0.0 - 1.0)
+    pub fn error_rate(&self) -> f64 {
+        if self.outcomes.is_empty() {
+            return 0.0;
+        }
+        self.outcomes.iter().filter(|ok| !**ok).count() as f64 / self.outcomes.len() as f64
+    }
+
+    /// Keeps failing and hasn't had its break yet
+    pub fn is_down(&self) -> bool {
+        self.is_down_at(Instant::now())
+    }
+
+    fn is_down_at(&self, now: Instant) -> bool {
+        self.failures_in_row >= FAILOVER_AFTER
+            && self.last_failure.map(|t| now.saturating_duration_since(t) < RECHECK_AFTER).unwrap_or(false)
+    }
+}
+
+impl fmt::Display for Health {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.outcomes.is_empty() {
+            return write!(f, "standby");
+        }
+
+        if self.is_down() {
+            write!(f, "DOWN, ")?;
+        }
+        match self.last_success {
+            Some(t) => write!(f, "ok {}s ago", t.elapsed().as_secs())?,
+            None => write!(f, "no success yet")?,
+        }
+        if let Some(latency) = self.latency {
+            write!(f, ", {}ms", latency.as_millis())?;
+        }
+        write!(f, ", {:.0}% errors", self.error_rate() * 100.0)
+    }
+}
+
+/// Requests per minute we allow ourselves against one feed (sliding window).
+#[derive(Debug)]
+pub struct Budget {
+    per_minute: usize,
+    sent: VecDeque<Instant>,
+}
+
+impl Budget {
+    pub fn new(per_minute: usize) -> Self {
+        Self { per_minute: per_minute.max(1), sent: VecDeque::new() }
+    }
+
+    /// Takes a slot for a request now, or says how long to wait for the next free one.
+    pub fn reserve(&mut self) -> Option<Duration> {
+        self.reserve_at(Instant::now())
+    }
+
+    fn reserve_at(&mut self, now: Instant) -> Option<Duration> {
+        let minute = Duration::from_secs(60);
+        while self.sent.front().map(|t| now.saturating_duration_since(*t) >= minute).unwrap_or(false) {
+            self.sent.pop_front();
+        }
+
+        if self.sent.len() < self.per_minute {
+            self.sent.push_back(now);
+            None
+        } else {
+            Some(minute - now.saturating_duration_since(self.sent[0]))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn down_after_three_failures_in_a_row() {
+        let mut health = Health::default();
+        health.record_failure();
+        health.record_failure();
+        assert!(!health.is_down());
+        health.record_failure();
+        assert!(health.is_down());
+
+        // one success and it's back
+        health.record_success(Duration::from_millis(80));
+        assert!(!health.is_down());
+        assert_eq!(health.error_rate(), 0.75);
+    }
+
+    #[test]
+    fn down_feed_is_tried_again_after_a_break() {
+        let mut health = Health::default();
+        for _ in 0..FAILOVER_AFTER {
+            health.record_failure();
+        }
+        let now = Instant::now();
+        assert!(health.is_down_at(now + RECHECK_AFTER - Duration::from_secs(1)));
+        assert!(!health.is_down_at(now + RECHECK_AFTER));
+    }
+
+    #[test]
+    fn error_rate_only_counts_the_window() {
+        let mut health = Health::default();
+        for _ in 0..OUTCOME_WINDOW {
+            health.record_failure();
+        }
+        for _ in 0..OUTCOME_WINDOW / 2 {
+            health.record_success(Duration::from_millis(80));
+        }
+        assert_eq!(health.error_rate(), 0.5);
+    }
+
+    #[test]
+    fn budget_is_a_sliding_minute() {
+        let mut budget = Budget::new(2);
+        let start = Instant::now();
+        assert_eq!(budget.reserve_at(start), None);
+        assert_eq!(budget.reserve_at(start + Duration::from_secs(20)), None);
+
+        // full: wait until the first request is a minute old
+        assert_eq!(budget.reserve_at(start + Duration::from_secs(30)), Some(Duration::from_secs(30)));
+        assert_eq!(budget.reserve_at(start + Duration::from_secs(60)), None);
+        // the one from 20s is still in the window, the new one too
+        assert_eq!(budget.reserve_at(start + Duration::from_secs(61)), Some(Duration::from_secs(19)));
+    }
+}
@@ -1,26 +1,60 @@
+use std::collections::BTreeMap;
 use std::error::Error;
-use std::fmt::format;
 use std::fs::File;
 use std::io::Write;
-use crate::models::DefenseDisplay;
+use crate::models::{DefenseDisplay, Severity};
+use crate::reason::{self, Finding};
 
 fn get_header() -> &'static str {
     r#"<?xml version="1.0" encoding="UTF-8"?>
     <kml xmlns="http://www.opengis.net/kml/2.2">
     <Document>
         <name>Flight Radar Intelligence</name>
-        <Style id="style_mil">
+        <Style id="style_emerg">
+            <IconStyle>
+                <color>ff00a5ff</color> <scale>1.6</scale>
+                <Icon><href>http://maps.google.com/mapfiles/kml/shapes/caution.png</href></Icon>
+            </IconStyle>
+            <LabelStyle>
+                <color>ff00a5ff</color> <scale>1.2</scale>
+            </LabelStyle>
+        </Style>
+        <Style id="style_high">
             <IconStyle>
                 <color>ff0000ff</color> <scale>1.2</scale>
                 <Icon><href>http://maps.google.com/mapfiles/kml/shapes/airports.png</href></Icon>
             </IconStyle>
         </Style>
-        <Style id="style_warn">
+        <Style id="style_medium">
             <IconStyle>
                 <color>ff00ffff</color> <scale>1.0</scale>
                 <Icon><href>http://maps.google.com/mapfiles/kml/shapes/caution.png</href></Icon>
             </IconStyle>
         </Style>
+        <Style id="style_dark">
+            <IconStyle>
+                <color>ff808080</color> <scale>1.2</scale>
+                <Icon><href>http://maps.google.com/mapfiles/kml/shapes/forbidden.png</href></Icon>
+            </IconStyle>
+        </Style>
+        <Style id="style_trail">
+            <LineStyle>
+                <color>a0ffffff</color> <width>2</width>
+            </LineStyle>
+        </Style>
+        <Style id="style_pattern">
+            <LineStyle>
+                <color>ff00ff00</color> <width>2</width>
+            </LineStyle>
+            <PolyStyle>
+                <color>3000ff00</color>
+            </PolyStyle>
+        </Style>
+        <Style id="style_group">
+            <LineStyle>
+                <color>ffff00ff</color> <width>3</width>
+            </LineStyle>
+        </Style>
         <Style id="style_norm">
             <IconStyle>
                 <color>ffffffff</color> <scale>0.8</scale>
@@ -36,19 +70,37 @@ pub fn save_kml(filename: &str, anomalies: &Vec<DefenseDisplay>) -> Result<(), B
 
     for plane in anomalies {
         // Logic for color/style
-        let style = if plane.reason.contains("HVT") || plane.reason.contains("MIL") || plane.reason.contains("Country") {
-            "#style_mil" // red
-        } else if plane.reason.contains("NAV") {
-            "#style_warn" // yellow
+        let went_dark = plane.reasons.iter().any(|r| matches!(r.finding, Finding::WentDark { .. }));
+        let style = if went_dark {
+            "#style_dark" // grey, last known position
+        } else if plane.severity == Severity::Emergency {
+            "#style_emerg" // orange, bigger
+        } else if plane.score >= reason::SCORE_HIGH {
+            "#style_high" // red
+        } else if plane.score >= reason::SCORE_MEDIUM {
+            "#style_medium" // yellow
         } else {
             "#style_norm" // white
         };
 
-        let description = format!(
-            "<b>Operator:</b> {}<br/><b>Type:</b> {}<br/><b>Callsign:</b> {}<br/><b>Speed:</b> {:.0} kts<br/><b>Alt:</b> {:.0} ft<br/><b>Reason:</b> {}",
-            plane.operator, plane.type_code, plane.callsign, plane.speed, plane.alt, plane.reason
+        let mut description = format!(
+            "<b>Operator:</b> {}<br/><b>Type:</b> {}<br/><b>Role:</b> {}<br/><b>Callsign:</b> {}<br/><b>Speed:</b> {:.0} kts<br/><b>Alt:</b> {:.0} ft<br/><b>Severity:</b> {}<br/><b>Threat Score:</b> {:.0}",
+            escape(&plane.operator), escape(&plane.type_code), plane.role.map(|r| r.to_string()).unwrap_or("-".to_string()), escape(&plane.callsign), plane.speed, plane.alt, plane.severity, plane.score
         );
 
+        // One line per reason, with where it comes from
+        description.push_str("<br/><b>Reasons:</b>");
+        for r in &plane.reasons {
+            description.push_str(&format!("<br/>- {} <i>({}, {}, {:.0})</i>", escape(&r.text()), r.finding.source(), r.category, r.weighted_score()));
+        }
+
+        // Everything else the feed told us, and what we know from earlier scans:
+        for name in DefenseDisplay::columns() {
+            if let Some(value) = plane.field(name) {
+                description.push_str(&format!("<br/><b>{}:</b> {}", name, escape(&value)));
+            }
+        }
+
         // Create KML Placemark
         let kml_placemark = format!(
             r#"
@@ -60,16 +112,116 @@ pub fn save_kml(filename: &str, anomalies: &Vec<DefenseDisplay>) -> Result<(), B
                     <coordinates>{},{},{}</coordinates>
                 </Point>
             </Placemark>"#,
-            plane.icao, description, style, plane.lon, plane.lat, plane.alt * 0.3048 // Alt in Metern für KML (optional)
+            escape(&if went_dark { format!("{} (last seen)", plane.icao) } else { plane.icao.clone() }),
+            description, style, plane.lon, plane.lat, plane.alt * 0.3048 // Alt in Metern für KML (optional)
         );
 
         file.write_all(kml_placemark.as_bytes())?;
+
+        // Trail of the earlier scans:
+        let trail: Vec<String> = plane.track.iter()
+            .flat_map(|t| t.trail.iter())
+            .map(|(lon, lat, alt)| format!("{},{},{}", lon, lat, alt * 0.3048))
+            .collect();
+
+        if trail.len() > 1 {
+            let kml_trail = format!(
+                r#"
+            <Placemark>
+                <name>{} trail</name>
+                <styleUrl>#style_trail</styleUrl>
+                <LineString>
+                    <altitudeMode>absolute</altitudeMode>
+                    <coordinates>{}</coordinates>
+                </LineString>
+            </Placemark>"#,
+                escape(&plane.icao), trail.join(" ")
+            );
+            file.write_all(kml_trail.as_bytes())?;
+        }
+
+        // Area of the orbit/racetrack, as a circle around its center:
+        if let Some(p) = &plane.pattern {
+            let kml_pattern = format!(
+                r#"
+            <Placemark>
+                <name>{} {}</name>
+                <description><![CDATA[<b>Center:</b> {:.4}, {:.4}<br/><b>Radius:</b> {:.1} nm<br/><b>On station:</b> {:.0} min<br/><b>Laps:</b> {:.1}]]></description>
+                <styleUrl>#style_pattern</styleUrl>
+                <Polygon>
+                    <outerBoundaryIs><LinearRing>
+                        <coordinates>{}</coordinates>
+                    </LinearRing></outerBoundaryIs>
+                </Polygon>
+            </Placemark>"#,
+                escape(&plane.icao), p, p.center.0, p.center.1, p.radius_nm, p.minutes, p.turn.abs() / 360.0, circle(p.center, p.radius_nm)
+            );
+            file.write_all(kml_pattern.as_bytes())?;
+        }
+    }
+
+    // Formations: a line from the tanker (or first aircraft) to each other member
+    let mut groups: BTreeMap<usize, Vec<&DefenseDisplay>> = BTreeMap::new();
+    for plane in anomalies {
+        if let Some(g) = &plane.group {
+            groups.entry(g.id).or_default().push(plane);
+        }
+    }
+
+    for members in groups.values() {
+        let Some(group) = members[0].group.as_ref() else {
+            continue;
+        };
+        let position = |icao: &str| members.iter()
+            .find(|p| p.icao == icao)
+            .map(|p| format!("{},{},{}", p.lon, p.lat, p.alt * 0.3048));
+
+        let Some(lead) = position(&group.members[0]) else {
+            continue;
+        };
+        let lines: Vec<String> = group.members.iter().skip(1)
+            .filter_map(|icao| position(icao))
+            .map(|other| format!("<LineString><altitudeMode>absolute</altitudeMode><coordinates>{} {}</coordinates></LineString>", lead, other))
+            .collect();
+
+        if !lines.is_empty() {
+            let kml_group = format!(
+                r#"
+            <Placemark>
+                <name>{}</name>
+                <styleUrl>#style_group</styleUrl>
+                <MultiGeometry>{}</MultiGeometry>
+            </Placemark>"#,
+                escape(&group.to_string()), lines.join("")
+            );
+            file.write_all(kml_group.as_bytes())?;
+        }
     }
 
     file.write_all(b"\n</Document>\n</kml>")?;
     Ok(())
 }
 
+// Feed text goes into the KML and the HTML of the balloons: without <, > and & it can't
+// end a CDATA section or inject markup
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+// Closed ring of "lon,lat" around a center (radius in nm), 1 nm = 1 arc minute
+fn circle(center: (f64, f64), radius_nm: f64) -> String {
+    let scale = center.0.to_radians().cos().max(0.01);
+    (0..=36)
+        .map(|i| {
+            let angle = (i as f64 * 10.0).to_radians();
+            let lat = center.0 + radius_nm / 60.0 * angle.cos();
+            let lon = center.1 + radius_nm / 60.0 * angle.sin() / scale;
+            format!("{},{}", lon, lat)
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
 /// Creates the Link File, that tells Google Earth to load intelligence.kml anew regularly
 pub fn create_network_link(filename: &str) -> Result<(), Box<dyn Error>> {
     let content = r#"<?xml version="1.0" encoding="UTF-8"?>
@@ -87,4 +239,16 @@ pub fn create_network_link(filename: &str) -> Result<(), Box<dyn Error>> {
     let mut file = File::create(filename)?;
     file.write_all(content.as_bytes())?;
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn feed_text_cant_break_out_of_the_description() {
+        assert_eq!(escape("]]><script>"), "]]&gt;&lt;script&gt;");
+        assert_eq!(escape("AT&T"), "AT&amp;T");
+        assert_eq!(escape("RCH123"), "RCH123");
+    }
+}
@@ -0,0 +1,30 @@
+// The modules behind the CLI, also used by the integration tests in tests/.
+
+pub mod geo;
+pub mod models;
+pub mod db;
+pub mod kml;
+pub mod provider;
+pub mod modes;
+pub mod receiver;
+pub mod beast;
+pub mod sbs;
+pub mod source;
+pub mod capture;
+pub mod serve;
+pub mod query;
+pub mod area;
+pub mod health;
+pub mod squawk;
+pub mod country;
+pub mod milhex;
+pub mod lookups;
+pub mod tracks;
+pub mod pattern;
+pub mod events;
+pub mod formation;
+pub mod rules;
+pub mod reason;
+pub mod watchlist;
+pub mod airline;
+pub mod role;
@@ -0,0 +1,60 @@
+use std::error::Error;
+use std::fs::File;
+use std::io;
+use crate::airline::AirlineTable;
+use crate::db::{self, AircraftDB};
+use crate::milhex::MilRanges;
+use crate::models::{Aircraft, Args};
+use crate::rules::RuleSet;
+use crate::squawk::SquawkTable;
+use crate::watchlist::Watchlist;
+
+/// The reference tables, detection rules and watchlists the checks use, loaded once at startup.
+pub struct Lookups {
+    pub db: AircraftDB,
+    pub squawks: SquawkTable,
+    pub mil_ranges: MilRanges,
+    pub airlines: AirlineTable,
+    pub rules: RuleSet,
+    pub watchlist: Watchlist,
+}
+
+impl Lookups {
+    pub fn load(args: &Args) -> Result<Self, Box<dyn Error>> {
+        println!("Loading Aircraft Database...");
+        let db = db::load_database()?;
+        println!("Loaded DB.");
+
+        Ok(Self {
+            db,
+            squawks: SquawkTable::load(args.squawk_table.as_deref())?,
+            mil_ranges: MilRanges::load(args.mil_ranges.as_deref())?,
+            airlines: AirlineTable::load(args.airlines.as_deref(), args.airline_overrides.as_deref())?,
+            rules: RuleSet::load(args.rules.as_deref(), &Aircraft::rule_fields())?,
+            watchlist: Watchlist::load(&args.watchlist)?,
+        })
+    }
+
+    /// No aircraft database or watchlist, only the tables and rules that ship with the tool
+    /// (nothing is read from the working directory).
+    pub fn built_in() -> Self {
+        Self {
+            db: AircraftDB::new(),
+            squawks: SquawkTable::built_in(),
+            mil_ranges: MilRanges::built_in(),
+            airlines: AirlineTable::built_in(),
+            rules: RuleSet::built_in(),
+            watchlist: Watchlist::default(),
+        }
+    }
+}
+
+/// Opens `path`, or `default` if no path was given. The default file is optional (None if it isn't there),
+/// a path from the command line has to exist.
+pub fn open_optional(path: Option<&str>, default: &str) -> io::Result<Option<File>> {
+    match File::open(path.unwrap_or(default)) {
+        Ok(file) => Ok(Some(file)),
+        Err(e) if path.is_none() && e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
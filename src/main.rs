@@ -1,13 +1,20 @@
 use std::error::Error;
+use std::io::IsTerminal;
 use clap::Parser;
-use tabled::settings::Style;
+use tabled::builder::Builder;
+use tabled::settings::object::Rows;
+use tabled::settings::{Color, Style};
+use tabled::Tabled;
 use std::{thread, time};
-use models::{Args, AirplanesLiveResponse, DefenseDisplay};
-
-mod geo;
-mod models;
-mod db;
-mod kml;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use flight_radar_cli::{area, beast, capture, events, formation, geo, kml, lookups, models, pattern, query, reason, serve, source, tracks};
+use models::{Args, Aircraft, Command, DefenseDisplay, Severity};
+use query::Query;
+use lookups::Lookups;
+use area::Coverage;
+use tracks::TrackStore;
+use events::EventLog;
+use reason::{Category, Finding, Reason};
 
 
 #[tokio::main]
@@ -15,10 +22,34 @@ async fn main() -> Result<(), Box<dyn Error>> {
     // Parse arguments:
     let mut args = Args::parse();
 
-    // load DB:
-    println!("Loading Aircraft Database...");
-    let db = db::load_database()?;
-    println!("Loaded DB.");
+    // The stand-in server doesn't need the DB or a location:
+    if let Some(Command::ServeCapture { capture, port, synthetic, center_lat, center_lon, fault, fault_every }) = &args.command {
+        return serve::run(serve::ServeConfig {
+            port: *port,
+            capture: capture.clone(),
+            synthetic: *synthetic,
+            center: (*center_lat, *center_lon),
+            fault: *fault,
+            fault_every: *fault_every,
+        }).await;
+    }
+
+    // load DB and the other lookup tables:
+    let lookups = Lookups::load(&args)?;
+
+    // If KML is active, create the Network Link
+    if args.kml {
+        println!("Creating Network Link...");
+        kml::create_network_link("radar_link.kml")?;
+        println!("DONE! Open 'radar_link.kml' in Google Earth now.");
+        println!("System is starting Live-Scan in 3 Seconds...");
+        thread::sleep(time::Duration::from_secs(3));
+    }
+
+    // Replay instead of live scanning:
+    if let Some(Command::Replay { file, playback_speed, step }) = &args.command {
+        return run_replay(file, *playback_speed, *step, &args, &lookups).await;
+    }
 
     // Resolve Location:
     if let Some(loc) = &args.location {
@@ -31,85 +62,255 @@ async fn main() -> Result<(), Box<dyn Error>> {
         args.lon = Some(lon);
     }
 
-    if args.lat.is_none() || args.lon.is_none() {
-        eprintln!("Error: Please specify --location or --lat/--lon");
-        return Ok(());
-    }
+    // What to ask for: a worldwide query from a subcommand, a whole area, or the sector around the location
+    let reference = args.lat.zip(args.lon);
+    let area = match (&args.bbox, &args.polygon) {
+        (Some(bbox), _) => Some(area::parse_bbox(bbox)?),
+        (_, Some(polygon)) => Some(area::parse_polygon(polygon)?),
+        _ => None,
+    };
 
-    let lat = args.lat.unwrap();
-    let lon = args.lon.unwrap();
+    let query = match (args.command.as_ref().and_then(Command::query), area) {
+        (Some(query), _) => query,
+        (None, Some(polygon)) => Query::Area(polygon),
+        (None, None) => {
+            let Some((lat, lon)) = reference else {
+                eprintln!("Error: Please specify --location, --lat/--lon, --bbox or --polygon");
+                return Ok(());
+            };
+            Query::Point { lat, lon, radius: args.radius }
+        }
+    };
 
-    // If KML is active, create the Network Link
-    if args.kml {
-        println!("Creating Network Link...");
-        kml::create_network_link("radar_link.kml")?;
-        println!("DONE! Open 'radar_link.kml' in Google Earth now.");
-        println!("System is starting Live-Scan in 3 Seconds...");
-        thread::sleep(time::Duration::from_secs(3));
-    }
+    // Data Source (HTTP feed or our own receivers):
+    let source = source::Source::from_args(&args, reference)?;
 
-    // HTTP Request:
-    let client = reqwest::Client::new();
-    let url = format!(
-        "https://api.airplanes.live/v2/point/{}/{}/{}",
-        lat, lon, args.radius
-    );
+    // Recording:
+    let mut recorder = match &args.record {
+        Some(path) => Some(capture::Recorder::open(path)?),
+        None => None,
+    };
+
+    // History of every aircraft across scans:
+    let mut tracks = TrackStore::new(args.track_expiry);
+    let mut event_log = match &args.event_log {
+        Some(path) => Some(EventLog::open(path)?),
+        None => None,
+    };
 
     // Endless Loop
     loop {
+        // Ask first, so the header shows how the feed did this time
+        let result = source.fetch(&query).await;
+
         // Empty Screen (ANSI Escape Code)
         print!("\x1B[2J\x1B[1;1H");
 
         println!(" --- LIVE RADAR SCAN --- ");
         println!("Time: {:?}", chrono::Local::now().format("%H:%M:%S").to_string());
-        println!("Sector: {:.4}, {:.4} | Radius: {}nm", lat, lon, args.radius);
-
-        // Send Request
-        match client.get(&url).send().await {
-            Ok(resp) => {
-                match resp.json::<AirplanesLiveResponse>().await {
-                    Ok(data) => {
-                        let aircraft_list = data.ac.unwrap_or_default();
-
-                        // Filter Anomalies
-                        let anomalies: Vec<DefenseDisplay> = aircraft_list.iter()
-                            .filter_map(|ac| {
-                                match ac.check_interest(&args) {
-                                    Some(reason) => Some(DefenseDisplay::new(ac, reason, &db)),
-                                    None => None,
-                                }
-                            })
-                            .collect();
-
-                        if anomalies.is_empty() {
-                            println!("Status: Green. No targets.");
-                            // Write empty KML to make points in Google Earth disappear
-                            if args.kml {
-                                let _ = kml::save_kml("intelligence.kml", &Vec::new());
-                            }
-                        } else {
-                            println!("ALERT: {} targets found!", anomalies.len());
-
-                            // KML Update
-                            if args.kml {
-                                if let Err(e) = kml::save_kml("intelligence.kml", &anomalies) {
-                                    eprintln!("KML Error: {}", e);
-                                }
-                            }
-
-                            // Show Table:
-                            let mut table = tabled::Table::new(anomalies);
-                            table.with(Style::modern());
-                            println!("{}", table);
-                        }
-                    },
-                    Err(e) => eprintln!("JSON Error: {}", e),
+        println!("{} | Feed: {}", query, source.label());
+        if let Some(health) = source.health() {
+            println!("Health: {}", health);
+        }
+
+        match result {
+            Ok(body) => {
+                // Record the raw response before parsing, so broken ones end up in the capture too
+                if let Some(rec) = recorder.as_mut() {
+                    let record = capture::CaptureRecord::new(source.feed_name(), &query, body.clone());
+                    if let Err(e) = rec.write(&record) {
+                        eprintln!("Record Error: {}", e);
+                    }
+                }
+
+                match source.parse(&body, &query, &lookups) {
+                    Ok(aircraft_list) => {
+                        let scan = Scan { aircraft: &aircraft_list, query: &query, coverage: source::coverage(&body, &query), now: beast::unix_now() };
+                        show_scan(scan, &args, &lookups, &mut tracks, &mut event_log)
+                    }
+                    Err(e) => eprintln!("{}", e),
                 }
             },
-            Err(e) => eprintln!("Connection Error: {}", e),
+            Err(e) => eprintln!("{}", e),
+        }
+        // Every 10 seconds, unless all feeds asked us to wait longer
+        let wait = source.paused_for().unwrap_or_default().max(time::Duration::from_secs(10));
+        println!("\nNext Scan in {} seconds...", wait.as_secs_f64().ceil());
+        tokio::time::sleep(wait).await;
+    }
+}
+
+/// Pushes a recorded capture through the same checks as a live scan.
+async fn run_replay(file: &str, playback_speed: f64, step: bool, args: &Args, lookups: &Lookups) -> Result<(), Box<dyn Error>> {
+    let records = capture::load(file)?;
+    println!("Loaded {} scans from '{}'.", records.len(), file);
+    let mut tracks = TrackStore::new(args.track_expiry);
+    let mut event_log = match &args.event_log {
+        Some(path) => Some(EventLog::open(path)?),
+        None => None,
+    };
+    // --step waits for Enter through tokio, so it doesn't block a runtime thread
+    let mut stdin = BufReader::new(tokio::io::stdin());
+
+    for (i, record) in records.iter().enumerate() {
+        print!("\x1B[2J\x1B[1;1H");
+
+        println!(" --- REPLAY {}/{} --- ", i + 1, records.len());
+        println!("Time: {}", record.time);
+        let Some(query) = record.query() else {
+            continue; // `capture::load` already skips these
+        };
+        println!("{} | Feed: {}", query, record.feed);
+
+        match source::parse_body(&record.feed, &record.body, &query, lookups) {
+            Ok(aircraft_list) => {
+                // the history runs on recorded time, so it looks the same at any playback speed
+                let now = record.timestamp().map(|t| t.timestamp_millis() as f64 / 1000.0).unwrap_or_else(beast::unix_now);
+                let scan = Scan { aircraft: &aircraft_list, query: &query, coverage: source::coverage(&record.body, &query), now };
+                show_scan(scan, args, lookups, &mut tracks, &mut event_log)
+            }
+            Err(e) => eprintln!("{}", e),
         }
-        println!("\nNext Scan in 10 seconds...");
-        thread::sleep(time::Duration::from_secs(10));
+
+        let Some(next) = records.get(i + 1) else {
+            break;
+        };
+
+        if step {
+            println!("\nPress Enter for the next scan...");
+            let mut line = String::new();
+            stdin.read_line(&mut line).await?;
+        } else if playback_speed > 0.0 {
+            // Wait as long as the recording did (scaled by the playback speed)
+            if let (Some(now), Some(then)) = (record.timestamp(), next.timestamp()) {
+                let gap = (then - now).num_milliseconds().max(0) as f64 / 1000.0 / playback_speed;
+                tokio::time::sleep(time::Duration::from_secs_f64(gap)).await;
+            }
+        }
+    }
+
+    println!("\nReplay finished.");
+    Ok(())
+}
+
+/// One parsed answer, and what part of the sector it covered.
+struct Scan<'a> {
+    aircraft: &'a [Aircraft],
+    query: &'a Query,
+    coverage: Coverage,
+    now: f64,
+}
+
+/// Runs the anomaly checks on one scan and shows the results (table + KML).
+fn show_scan(scan: Scan, args: &Args, lookups: &Lookups, tracks: &mut TrackStore, event_log: &mut Option<EventLog>) {
+    let Scan { aircraft: aircraft_list, query, coverage, now } = scan;
+
+    // Aircraft of interest that vanished well inside the sector went dark
+    // (not those where the scan didn't look this time)
+    let gone = tracks.update(aircraft_list, now, &coverage);
+    tracks.check_dark(gone, query);
+
+    // Registrations that don't match the aircraft database
+    for ac in aircraft_list {
+        if let Some(registration) = lookups.db.get(&ac.icao).and_then(|info| info.registration.as_deref()) {
+            tracks.check_registration(ac, registration, now);
+        }
+    }
+
+    // Callsign, source and registration changes go to the event log as they happen
+    let events = tracks.take_events();
+    if let Some(log) = event_log.as_mut() {
+        for event in &events {
+            if let Err(e) = log.write(event) {
+                eprintln!("Event Log Error: {}", e);
+            }
+        }
+    }
+
+    // Formations and tankers with their receivers
+    let groups = formation::detect(aircraft_list, args, lookups);
+    let group_of = formation::by_member(&groups);
+
+    // Filter Anomalies
+    let mut anomalies: Vec<DefenseDisplay> = aircraft_list.iter()
+        .filter(|ac| ac.country_allowed(args) && ac.role_allowed(args, lookups))
+        .filter_map(|ac| {
+            let (track, group) = (tracks.get(&ac.icao), group_of.get(ac.icao.as_str()).copied());
+            let pattern = track.and_then(pattern::detect);
+            ac.check_interest(args, lookups, track, pattern, group).map(|reasons| DefenseDisplay::new(ac, reasons, lookups, track, pattern, group))
+        })
+        .collect();
+
+    for row in &anomalies {
+        tracks.flag(&row.icao);
+    }
+
+    // Still dark: shown at their last known position
+    for dark in tracks.dark().filter(|d| d.track.last.country_allowed(args) && d.track.last.role_allowed(args, lookups)) {
+        let finding = Finding::WentDark { secs: now - dark.track.last_seen, inside_nm: dark.inside_nm };
+        let reason = Reason::new(finding, Category::Dark, Severity::Warning);
+        anomalies.push(DefenseDisplay::new(&dark.track.last, vec![reason], lookups, Some(&dark.track), pattern::detect(&dark.track), None));
+    }
+
+    // Biggest threat first
+    anomalies.sort_by(|a, b| b.score.total_cmp(&a.score));
+
+    if anomalies.is_empty() {
+        println!("Status: Green. No targets.");
+        // Write empty KML to make points in Google Earth disappear
+        if args.kml {
+            let _ = kml::save_kml("intelligence.kml", &Vec::new());
+        }
+    } else {
+        println!("ALERT: {} targets found!", anomalies.len());
+
+        // KML Update
+        if args.kml && let Err(e) = kml::save_kml("intelligence.kml", &anomalies) {
+            eprintln!("KML Error: {}", e);
+        }
+
+        // Show Table (with the watchlist notes, if there are any):
+        let mut columns = args.columns.clone();
+        if !columns.iter().any(|c| c == "notes") && anomalies.iter().any(|a| a.field("notes").is_some()) {
+            columns.push("notes".to_string());
+        }
+        let mut table = build_table(&anomalies, &columns);
+        table.with(Style::modern());
+        color_rows(&mut table, &anomalies);
+        println!("{}", table);
     }
 }
 
+/// Red rows for high threat scores, yellow for medium (not when piped or with NO_COLOR set).
+fn color_rows(table: &mut tabled::Table, anomalies: &[DefenseDisplay]) {
+    if !std::io::stdout().is_terminal() || std::env::var_os("NO_COLOR").is_some() {
+        return;
+    }
+    for (i, row) in anomalies.iter().enumerate() {
+        let color = if row.score >= reason::SCORE_HIGH {
+            Color::FG_RED
+        } else if row.score >= reason::SCORE_MEDIUM {
+            Color::FG_YELLOW
+        } else {
+            continue;
+        };
+        table.modify(Rows::one(i + 1), color); // row 0 is the header
+    }
+}
+
+/// The usual columns plus the extra feed fields picked with --columns.
+fn build_table(anomalies: &[DefenseDisplay], columns: &[String]) -> tabled::Table {
+    let mut builder = Builder::default();
+
+    let mut header: Vec<String> = DefenseDisplay::headers().into_iter().map(|h| h.to_string()).collect();
+    header.extend(columns.iter().cloned());
+    builder.push_record(header);
+
+    for row in anomalies {
+        let mut fields: Vec<String> = row.fields().into_iter().map(|f| f.to_string()).collect();
+        fields.extend(columns.iter().map(|c| row.field(c).unwrap_or("-".to_string())));
+        builder.push_record(fields);
+    }
+
+    builder.build()
+}
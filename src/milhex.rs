@@ -0,0 +1,162 @@
+use std::error::Error;
+use crate::lookups;
+
+/// Optional file with more ranges: start,end,label (hex without 0x)
+pub const DEFAULT_TABLE: &str = "mil_ranges.csv";
+
+// Address blocks that states keep for their military (as used by readsb/tar1090).
+// Many military transponders don't set any flag, so this catches them on Beast/SBS input too.
+const BUILT_IN: [(u32, u32, &str); 39] = [
+    (0xADF7C8, 0xAFFFFF, "US military"),
+    (0x010070, 0x01008F, "Egypt military"),
+    (0x0A4000, 0x0A4FFF, "Algeria military"),
+    (0x33FF00, 0x33FFFF, "Italy military"),
+    (0x350000, 0x37FFFF, "Spain military"),
+    (0x3AA000, 0x3AFFFF, "France military"),
+    (0x3B7000, 0x3BFFFF, "France military"),
+    (0x3EA000, 0x3EBFFF, "Germany military"),
+    (0x3F4000, 0x3FBFFF, "Germany military"),
+    (0x400000, 0x40003F, "UK military"),
+    (0x43C000, 0x43CFFF, "UK military"),
+    (0x444000, 0x446FFF, "Austria military"),
+    (0x44F000, 0x44FFFF, "Belgium military"),
+    (0x457000, 0x457FFF, "Bulgaria military"),
+    (0x45F400, 0x45F4FF, "Denmark military"),
+    (0x468000, 0x4683FF, "Greece military"),
+    (0x473C00, 0x473C0F, "Hungary military"),
+    (0x478100, 0x4781FF, "Norway military"),
+    (0x480000, 0x480FFF, "Netherlands military"),
+    (0x48D800, 0x48D87F, "Poland military"),
+    (0x497C00, 0x497CFF, "Portugal military"),
+    (0x498420, 0x49842F, "Czechia military"),
+    (0x4B7000, 0x4B7FFF, "Switzerland military"),
+    (0x4B8200, 0x4B82FF, "Turkey military"),
+    (0x506F00, 0x506FFF, "Slovenia military"),
+    (0x70C070, 0x70C07F, "Oman military"),
+    (0x710258, 0x71028F, "Saudi Arabia military"),
+    (0x710380, 0x71039F, "Saudi Arabia military"),
+    (0x738A00, 0x738AFF, "Israel military"),
+    (0x7C822E, 0x7C84FF, "Australia military"),
+    (0x7C8800, 0x7C88FF, "Australia military"),
+    (0x7C9000, 0x7CBFFF, "Australia military"),
+    (0x7CF800, 0x7CFAFF, "Australia military"),
+    (0x7D0000, 0x7FFFFF, "Australia military"),
+    (0x800200, 0x8002FF, "India military"),
+    (0xC0CDF9, 0xC3FFFF, "Canada military"),
+    (0xC87F00, 0xC87FFF, "New Zealand military"),
+    (0xE40000, 0xE41FFF, "Brazil military"),
+    (0xE80600, 0xE806FF, "Chile military"),
+];
+
+/// One military address block.
+#[derive(Debug, Clone)]
+pub struct MilRange {
+    pub start: u32,
+    pub end: u32,
+    pub label: String,
+}
+
+impl MilRange {
+    /// "UK military 43C000-43CFFF", so the reason says which rule matched
+    pub fn describe(&self) -> String {
+        format!("{} {:06X}-{:06X}", self.label, self.start, self.end)
+    }
+}
+
+/// Known military ranges: the user's file first, then the built-in ones.
+pub struct MilRanges {
+    ranges: Vec<MilRange>,
+}
+
+impl MilRanges {
+    /// Ranges from --mil-ranges (or mil_ranges.csv) are checked before the built-in blocks,
+    /// so a narrower user range gets its own label.
+    pub fn load(path: Option<&str>) -> Result<Self, Box<dyn Error>> {
+        let mut ranges = Vec::new();
+
+        let file_path = path.unwrap_or(DEFAULT_TABLE);
+        let file = lookups::open_optional(path, DEFAULT_TABLE).map_err(|e| format!("Military ranges '{}': {}", file_path, e))?;
+        if let Some(file) = file {
+            let mut rdr = csv::ReaderBuilder::new().flexible(true).from_reader(file);
+            for record in rdr.records() {
+                let record = match record {
+                    Ok(r) => r,
+                    Err(e) => {
+                        eprintln!("Skipping broken range line: {}", e);
+                        continue;
+                    }
+                };
+                let hex = |i: usize| record.get(i).and_then(|s| u32::from_str_radix(s.trim().trim_start_matches("0x"), 16).ok());
+                let (Some(start), Some(end)) = (hex(0), hex(1)) else {
+                    eprintln!("Skipping range line without start/end: {:?}", record);
+                    continue;
+                };
+                let label = record.get(2).map(|s| s.trim()).filter(|s| !s.is_empty()).unwrap_or("user range");
+                ranges.push(MilRange { start, end, label: label.to_string() });
+            }
+            println!("Loaded {} military hex ranges from '{}'.", ranges.len(), file_path);
+        }
+
+        ranges.extend(Self::built_in().ranges);
+        Ok(Self { ranges })
+    }
+
+    /// Only the ranges that ship with the tool
+    pub fn built_in() -> Self {
+        let ranges = BUILT_IN.iter().map(|(start, end, label)| MilRange {
+            start: *start,
+            end: *end,
+            label: label.to_string(),
+        }).collect();
+        Self { ranges }
+    }
+
+    /// The first range the hex falls into
+    pub fn lookup(&self, icao: &str) -> Option<&MilRange> {
+        let address = u32::from_str_radix(icao.trim(), 16).ok()?; // '~' addresses never match
+        self.ranges.iter().find(|r| (r.start..=r.end).contains(&address))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn label<'a>(ranges: &'a MilRanges, icao: &str) -> Option<&'a str> {
+        ranges.lookup(icao).map(|r| r.label.as_str())
+    }
+
+    #[test]
+    fn built_in_ranges_include_both_ends() {
+        let ranges = MilRanges::built_in();
+        assert_eq!(label(&ranges, "43c000"), Some("UK military"));
+        assert_eq!(label(&ranges, "43CFFF"), Some("UK military"));
+        assert_eq!(label(&ranges, "43d000"), None);
+        assert_eq!(ranges.lookup("ae1234").map(|r| r.describe()).as_deref(), Some("US military ADF7C8-AFFFFF"));
+        assert_eq!(label(&ranges, "3c6586"), None); // Lufthansa
+    }
+
+    #[test]
+    fn user_ranges_win_and_take_0x_values() {
+        let path = std::env::temp_dir().join(format!("mil-ranges-{}.csv", std::process::id()));
+        fs::write(&path, "start,end,label\n0x43C100,0x43C1FF,RAF trials\nADF800,adf8ff,\nnot,hex,broken\n").unwrap();
+        let ranges = MilRanges::load(path.to_str()).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let own = ranges.lookup("43c180").unwrap();
+        assert_eq!(own.describe(), "RAF trials 43C100-43C1FF");
+        // an empty label gets a default, the built-in block around it still counts
+        assert_eq!(label(&ranges, "adf880"), Some("user range"));
+        assert_eq!(label(&ranges, "43c200"), Some("UK military"));
+        assert_eq!(label(&ranges, "ae0000"), Some("US military"));
+    }
+
+    #[test]
+    fn non_icao_addresses_never_match() {
+        let ranges = MilRanges::built_in();
+        assert_eq!(label(&ranges, "~ae1234"), None);
+        assert_eq!(label(&ranges, "~43c000"), None);
+        assert_eq!(label(&ranges, ""), None);
+    }
+}
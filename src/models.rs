@@ -1,15 +1,28 @@
 use serde::{Deserialize, Serialize, Deserializer};
 use serde_json::Value;
-use clap::Parser;
+use clap::{Parser, Subcommand, ValueEnum};
 use tabled::Tabled;
-use crate::db::AircraftDB;
+use crate::area::Tile;
+use crate::country;
+use crate::formation::Group;
+use crate::events::{self, EventKind, TrackEvent};
+use crate::airline::Airline;
+use crate::role::{self, Role};
+use crate::provider::ProviderKind;
+use crate::query::Query;
+use crate::serve::Fault;
+use crate::lookups::Lookups;
+use crate::reason::{self, Reason};
+use crate::rules::{self, Facts};
+use crate::squawk;
+use crate::pattern::Pattern;
+use crate::tracks::{self, Track, TrackSummary};
 
 /// A simple CLI tool to scan OpenSky Data for Anomalies.
 #[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, subcommand_negates_reqs = true)]
 #[command(group(
     clap::ArgGroup::new("coords")
-        .required(true)
         .args(&["location", "lat"])
 ))]
 pub struct Args {
@@ -37,6 +50,18 @@ pub struct Args {
     #[arg(long, default_value_t = 1000.0)]
     pub spoof_delta: f64,
 
+    /// Fastest believable speed between two positions in kts (faster moves count as a position jump)
+    #[arg(long, default_value_t = 1000.0)]
+    pub jump_speed: f64,
+
+    /// Aircraft closer than this (nm) with the same altitude, track and speed count as a formation
+    #[arg(long, default_value_t = 2.0)]
+    pub formation_distance: f64,
+
+    /// Largest altitude difference (ft) within a formation
+    #[arg(long, default_value_t = 500.0)]
+    pub formation_alt: f64,
+
     /// Radius around the target in nautical miles
     #[arg(short, long, default_value_t = 250.0)]
     pub radius: f64,
@@ -46,17 +71,279 @@ pub struct Args {
     pub min_alt: Option<f64>,
 
     /// Maximum height in meters (for example, to find low flights)
+    /// Above it only emergencies and watchlist hits are shown
     #[arg(long)]
     pub max_alt: Option<f64>,
 
     /// KML Creation for better reading of results
     #[arg(long)]
     pub kml: bool,
+
+    /// Live feed to poll
+    #[arg(long, value_enum, default_value_t = ProviderKind::AirplanesLive)]
+    pub provider: ProviderKind,
+
+    /// Base URL of the feed (e.g., a self-hosted mirror), defaults to the provider's public API
+    #[arg(long)]
+    pub base_url: Option<String>,
+
+    /// API key for feeds that need one (ADSBExchange, OpenSky)
+    #[arg(long)]
+    pub api_key: Option<String>,
+
+    /// Second feed to switch to when the main one keeps failing
+    #[arg(long, value_enum)]
+    pub fallback_provider: Option<ProviderKind>,
+
+    /// Base URL of the fallback feed
+    #[arg(long)]
+    pub fallback_base_url: Option<String>,
+
+    /// API key of the fallback feed
+    #[arg(long)]
+    pub fallback_api_key: Option<String>,
+
+    /// Max requests per minute to each feed (retries included)
+    #[arg(long, default_value_t = 60)]
+    pub budget: usize,
+
+    /// Read raw Mode S from a dump1090/readsb Beast port instead of an API (e.g., "127.0.0.1:30005")
+    #[arg(long, group = "input")]
+    pub beast: Option<String>,
+
+    /// Read raw Mode S from a dump1090/readsb AVR text port (e.g., "127.0.0.1:30002")
+    #[arg(long, group = "input")]
+    pub avr: Option<String>,
+
+    /// Read recorded Mode S frames from a file (Beast binary or AVR text)
+    #[arg(long, group = "input")]
+    pub frames_file: Option<String>,
+
+    /// Read a SBS-1 BaseStation feed (e.g., "127.0.0.1:30003" or a Virtual Radar Server)
+    #[arg(long, group = "input")]
+    pub sbs: Option<String>,
+
+    /// Read a recorded BaseStation (.sbs) file
+    #[arg(long, group = "input")]
+    pub sbs_file: Option<String>,
+
+    /// Poll a tar1090/readsb aircraft.json from a local path or URL (e.g., "/run/readsb/aircraft.json")
+    #[arg(long, group = "input")]
+    pub aircraft_json: Option<String>,
+
+    /// Only show aircraft registered in these countries, comma separated (e.g., "Russia,Belarus")
+    #[arg(long, value_delimiter = ',')]
+    pub country: Vec<String>,
+
+    /// Hide aircraft registered in these countries, comma separated
+    #[arg(long, value_delimiter = ',')]
+    pub exclude_country: Vec<String>,
+
+    /// Only show aircraft with these probable roles, comma separated
+    #[arg(long, value_enum, value_delimiter = ',')]
+    pub role: Vec<Role>,
+
+    /// Military from these countries is reported as foreign military in the sector
+    #[arg(long, value_delimiter = ',', default_value = "Russia,Belarus,China,Iran,North Korea")]
+    pub countries_of_interest: Vec<String>,
+
+    /// Detection rules (TOML), "rules.toml" is picked up by default. Replaces the built-in rules
+    #[arg(long)]
+    pub rules: Option<String>,
+
+    /// ICAO airline designator table (designator,telephony,name,country,military), "airlines.csv" is picked up by default
+    #[arg(long)]
+    pub airlines: Option<String>,
+
+    /// Our own designators and callsign patterns (same columns), win over the table. "airlines_local.csv" is picked up by default
+    #[arg(long)]
+    pub airline_overrides: Option<String>,
+
+    /// Watchlist of hex codes, registrations or callsigns to follow (CSV or TOML, can be repeated).
+    /// "watchlist.csv" and "watchlist.toml" are picked up by default
+    #[arg(long)]
+    pub watchlist: Vec<String>,
+
+    /// CSV with more military hex ranges (start,end,label), "mil_ranges.csv" is picked up by default
+    #[arg(long)]
+    pub mil_ranges: Option<String>,
+
+    /// CSV with more notable squawk codes (code,label,severity), "squawks.csv" is picked up by default
+    #[arg(long)]
+    pub squawk_table: Option<String>,
+
+    /// Extra table columns from the feed, comma separated (e.g., "squawk,mach,ias,nic,rssi")
+    #[arg(long, value_delimiter = ',', value_parser = clap::builder::PossibleValuesParser::new(DefenseDisplay::columns()))]
+    pub columns: Vec<String>,
+
+    /// Forget the track history of aircraft not seen for this many seconds
+    #[arg(long, default_value_t = 120.0)]
+    pub track_expiry: f64,
+
+    /// Scan a whole bounding box instead of one circle: "min_lat,min_lon,max_lat,max_lon"
+    #[arg(long, group = "area")]
+    pub bbox: Option<String>,
+
+    /// Scan a polygon instead of one circle: "lat,lon;lat,lon;..." (e.g., the Baltic Sea)
+    #[arg(long, group = "area")]
+    pub polygon: Option<String>,
+
+    /// Radius of each tile of a --bbox/--polygon scan in nautical miles (the API caps at 250)
+    #[arg(long, default_value_t = 250.0)]
+    pub tile_radius: f64,
+
+    /// How many tiles are requested at the same time
+    #[arg(long, default_value_t = 4)]
+    pub concurrency: usize,
+
+    /// Max tile requests per second (airplanes.live allows 1)
+    #[arg(long, default_value_t = 1.0)]
+    pub rate: f64,
+
+    /// Append every raw feed response to this capture file (NDJSON) for later replay
+    #[arg(long)]
+    pub record: Option<String>,
+
+    /// Append callsign, source and registration changes to this file
+    #[arg(long)]
+    pub event_log: Option<String>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Watch all military traffic worldwide (no location needed)
+    Mil,
+
+    /// Follow specific airframes by hex code (comma separated)
+    Hex {
+        hex: String,
+    },
+
+    /// Follow a callsign worldwide
+    Callsign {
+        callsign: String,
+    },
+
+    /// Follow a registration worldwide
+    Reg {
+        registration: String,
+    },
+
+    /// Watch one aircraft type worldwide (e.g., "K35R")
+    Type {
+        #[arg(value_name = "TYPE")]
+        type_code: String,
+    },
+
+    /// Watch one squawk code worldwide (e.g., "7700")
+    Squawk {
+        code: String,
+    },
+
+    /// Replay a capture recorded with --record through the normal checks, table and KML
+    Replay {
+        /// Capture file (NDJSON)
+        file: String,
+
+        /// Playback speed (1 = real time, 10 = ten times faster, 0 = no waiting)
+        #[arg(long, default_value_t = 1.0)]
+        playback_speed: f64,
+
+        /// Wait for Enter after each scan instead of using the recorded timing
+        #[arg(long)]
+        step: bool,
+    },
+
+    /// Serve recorded or synthetic traffic like the airplanes.live v2 API (point it at it with --base-url)
+    ServeCapture {
+        /// Capture file (NDJSON) to serve, one recorded scan per request. Without it, synthetic traffic is served
+        #[arg(long)]
+        capture: Option<String>,
+
+        /// Port to listen on (127.0.0.1 only)
+        #[arg(long, default_value_t = 8080)]
+        port: u16,
+
+        /// Number of synthetic aircraft
+        #[arg(long, default_value_t = 50)]
+        synthetic: usize,
+
+        /// Center latitude of the synthetic traffic
+        #[arg(long, default_value_t = 52.0)]
+        center_lat: f64,
+
+        /// Center longitude of the synthetic traffic
+        #[arg(long, default_value_t = 4.0)]
+        center_lon: f64,
+
+        /// Error to inject, to test how the scan loop handles it
+        #[arg(long, value_enum, default_value_t = Fault::None)]
+        fault: Fault,
+
+        /// Only inject the fault on every n-th request
+        #[arg(long, default_value_t = 1)]
+        fault_every: u64,
+    },
+}
+
+// Numbers sometimes come as strings ("12.5"), anything unreadable turns into None
+fn parse_number<'de, D>(deserializer: D) -> Result<Option<f64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Value::deserialize(deserializer)? {
+        Value::Number(n) => Ok(n.as_f64()),
+        Value::String(s) => Ok(s.trim().parse::<f64>().ok()),
+        _ => Ok(None),
+    }
+}
+
+// Text that may also come as a number (e.g., squawk 7700), empty strings turn into None
+fn parse_text<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Value::deserialize(deserializer)? {
+        Value::String(s) if !s.trim().is_empty() => Ok(Some(s)),
+        Value::Number(n) => Ok(Some(n.to_string())),
+        _ => Ok(None),
+    }
+}
+
+// readsb sends flags as 0/1, other feeds as true/false
+fn parse_flag<'de, D>(deserializer: D) -> Result<Option<bool>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Value::deserialize(deserializer)? {
+        Value::Bool(b) => Ok(Some(b)),
+        Value::Number(n) => Ok(n.as_f64().map(|v| v != 0.0)),
+        _ => Ok(None),
+    }
+}
+
+// A list of strings (nav_modes), a single string counts as a list with one entry
+fn parse_list<'de, D>(deserializer: D) -> Result<Option<Vec<String>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Value::deserialize(deserializer)? {
+        Value::Array(items) => Ok(Some(items.iter().filter_map(|v| v.as_str().map(|s| s.to_string())).collect())),
+        Value::String(s) => Ok(Some(vec![s])),
+        _ => Ok(None),
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
 pub struct AirplanesLiveResponse {
+    #[serde(alias = "aircraft")]
     pub ac: Option<Vec<Aircraft>>, // Option, in case there are no planes available for some reason
+    /// Tiles of a merged area scan that failed (our own addition, see `source::fetch_area`)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub missing: Vec<Tile>,
 }
 
 // Helper function for dirty data:
@@ -74,7 +361,7 @@ where
     }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
 pub struct Aircraft {
     #[serde(rename = "hex")]
     pub icao: String,
@@ -84,30 +371,175 @@ pub struct Aircraft {
     pub type_code: Option<String>,
     #[serde(rename = "r")]
     pub registration: Option<String>,
-    #[serde(rename = "gs")]
+    #[serde(rename = "gs", deserialize_with = "parse_number", default)]
     pub ground_speed: Option<f64>, // ground speed in knots
     #[serde(rename = "alt_baro", deserialize_with = "parse_altitude", default)]
     pub alt_baro: Option<f64>, // height in feet
     #[serde(rename = "alt_geom", deserialize_with = "parse_altitude", default)]
     pub alt_geom: Option<f64>, // GPS height in feet
-    #[serde(rename = "type")]
+    #[serde(rename = "type", default)]
     pub source_type: String, // "adsb", "mlat" <-- This is the Ghost detector
 
     // may be missing:
+    #[serde(deserialize_with = "parse_number", default)]
     pub lat: Option<f64>,
+    #[serde(deserialize_with = "parse_number", default)]
     pub lon: Option<f64>,
 
-    #[serde(rename = "mil")]
+    #[serde(rename = "mil", deserialize_with = "parse_flag", default)]
     pub is_military: Option<bool>, // Airplanes.live often flags military aircrafts
+
+    // everything below is optional in the readsb format and may come in odd shapes:
+    #[serde(deserialize_with = "parse_text", default)]
+    pub squawk: Option<String>, // transponder code, e.g. "7700"
+    #[serde(deserialize_with = "parse_text", default)]
+    pub emergency: Option<String>, // "none", "general", "lifeguard", "minfuel", "nordo", "unlawful", "downed"
+    #[serde(deserialize_with = "parse_text", default)]
+    pub category: Option<String>, // emitter category, e.g. "A3"
+    #[serde(deserialize_with = "parse_text", default)]
+    pub desc: Option<String>, // long type name, e.g. "BOEING KC-135R Stratotanker"
+    #[serde(rename = "ownOp", deserialize_with = "parse_text", default)]
+    pub owner_operator: Option<String>, // registered owner/operator
+    #[serde(deserialize_with = "parse_text", default)]
+    pub year: Option<String>, // year of manufacture
+
+    // Movement:
+    #[serde(deserialize_with = "parse_number", default)]
+    pub track: Option<f64>, // true track over ground in degrees
+    #[serde(deserialize_with = "parse_number", default)]
+    pub true_heading: Option<f64>,
+    #[serde(deserialize_with = "parse_number", default)]
+    pub mag_heading: Option<f64>,
+    #[serde(deserialize_with = "parse_number", default)]
+    pub track_rate: Option<f64>, // degrees per second
+    #[serde(deserialize_with = "parse_number", default)]
+    pub roll: Option<f64>, // degrees, negative = left
+    #[serde(deserialize_with = "parse_number", default)]
+    pub baro_rate: Option<f64>, // climb/descent in ft/min (barometric)
+    #[serde(deserialize_with = "parse_number", default)]
+    pub geom_rate: Option<f64>, // climb/descent in ft/min (GNSS)
+    #[serde(deserialize_with = "parse_number", default)]
+    pub ias: Option<f64>, // indicated airspeed in knots
+    #[serde(deserialize_with = "parse_number", default)]
+    pub tas: Option<f64>, // true airspeed in knots
+    #[serde(deserialize_with = "parse_number", default)]
+    pub mach: Option<f64>,
+
+    // Weather derived from the airspeeds:
+    #[serde(deserialize_with = "parse_number", default)]
+    pub wd: Option<f64>, // wind direction
+    #[serde(deserialize_with = "parse_number", default)]
+    pub ws: Option<f64>, // wind speed in knots
+    #[serde(deserialize_with = "parse_number", default)]
+    pub oat: Option<f64>, // outer air temperature in °C
+    #[serde(deserialize_with = "parse_number", default)]
+    pub tat: Option<f64>, // total air temperature in °C
+
+    // What the crew set on the autopilot:
+    #[serde(deserialize_with = "parse_number", default)]
+    pub nav_qnh: Option<f64>, // hPa
+    #[serde(deserialize_with = "parse_number", default)]
+    pub nav_altitude_mcp: Option<f64>, // selected altitude in ft
+    #[serde(deserialize_with = "parse_number", default)]
+    pub nav_altitude_fms: Option<f64>,
+    #[serde(deserialize_with = "parse_number", default)]
+    pub nav_heading: Option<f64>,
+    #[serde(deserialize_with = "parse_list", default)]
+    pub nav_modes: Option<Vec<String>>, // e.g. ["autopilot", "vnav", "tcas"]
+
+    // Integrity and accuracy of the position (ADS-B version 2 quality indicators):
+    #[serde(deserialize_with = "parse_number", default)]
+    pub version: Option<f64>, // ADS-B version
+    #[serde(deserialize_with = "parse_number", default)]
+    pub nic: Option<f64>,
+    #[serde(deserialize_with = "parse_number", default)]
+    pub rc: Option<f64>, // radius of containment in meters
+    #[serde(deserialize_with = "parse_number", default)]
+    pub nic_baro: Option<f64>,
+    #[serde(deserialize_with = "parse_number", default)]
+    pub nac_p: Option<f64>,
+    #[serde(deserialize_with = "parse_number", default)]
+    pub nac_v: Option<f64>,
+    #[serde(deserialize_with = "parse_number", default)]
+    pub sil: Option<f64>,
+    #[serde(deserialize_with = "parse_text", default)]
+    pub sil_type: Option<String>,
+    #[serde(deserialize_with = "parse_number", default)]
+    pub gva: Option<f64>,
+    #[serde(deserialize_with = "parse_number", default)]
+    pub sda: Option<f64>,
+    #[serde(deserialize_with = "parse_flag", default)]
+    pub alert: Option<bool>, // squawk changed recently
+    #[serde(deserialize_with = "parse_flag", default)]
+    pub spi: Option<bool>, // "ident" button pressed
+
+    // Database and receiver side:
+    #[serde(rename = "dbFlags", deserialize_with = "parse_number", default)]
+    pub db_flags: Option<f64>, // bit field: 1 military, 2 interesting, 4 PIA, 8 LADD
+    #[serde(deserialize_with = "parse_number", default)]
+    pub seen: Option<f64>, // seconds since the last message
+    #[serde(deserialize_with = "parse_number", default)]
+    pub seen_pos: Option<f64>, // seconds since the last position update
+    #[serde(deserialize_with = "parse_number", default)]
+    pub rssi: Option<f64>, // signal strength in dBFS
+    #[serde(deserialize_with = "parse_number", default)]
+    pub messages: Option<f64>,
+}
+
+/// How urgent a target is, shown in the table and used for the KML style.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum Severity {
+    #[default]
+    Normal,
+    Warning,
+    Emergency,
+}
+
+impl Severity {
+    /// "emergency" or "warning" (any case), anything else is normal
+    pub fn parse(text: &str) -> Self {
+        match text.trim().to_lowercase().as_str() {
+            "emergency" => Severity::Emergency,
+            "warning" => Severity::Warning,
+            _ => Severity::Normal,
+        }
+    }
+
+    /// Lowercase name, the way `parse` reads it
+    pub fn name(&self) -> &'static str {
+        match self {
+            Severity::Normal => "normal",
+            Severity::Warning => "warning",
+            Severity::Emergency => "emergency",
+        }
+    }
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Normal => write!(f, "-"),
+            Severity::Warning => write!(f, "WARNING"),
+            Severity::Emergency => write!(f, "EMERGENCY"),
+        }
+    }
 }
 
 #[derive(Tabled)]
 pub struct DefenseDisplay {
-    pub(crate) icao: String,
+    #[tabled(rename = "Severity")]
+    pub severity: Severity,
+    #[tabled(rename = "Score", format("{:.0}", self.score))]
+    pub score: f64, // threat score, see `reason::threat_score`
+    pub icao: String,
     #[tabled(rename = "Type")]
     pub(crate) type_code: String,
+    #[tabled(rename = "Role", display = "display_role")]
+    pub role: Option<Role>, // what it is probably doing
     #[tabled(rename = "Operator")]
     pub(crate) operator: String,
+    #[tabled(rename = "Country")]
+    pub(crate) country: String,
     pub(crate) callsign: String,
     #[tabled(rename = "Speed (kt)")]
     pub(crate) speed: f64,
@@ -118,136 +550,441 @@ pub struct DefenseDisplay {
     #[tabled(rename = "Source")]
     source: String, // MLAT or ADS-B
     #[tabled(rename = "Reason")]
-    pub reason: String,
+    pub reason: String, // text of all reasons
+    #[tabled(skip)]
+    pub reasons: Vec<Reason>,
     #[tabled(skip)]
     pub lat: f64,
     #[tabled(skip)]
     pub lon: f64,
+    #[tabled(skip)]
+    pub aircraft: Aircraft, // everything the feed sent, for extra columns and KML
+    #[tabled(skip)]
+    pub track: Option<TrackSummary>, // history from earlier scans
+    #[tabled(skip)]
+    pub pattern: Option<Pattern>, // orbit/racetrack/loiter found in the track
+    #[tabled(skip)]
+    pub group: Option<Group>, // formation this aircraft flies in
+    #[tabled(skip)]
+    pub airline: Option<Airline>, // who flies under the callsign
 }
 
-impl Aircraft {
-    // Intelligence Logic:
-    pub fn check_interest(&self, args: &Args) -> Option<String> {
-        let mut reasons = Vec::new();
-        let speed = self.ground_speed.unwrap_or(0.0);
-        let alt = self.alt_baro.unwrap_or(0.0);
-        let type_code = self.type_code.clone().unwrap_or_default();
-
-        // 1. Hard Filter:
-        if let Some(max) = args.max_alt {
-            if alt > max { return None; }
+fn display_role(role: &Option<Role>) -> String {
+    role.map(|r| r.to_string()).unwrap_or("-".to_string())
+}
+
+impl Command {
+    /// The feed query for the worldwide subcommands (None for the others)
+    pub fn query(&self) -> Option<Query> {
+        match self {
+            Command::Mil => Some(Query::Mil),
+            Command::Hex { hex } => Some(Query::Hex(hex.to_lowercase())),
+            Command::Callsign { callsign } => Some(Query::Callsign(callsign.to_uppercase())),
+            Command::Reg { registration } => Some(Query::Reg(registration.to_uppercase())),
+            Command::Type { type_code } => Some(Query::Type(type_code.to_uppercase())),
+            Command::Squawk { code } => Some(Query::Squawk(code.clone())),
+            Command::Replay { .. } | Command::ServeCapture { .. } => None,
         }
+    }
+}
 
-        // Spoofing / Jamming Check:
-        if let (Some(baro), Some(geom)) = (self.alt_baro, self.alt_geom) {
-            // Calculate absolute Difference:
-            let delta = (baro - geom).abs();
+// Bits of `dbFlags` (as documented by readsb)
+const DB_FLAG_MILITARY: u32 = 1;
+const DB_FLAG_INTERESTING: u32 = 2;
+const DB_FLAG_PIA: u32 = 4;
+const DB_FLAG_LADD: u32 = 8;
 
-            // If difference is larger than threshold: Alert
-            if delta > args.spoof_delta {
-                reasons.push(format!("NAV ANOMALY (Δ {:.0}ft)", delta));
-            }
-        }
+/// How long callsign/source/registration changes stay in the Reason column (seconds)
+const EVENT_SHOW_SECS: f64 = 600.0;
+
+impl Aircraft {
+    /// Feed fields that can be shown as extra table columns (readsb names)
+    pub const FIELDS: [&'static str; 45] = [
+        "r", "desc", "ownOp", "year", "category", "squawk", "emergency", "alert", "spi",
+        "track", "true_heading", "mag_heading", "track_rate", "roll", "baro_rate", "geom_rate",
+        "alt_geom", "ias", "tas", "mach", "wd", "ws", "oat", "tat",
+        "nav_qnh", "nav_altitude_mcp", "nav_altitude_fms", "nav_heading", "nav_modes",
+        "version", "nic", "rc", "nic_baro", "nac_p", "nac_v", "sil", "sil_type", "gva", "sda",
+        "dbFlags", "seen", "seen_pos", "rssi", "messages", "position",
+    ];
+
+    /// One of the `FIELDS` as text, None if the feed didn't send it
+    pub fn field(&self, name: &str) -> Option<String> {
+        let num = |v: Option<f64>| v.map(|n| format!("{}", (n * 1000.0).round() / 1000.0));
+        let flag = |v: Option<bool>| v.map(|b| if b { "yes" } else { "no" }.to_string());
 
-        // 2. Intelligence Triggers:
-        // A. Speed and Altitude:
-        if (alt < 25000.0 && speed > args.speed) || speed > 550.0 {
-            reasons.push(format!("Speed ({:.0} kts @ {:.0} ft)", speed, alt));
+        match name {
+            "r" => self.registration.clone(),
+            "desc" => self.desc.clone(),
+            "ownOp" => self.owner_operator.clone(),
+            "year" => self.year.clone(),
+            "category" => self.category.clone(),
+            "squawk" => self.squawk.clone(),
+            "emergency" => self.emergency.clone(),
+            "alert" => flag(self.alert),
+            "spi" => flag(self.spi),
+            "track" => num(self.track),
+            "true_heading" => num(self.true_heading),
+            "mag_heading" => num(self.mag_heading),
+            "track_rate" => num(self.track_rate),
+            "roll" => num(self.roll),
+            "baro_rate" => num(self.baro_rate),
+            "geom_rate" => num(self.geom_rate),
+            "alt_geom" => num(self.alt_geom),
+            "ias" => num(self.ias),
+            "tas" => num(self.tas),
+            "mach" => num(self.mach),
+            "wd" => num(self.wd),
+            "ws" => num(self.ws),
+            "oat" => num(self.oat),
+            "tat" => num(self.tat),
+            "nav_qnh" => num(self.nav_qnh),
+            "nav_altitude_mcp" => num(self.nav_altitude_mcp),
+            "nav_altitude_fms" => num(self.nav_altitude_fms),
+            "nav_heading" => num(self.nav_heading),
+            "nav_modes" => self.nav_modes.as_ref().map(|m| m.join(" ")),
+            "version" => num(self.version),
+            "nic" => num(self.nic),
+            "rc" => num(self.rc),
+            "nic_baro" => num(self.nic_baro),
+            "nac_p" => num(self.nac_p),
+            "nac_v" => num(self.nac_v),
+            "sil" => num(self.sil),
+            "sil_type" => self.sil_type.clone(),
+            "gva" => num(self.gva),
+            "sda" => num(self.sda),
+            "dbFlags" => self.db_flags.map(|f| {
+                let names = self.db_flag_names();
+                if names.is_empty() { format!("{}", f) } else { format!("{} ({})", f, names.join(" ")) }
+            }),
+            "seen" => num(self.seen),
+            "seen_pos" => num(self.seen_pos),
+            "rssi" => num(self.rssi),
+            "messages" => num(self.messages),
+            "position" => self.lat.zip(self.lon).map(|(lat, lon)| format!("{:.4}, {:.4}", lat, lon)),
+            _ => None,
         }
+    }
+
+    fn has_db_flag(&self, bit: u32) -> bool {
+        self.db_flags.map(|f| f as u32 & bit != 0).unwrap_or(false)
+    }
+
+    /// Short names of the set `dbFlags` bits
+    pub fn db_flag_names(&self) -> Vec<&'static str> {
+        [(DB_FLAG_MILITARY, "mil"), (DB_FLAG_INTERESTING, "interesting"), (DB_FLAG_PIA, "pia"), (DB_FLAG_LADD, "ladd")]
+            .into_iter()
+            .filter(|(bit, _)| self.has_db_flag(*bit))
+            .map(|(_, name)| name)
+            .collect()
+    }
+
+    /// Privacy ICAO Address: the FAA hands out temporary addresses that don't lead to the owner
+    pub fn is_pia(&self) -> bool {
+        self.has_db_flag(DB_FLAG_PIA)
+    }
 
-        // B. MLAT Detection (Ghost Tracking)
-        // List of boring small planes we want to ignore:
-        let boring_types = vec!["C172", "C152", "P28A", "DA40", "R44", "G115"];
+    /// Limiting Aircraft Data Displayed: the owner asked the FAA to keep the flight off public sites
+    pub fn is_ladd(&self) -> bool {
+        self.has_db_flag(DB_FLAG_LADD)
+    }
+
+    /// readsb marks addresses that are not real ICAO addresses (TIS-B tracks, anonymous ADS-B) with a '~'
+    pub fn is_non_icao(&self) -> bool {
+        self.icao.starts_with('~')
+    }
+
+    /// Country of registration, from the ICAO address block
+    pub fn country(&self) -> Option<&'static str> {
+        country::country_of(&self.icao)
+    }
+
+    /// Military by the feed's flag, the dbFlags bit, a known military hex range or a military callsign
+    pub fn military(&self, lookups: &Lookups) -> bool {
+        self.is_military.unwrap_or(false)
+            || self.has_db_flag(DB_FLAG_MILITARY)
+            || lookups.mil_ranges.lookup(&self.icao).is_some()
+            || self.airline(lookups).is_some_and(|a| a.military)
+    }
 
-        if self.source_type == "mlat" {
-            if !boring_types.contains(&type_code.as_str()) {
-                // for now, simply flag it as mlat source:
-                reasons.push("MLAT as source".to_string());
+    /// Who flies under the callsign (airline designator or a known military callsign)
+    pub fn airline<'a>(&self, lookups: &'a Lookups) -> Option<&'a Airline> {
+        lookups.airlines.lookup(self.callsign.as_deref()?)
+    }
+
+    /// Does the aircraft pass --country/--exclude-country? (unknown countries only pass without --country)
+    pub fn country_allowed(&self, args: &Args) -> bool {
+        match self.country() {
+            Some(c) => {
+                (args.country.is_empty() || country::in_list(c, &args.country))
+                    && !country::in_list(c, &args.exclude_country)
             }
+            None => args.country.is_empty(),
         }
+    }
 
-        // C. High Value Target (HVT)
-        if let Some(t) = &self.type_code {
-            // default list:
-            let high_value = vec![
-                "K35R", "K46", "A332", "E3TF", "C17", "A400", // Tanker/Transport
-                "B52", "B1", "B2", // Bomber
-                "EUFI", "F35", "F16", "F18", "TORN" // Fighter
-            ];
-            if high_value.contains(&t.as_str()) {
-                reasons.push(format!("HVT: {}", t));
-            }
+    /// Does the aircraft pass --role? (unknown roles only pass without it)
+    pub fn role_allowed(&self, args: &Args, lookups: &Lookups) -> bool {
+        args.role.is_empty() || role::classify(self, lookups).is_some_and(|r| args.role.contains(&r))
+    }
 
-            // if user searched explicit types:
-            if let Some(target_type) = &args.aircraft_type {
-                if t.contains(target_type) {
-                    reasons.push("Target Type Match".to_string());
-                }
+    /// Why we can't know who flies this airframe (shown instead of "Unknown")
+    pub fn anonymity(&self) -> Option<&'static str> {
+        if self.is_non_icao() {
+            if self.source_type.starts_with("tisb") {
+                Some("Anonymous (TIS-B track)")
+            } else {
+                Some("Anonymous (non-ICAO address)")
             }
+        } else if self.is_pia() {
+            Some("Hidden (PIA address)")
+        } else if self.is_ladd() {
+            Some("Hidden (LADD)")
+        } else {
+            None
         }
+    }
 
-        // D. Explicit military flag from API
-        if self.is_military.unwrap_or(false) {
-            reasons.push("MIL FLAG".to_string());
-        }
+    // Intelligence Logic:
+    // `pattern` is what `pattern::detect` found in the track (done once per scan, it walks the whole track)
+    pub fn check_interest(&self, args: &Args, lookups: &Lookups, track: Option<&Track>, pattern: Option<Pattern>, group: Option<&Group>) -> Option<Vec<Reason>> {
+        // 1. Watchlist hits come first
+        let mut reasons: Vec<Reason> = lookups.watchlist.matches(self, self.known_registration(lookups).as_deref())
+            .iter()
+            .map(|entry| entry.reason())
+            .collect();
 
-        if reasons.is_empty() {
-            None
-        } else {
-            Some(reasons.join(", "))
+        // 2. Intelligence Triggers: everything else is in the rules (rules.toml or the built-in set)
+        let subject = Subject { ac: self, args, lookups, track, pattern, group };
+        reasons.extend(lookups.rules.evaluate(&subject));
+
+        // 3. Hard Filter: above --max-alt only emergencies and the watchlist still count
+        if let Some(max) = args.max_alt
+            && self.alt_baro.unwrap_or(0.0) > max {
+            reasons.retain(|r| r.severity == Severity::Emergency || r.category == reason::Category::Watchlist);
         }
+        (!reasons.is_empty()).then_some(reasons)
+    }
+
+    /// Registration from the feed, or from the aircraft database if the feed has none
+    pub fn known_registration(&self, lookups: &Lookups) -> Option<String> {
+        self.registration.clone()
+            .filter(|r| !r.trim().is_empty())
+            .or_else(|| lookups.db.get(&self.icao).and_then(|info| info.registration.clone()))
+    }
+
+    /// Every field a rule can test: the feed fields plus what the checks derive from them
+    pub fn rule_fields() -> Vec<&'static str> {
+        Subject::FACTS.iter().chain(Self::FIELDS.iter()).copied().collect()
+    }
+}
+
+/// One aircraft as the rules see it, with everything we know about it from earlier scans.
+struct Subject<'a> {
+    ac: &'a Aircraft,
+    args: &'a Args,
+    lookups: &'a Lookups,
+    track: Option<&'a Track>,
+    pattern: Option<Pattern>,
+    group: Option<&'a Group>,
+}
+
+impl Subject<'_> {
+    /// Derived fields, on top of `Aircraft::FIELDS`
+    const FACTS: [&'static str; 38] = [
+        "hex", "callsign", "type", "source", "gs", "speed", "alt_baro", "alt", "country",
+        "nav_delta", "military_flag", "mil_range", "military", "pia", "ladd", "non_icao", "interesting",
+        "squawk_code", "squawk_label", "squawk_severity", "emergency_state", "emergency_severity",
+        "emergency_squawked", "jump", "reappeared", "callsign_change", "source_change", "adsb_lost", "reg_mismatch",
+        "formation", "pattern", "registration", "watchlist",
+        "airline", "telephony", "airline_country", "airline_military", "role",
+    ];
+
+    // Recent track events of one kind, e.g. "10:05:12 CALLSIGN RCH123 > RCH456"
+    fn recent_events(&self, pick: impl Fn(&TrackEvent) -> bool) -> Option<rules::Value> {
+        let events: Vec<String> = self.track.iter()
+            .flat_map(|t| t.events.iter().filter(|e| t.last_seen - e.time <= EVENT_SHOW_SECS))
+            .filter(|e| pick(e))
+            .map(|e| e.stamped())
+            .collect();
+        (!events.is_empty()).then(|| rules::Value::Text(events.join(", ")))
     }
 }
 
-/// Try to guess the operator using the callsign:
-fn resolve_operator_by_callsign(callsign: &str) -> Option<String> {
-    let cs = callsign.to_uppercase();
-
-    // Military Callsign:
-    if cs.starts_with("RCH") { return Some("US Air Force (AMC)".to_string()); }
-    if cs.starts_with("RRR") { return Some("Royal Air Force".to_string()); }
-    if cs.starts_with("RRF") { return Some("Royal Air Force".to_string()); }
-    if cs.starts_with("NATO") { return Some("NATO E-3A Component".to_string()); }
-    if cs.starts_with("CNV") { return Some("US Navy".to_string()); }
-    if cs.starts_with("GAF") { return Some("German Air Force".to_string()); }
-    if cs.starts_with("IAM") { return Some("Italian Air Force".to_string()); }
-    if cs.starts_with("FAF") { return Some("French Air Force".to_string()); }
-    if cs.starts_with("SUI") { return Some("Swiss Air Force".to_string()); }
-    if cs.starts_with("TUAF") { return Some("Turkish Air Force".to_string()); }
-    if cs.starts_with("HVK") { return Some("Turkish Air Force".to_string()); }  // (Hava Kuvvetleri)
-
-    // Civil Airlines:
-    if cs.starts_with("DLH") { return Some("Lufthansa".to_string()); }
-    if cs.starts_with("RYR") { return Some("Ryanair".to_string()); }
-    if cs.starts_with("EZY") { return Some("EasyJet".to_string()); }
-    if cs.starts_with("BAW") { return Some("British Airways".to_string()); }
-    if cs.starts_with("AFR") { return Some("Air France".to_string()); }
-    if cs.starts_with("KLM") { return Some("KLM Royal Dutch Airlines".to_string()); }
-    if cs.starts_with("SAS") { return Some("Scandinavian Airlines".to_string()); }
-    if cs.starts_with("SVA") { return Some("Saudia".to_string()); }
-    if cs.starts_with("UAE") { return Some("Emirates".to_string()); }
-    if cs.starts_with("QTR") { return Some("Qatar Airways".to_string()); }
-    if cs.starts_with("THY") { return Some("Turkish Airlines".to_string()); }
-
-    None
+impl Facts for Subject<'_> {
+    fn fact(&self, name: &str) -> Option<rules::Value> {
+        let ac = self.ac;
+        let text = |s: &Option<String>| s.as_deref().map(str::trim).filter(|s| !s.is_empty()).map(|s| rules::Value::Text(s.to_string()));
+        let squawk = || ac.squawk.as_deref().and_then(|s| self.lookups.squawks.lookup(s));
+        let emergency = || ac.emergency.as_deref().and_then(squawk::emergency_severity);
+
+        match name {
+            "hex" => Some(rules::Value::Text(ac.icao.clone())),
+            "callsign" => text(&ac.callsign),
+            "type" => text(&ac.type_code),
+            "registration" => text(&ac.known_registration(self.lookups)),
+            "source" => Some(rules::Value::Text(ac.source_type.clone())),
+            "gs" => ac.ground_speed.map(rules::Value::Num),
+            "speed" => Some(rules::Value::Num(ac.ground_speed.unwrap_or(0.0))),
+            "alt_baro" => ac.alt_baro.map(rules::Value::Num),
+            "alt" => Some(rules::Value::Num(ac.alt_baro.unwrap_or(0.0))),
+            "country" => ac.country().map(|c| rules::Value::Text(c.to_string())),
+
+            // Spoofing / Jamming: difference between baro and GPS altitude
+            "nav_delta" => ac.alt_baro.zip(ac.alt_geom).map(|(baro, geom)| rules::Value::Num((baro - geom).abs())),
+
+            "military_flag" => Some(rules::Value::Bool(ac.is_military.unwrap_or(false) || ac.has_db_flag(DB_FLAG_MILITARY))),
+            "mil_range" => self.lookups.mil_ranges.lookup(&ac.icao).map(|r| rules::Value::Text(r.describe())),
+            "military" => Some(rules::Value::Bool(ac.military(self.lookups))),
+            "pia" => Some(rules::Value::Bool(ac.is_pia())),
+            "ladd" => Some(rules::Value::Bool(ac.is_ladd())),
+            "non_icao" => Some(rules::Value::Bool(ac.is_non_icao())),
+            "interesting" => Some(rules::Value::Bool(ac.has_db_flag(DB_FLAG_INTERESTING))),
+
+            // Emergencies: squawk codes and the emergency state the transponder sends
+            "squawk_code" => squawk().and(ac.squawk.as_deref()).and_then(squawk::normalize).map(rules::Value::Text),
+            "squawk_label" => squawk().map(|code| rules::Value::Text(code.label.clone())),
+            "squawk_severity" => squawk().map(|code| rules::Value::Text(code.severity.name().to_string())),
+            "emergency_state" => emergency().and(ac.emergency.as_ref()).map(|state| rules::Value::Text(state.to_lowercase())),
+            "emergency_severity" => emergency().map(|level| rules::Value::Text(level.name().to_string())),
+            // 7500 + "unlawful" etc. say the same thing twice
+            "emergency_squawked" => Some(rules::Value::Bool(
+                ac.squawk.as_deref().and_then(squawk::implied_emergency)
+                    .is_some_and(|implied| ac.emergency.as_deref().is_some_and(|state| state.trim().eq_ignore_ascii_case(implied)))
+            )),
+
+            // Position jumps between scans (GPS spoofing moves the reported position, not the aircraft)
+            "jump" => self.track.and_then(|t| t.position_jump(self.args.jump_speed)).map(|jump| {
+                rules::Value::Text(if jump.standing_still {
+                    format!("{:.0}nm at gs 0", jump.distance_nm)
+                } else {
+                    format!("{:.0}nm in {:.0}s = {:.0} kts", jump.distance_nm, jump.seconds, jump.implied_speed)
+                })
+            }),
+
+            // Back after going dark (for a while after it reappeared)
+            "reappeared" => self.track
+                .and_then(Track::recently_reappeared)
+                .map(|back| {
+                    let distance = back.distance_nm.map(|d| format!(", {:.0}nm from last position", d)).unwrap_or_default();
+                    rules::Value::Text(format!("after {}{}", tracks::format_duration(back.gap_secs), distance))
+                }),
+
+            // Recent changes: callsign, position source, registration
+            "callsign_change" => self.recent_events(|e| e.kind == EventKind::Callsign),
+            "source_change" => self.recent_events(|e| e.kind == EventKind::Source && !e.adsb_lost()),
+            "adsb_lost" => self.recent_events(|e| e.adsb_lost()),
+            "reg_mismatch" => self.recent_events(|e| e.kind == EventKind::Registration),
+
+            "formation" => self.group.map(|g| rules::Value::Text(g.to_string())),
+            "pattern" => self.pattern.map(|p| rules::Value::Text(p.to_string())),
+
+            // Who flies under the callsign
+            "airline" => ac.airline(self.lookups).map(|a| rules::Value::Text(a.name.clone())),
+            "telephony" => ac.airline(self.lookups).map(|a| a.telephony.clone()).filter(|t| !t.is_empty()).map(rules::Value::Text),
+            "airline_country" => ac.airline(self.lookups).map(|a| a.country.clone()).filter(|c| !c.is_empty()).map(rules::Value::Text),
+            "airline_military" => Some(rules::Value::Bool(ac.airline(self.lookups).is_some_and(|a| a.military))),
+
+            // Probable mission: tanker, aew, isr, airlift, vip, fighter, uav, maritime, bomber
+            "role" => role::classify(ac, self.lookups).and_then(|r| r.to_possible_value()).map(|v| rules::Value::Text(v.get_name().to_string())),
+
+            // Labels of the watchlist entries it matches
+            "watchlist" => {
+                let labels: Vec<String> = self.lookups.watchlist.matches(ac, ac.known_registration(self.lookups).as_deref())
+                    .iter()
+                    .map(|e| e.label.clone())
+                    .collect();
+                (!labels.is_empty()).then(|| rules::Value::Text(labels.join(", ")))
+            }
+            _ => ac.field(name).map(rules::Value::Text),
+        }
+    }
+
+    fn variable(&self, name: &str) -> Option<rules::Value> {
+        let args = self.args;
+        match name {
+            "speed" => Some(rules::Value::Num(args.speed)),
+            "spoof_delta" => Some(rules::Value::Num(args.spoof_delta)),
+            "aircraft_type" => args.aircraft_type.clone().map(rules::Value::Text),
+            "countries_of_interest" => Some(rules::Value::List(args.countries_of_interest.clone())),
+            "min_alt" => args.min_alt.map(rules::Value::Num),
+            "max_alt" => args.max_alt.map(rules::Value::Num),
+            _ => None,
+        }
+    }
 }
 
 impl DefenseDisplay {
-    pub fn new(a: &Aircraft, reason: String, db: &AircraftDB) -> Self {
+    /// Values derived from the track history (and the formation check, watchlist and callsign) that can be shown as extra columns
+    pub const TRACK_FIELDS: [&'static str; 14] = [
+        "first_seen", "in_sector", "flown_nm", "track_points", "sources",
+        "pattern", "pattern_center", "pattern_radius", "events", "group", "notes",
+        "airline", "telephony", "airline_country",
+    ];
+
+    /// Everything --columns accepts: track values first, then the feed fields
+    pub fn columns() -> Vec<&'static str> {
+        Self::TRACK_FIELDS.iter().chain(Aircraft::FIELDS.iter()).copied().collect()
+    }
+
+    /// One of the `columns()` as text, None if we don't know it
+    pub fn field(&self, name: &str) -> Option<String> {
+        let track = self.track.as_ref();
+        match name {
+            "first_seen" => track.map(|t| events::clock(t.first_seen)),
+            "in_sector" => track.map(|t| tracks::format_duration(t.time_in_sector)),
+            "flown_nm" => track.map(|t| format!("{:.1}", t.distance_nm)),
+            "track_points" => track.map(|t| t.points.to_string()),
+            "sources" => track.map(|t| t.sources.clone()).filter(|s| !s.is_empty()),
+            "pattern" => self.pattern.map(|p| p.kind.to_string()),
+            "pattern_center" => self.pattern.map(|p| format!("{:.4}, {:.4}", p.center.0, p.center.1)),
+            "pattern_radius" => self.pattern.map(|p| format!("{:.1}", p.radius_nm)),
+            "events" => track
+                .filter(|t| !t.events.is_empty())
+                .map(|t| t.events.join("; ")),
+            // the other members of the formation
+            "group" => self.group.as_ref().map(|g| {
+                let others: Vec<&str> = g.members.iter().filter(|m| **m != self.icao).map(|m| m.as_str()).collect();
+                format!("#{} with {}", g.id, others.join(", "))
+            }),
+            // from the airline designator table
+            "airline" => self.airline.as_ref().map(|a| a.name.clone()),
+            "telephony" => self.airline.as_ref().map(|a| a.telephony.clone()).filter(|t| !t.is_empty()),
+            "airline_country" => self.airline.as_ref().map(|a| a.country.clone()).filter(|c| !c.is_empty()),
+            // from the watchlist
+            "notes" => {
+                let notes: Vec<&str> = self.reasons.iter().filter_map(|r| r.notes.as_deref()).collect();
+                (!notes.is_empty()).then(|| notes.join("; "))
+            }
+            _ => self.aircraft.field(name),
+        }
+    }
+
+    pub fn new(a: &Aircraft, reasons: Vec<Reason>, lookups: &Lookups, track: Option<&Track>, pattern: Option<Pattern>, group: Option<&Group>) -> Self {
         let callsign = a.callsign.clone().unwrap_or("".to_string());
 
         // Operator Lookup via DB:
-        let mut operator = if let Some(info) = db.get(&a.icao) {
+        let mut operator = if let Some(info) = lookups.db.get(&a.icao) {
             info.operator.clone().unwrap_or("Unknown".to_string())
         } else {
             "Unknown".to_string()
         };
 
-        // Try finding the operator via Callsign Intelligence if DB has failed:
-        if operator == "Unknown" || operator.is_empty() {
-            if let Some(guessed_op) = resolve_operator_by_callsign(&callsign) {
-                operator = guessed_op; // overwrite with new result
-            }
+        // Try finding the operator via the callsign if DB has failed, with the radio name: US Air Force (AMC) "REACH"
+        let airline = a.airline(lookups);
+        if (operator == "Unknown" || operator.is_empty())
+            && let Some(airline) = airline {
+            operator = if airline.telephony.is_empty() || airline.telephony.eq_ignore_ascii_case(&airline.name) {
+                airline.name.clone()
+            } else {
+                format!("{} \"{}\"", airline.name, airline.telephony)
+            };
+        }
+
+        // Still nothing: say why, if the airframe is anonymous on purpose
+        if (operator == "Unknown" || operator.is_empty())
+            && let Some(hint) = a.anonymity() {
+            operator = hint.to_string();
         }
 
         // Calculate delta:
@@ -259,17 +996,225 @@ impl DefenseDisplay {
         };
 
         Self {
+            severity: reason::severity(&reasons),
+            score: reason::threat_score(&reasons),
             icao: a.icao.clone(),
             type_code: a.type_code.clone().unwrap_or("???".to_string()),
+            role: role::classify(a, lookups),
             operator,
+            country: a.country().unwrap_or("-").to_string(),
             callsign,
             speed: a.ground_speed.unwrap_or(0.0),
             alt: a.alt_baro.unwrap_or(0.0),
             delta: delta_str,
             source: a.source_type.clone(),
-            reason,
+            reason: reason::describe(&reasons),
+            reasons,
             lat: a.lat.unwrap_or(0.0),
             lon: a.lon.unwrap_or(0.0),
+            aircraft: a.clone(),
+            track: track.map(Track::summary),
+            pattern,
+            group: group.cloned(),
+            airline: airline.cloned(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::watchlist::Watchlist;
+
+    // Reasons (text and severity) the built-in rules give, with the command line flags
+    fn reasons(ac: &Aircraft, flags: &[&str]) -> Vec<(String, Severity)> {
+        let args = Args::parse_from(["flight_radar_cli"].iter().chain(flags));
+        ac.check_interest(&args, &Lookups::built_in(), None, None, None)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|r| (r.text(), r.severity))
+            .collect()
+    }
+
+    fn texts(ac: &Aircraft, flags: &[&str]) -> Vec<String> {
+        reasons(ac, flags).into_iter().map(|(text, _)| text).collect()
+    }
+
+    // A civil aircraft nothing is wrong with (Dutch address, ADS-B)
+    fn airliner() -> Aircraft {
+        Aircraft {
+            icao: "484506".to_string(),
+            type_code: Some("B738".to_string()),
+            alt_baro: Some(36000.0),
+            alt_geom: Some(36200.0),
+            ground_speed: Some(450.0),
+            source_type: "adsb_icao".to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn with_flags(flags: u32) -> Aircraft {
+        Aircraft { db_flags: Some(flags as f64), ..airliner() }
+    }
+
+    #[test]
+    fn each_db_flag_bit() {
+        assert!(with_flags(0).db_flag_names().is_empty());
+        assert!(airliner().db_flag_names().is_empty());
+        assert_eq!(with_flags(1).db_flag_names(), ["mil"]);
+        assert_eq!(with_flags(2).db_flag_names(), ["interesting"]);
+        assert_eq!(with_flags(4).db_flag_names(), ["pia"]);
+        assert_eq!(with_flags(8).db_flag_names(), ["ladd"]);
+        assert_eq!(with_flags(15).db_flag_names(), ["mil", "interesting", "pia", "ladd"]);
+        // bits we don't know are ignored
+        assert_eq!(with_flags(16 | 4).db_flag_names(), ["pia"]);
+
+        assert!(with_flags(4).is_pia() && !with_flags(4).is_ladd());
+        assert!(with_flags(8).is_ladd() && !with_flags(8).is_pia());
+    }
+
+    #[test]
+    fn anonymity_says_why_the_owner_is_unknown() {
+        assert_eq!(airliner().anonymity(), None);
+        assert_eq!(with_flags(4).anonymity(), Some("Hidden (PIA address)"));
+        assert_eq!(with_flags(8).anonymity(), Some("Hidden (LADD)"));
+        // PIA comes before LADD
+        assert_eq!(with_flags(12).anonymity(), Some("Hidden (PIA address)"));
+
+        let tisb = Aircraft { icao: "~a1b2c3".to_string(), source_type: "tisb_other".to_string(), ..airliner() };
+        assert!(tisb.is_non_icao());
+        assert_eq!(tisb.anonymity(), Some("Anonymous (TIS-B track)"));
+        let adsr = Aircraft { source_type: "adsr_other".to_string(), ..tisb.clone() };
+        assert_eq!(adsr.anonymity(), Some("Anonymous (non-ICAO address)"));
+        // the non-ICAO address wins over the flags
+        assert_eq!(Aircraft { db_flags: Some(4.0), ..tisb }.anonymity(), Some("Anonymous (TIS-B track)"));
+        assert!(!airliner().is_non_icao());
+    }
+
+    #[test]
+    fn db_flags_raise_reasons() {
+        assert!(texts(&airliner(), &[]).is_empty());
+        assert_eq!(texts(&with_flags(1), &[]), ["MIL FLAG"]);
+        assert_eq!(texts(&with_flags(2), &[]), ["DB: interesting"]);
+        assert_eq!(texts(&with_flags(4), &[]), ["PIA: privacy address"]);
+        assert_eq!(texts(&with_flags(8), &[]), ["LADD: blocked owner"]);
+        assert_eq!(texts(&with_flags(12), &[]), ["PIA: privacy address", "LADD: blocked owner"]);
+
+        let tisb = Aircraft { icao: "~a1b2c3".to_string(), source_type: "tisb_icao".to_string(), ..airliner() };
+        assert_eq!(texts(&tisb, &[]), ["NON-ICAO ADDRESS (tisb_icao)"]);
+    }
+
+    #[test]
+    fn nothing_to_report() {
+        assert!(reasons(&airliner(), &[]).is_empty());
+    }
+
+    #[test]
+    fn nav_anomaly_is_a_warning() {
+        let spoofed = Aircraft { alt_geom: Some(38400.0), ..airliner() };
+        assert_eq!(reasons(&spoofed, &[]), [("NAV ANOMALY (Δ 2400ft)".to_string(), Severity::Warning)]);
+        assert!(reasons(&spoofed, &["--spoof-delta", "3000"]).is_empty());
+    }
+
+    #[test]
+    fn fast_and_low_or_very_fast() {
+        let low = Aircraft { alt_baro: Some(8000.0), alt_geom: Some(8100.0), ground_speed: Some(320.4), ..airliner() };
+        assert_eq!(reasons(&low, &[]), [("Speed (320 kts @ 8000 ft)".to_string(), Severity::Normal)]);
+        assert!(reasons(&low, &["--speed", "350"]).is_empty());
+        // up high only above 550 kts
+        assert!(reasons(&Aircraft { ground_speed: Some(540.0), ..airliner() }, &[]).is_empty());
+        assert_eq!(texts(&Aircraft { ground_speed: Some(560.0), ..airliner() }, &[]), ["Speed (560 kts @ 36000 ft)"]);
+        // no altitude counts as 0 ft
+        assert_eq!(texts(&Aircraft { alt_baro: None, alt_geom: None, ..low }, &[]), ["Speed (320 kts @ 0 ft)"]);
+    }
+
+    #[test]
+    fn mlat_leaves_out_boring_types() {
+        let mlat = Aircraft { source_type: "mlat".to_string(), ..airliner() };
+        assert_eq!(texts(&mlat, &[]), ["MLAT as source"]);
+        assert!(reasons(&Aircraft { type_code: Some("C172".to_string()), ..mlat.clone() }, &[]).is_empty());
+        // an unknown type isn't one of the boring ones
+        assert_eq!(texts(&Aircraft { type_code: None, ..mlat }, &[]), ["MLAT as source"]);
+    }
+
+    #[test]
+    fn high_value_types() {
+        for t in ["K35R", "E3TF", "B52", "EUFI", "TORN"] {
+            let ac = Aircraft { type_code: Some(t.to_string()), ..airliner() };
+            assert_eq!(reasons(&ac, &[]), [(format!("HVT: {}", t), Severity::Normal)]);
         }
+        assert!(reasons(&Aircraft { type_code: Some("K35".to_string()), ..airliner() }, &[]).is_empty());
+    }
+
+    #[test]
+    fn target_type_only_when_asked_for() {
+        assert!(reasons(&airliner(), &[]).is_empty());
+        assert_eq!(texts(&airliner(), &["--aircraft-type", "B73"]), ["Target Type Match"]);
+        assert!(reasons(&airliner(), &["--aircraft-type", "A32"]).is_empty());
+        // no type, no match
+        assert!(reasons(&Aircraft { type_code: None, ..airliner() }, &["--aircraft-type", "B73"]).is_empty());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn military_flag() {
+        let flagged = Aircraft { is_military: Some(true), ..airliner() };
+        assert_eq!(texts(&flagged, &[]), ["MIL FLAG"]);
+    }
+
+    #[test]
+    fn military_hex_counts_as_military() {
+        // US military block, no flag from the feed
+        let ac = Aircraft { icao: "ae1234".to_string(), ..airliner() };
+        assert_eq!(texts(&ac, &[]), ["MIL HEX: US military ADF7C8-AFFFFF"]);
+        assert_eq!(texts(&ac, &["--countries-of-interest", "Russia,United States"]), [
+            "MIL HEX: US military ADF7C8-AFFFFF",
+            "FOREIGN MIL IN SECTOR (Country: United States)",
+        ]);
+        // civil aircraft from a country of interest aren't foreign military
+        let civil = Aircraft { icao: "a12345".to_string(), ..airliner() };
+        assert!(reasons(&civil, &["--countries-of-interest", "United States"]).is_empty());
+    }
+
+    #[test]
+    fn squawk_covers_the_emergency_state() {
+        let hijack = Aircraft { squawk: Some("7500".to_string()), emergency: Some("unlawful".to_string()), ..airliner() };
+        assert_eq!(reasons(&hijack, &[]), [("SQUAWK 7500: HIJACK".to_string(), Severity::Emergency)]);
+        let state_only = Aircraft { squawk: Some("1000".to_string()), ..hijack };
+        assert_eq!(reasons(&state_only, &[]), [("EMERGENCY: unlawful".to_string(), Severity::Emergency)]);
+    }
+
+    #[test]
+    fn max_alt_keeps_emergencies_and_the_watchlist() {
+        let args = ["--max-alt", "30000"];
+        let flagged = Aircraft { is_military: Some(true), ..airliner() };
+        assert!(reasons(&flagged, &args).is_empty());
+
+        // a military hijack is still a hijack
+        let hijack = Aircraft { squawk: Some("7500".to_string()), ..flagged.clone() };
+        assert_eq!(reasons(&hijack, &args), [("SQUAWK 7500: HIJACK".to_string(), Severity::Emergency)]);
+
+        let path = std::env::temp_dir().join(format!("max-alt-watchlist-{}.csv", std::process::id()));
+        std::fs::write(&path, "field,pattern,label\nhex,484506,SAM\n").unwrap();
+        let mut lookups = Lookups::built_in();
+        lookups.watchlist = Watchlist::load(&[path.to_string_lossy().to_string()]).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let cli = Args::parse_from(["flight_radar_cli", "--max-alt", "30000"]);
+        let watched = flagged.check_interest(&cli, &lookups, None, None, None).unwrap();
+        assert_eq!(watched.iter().map(|r| r.text()).collect::<Vec<_>>(), ["WATCHLIST: SAM"]);
+    }
+
+    #[test]
+    fn role_filter() {
+        let lookups = Lookups::built_in();
+        let tanker = Aircraft { type_code: Some("K35R".to_string()), ..airliner() };
+        let fighter = Aircraft { type_code: Some("F16".to_string()), ..airliner() };
+
+        let args = Args::parse_from(["flight_radar_cli", "--role", "tanker,aew"]);
+        assert!(tanker.role_allowed(&args, &lookups));
+        assert!(!fighter.role_allowed(&args, &lookups));
+        assert!(!airliner().role_allowed(&args, &lookups)); // no role at all
+
+        let no_filter = Args::parse_from(["flight_radar_cli"]);
+        assert!(airliner().role_allowed(&no_filter, &lookups));
+    }
+}
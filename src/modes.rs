@@ -0,0 +1,557 @@
+// Mode S / ADS-B decoder for raw 1090 MHz frames.
+// References: ICAO Annex 10 Vol. IV, DO-260B and "The 1090MHz Riddle" (mode-s.org).
+
+/// Generator polynomial of the 24 bit Mode S parity
+const CRC_POLY: u32 = 0xFFF409;
+
+/// Number of latitude zones used by CPR
+const NZ: f64 = 15.0;
+
+// 6 bit character set used in identification messages ('#' = invalid)
+const CALLSIGN_CHARS: &[u8; 64] = b"#ABCDEFGHIJKLMNOPQRSTUVWXYZ##### ###############0123456789######";
+
+/// One decoded Mode S message. Only the parts we can put into `Aircraft` are kept.
+#[derive(Debug)]
+pub enum ModeS {
+    /// TC 1-4: callsign and emitter category
+    Identification { category: String, callsign: String },
+    /// TC 9-18 (baro) and TC 20-22 (GNSS): altitude plus one half of a CPR pair
+    AirbornePosition { alt: Option<f64>, gnss: bool, cpr: CprFrame },
+    /// TC 5-8: the aircraft is on the ground (surface CPR is not decoded)
+    SurfacePosition,
+    /// TC 19: velocity over ground or airspeed/heading, plus vertical rate
+    Velocity {
+        ground_speed: Option<f64>,
+        track: Option<f64>,
+        heading: Option<f64>,
+        ias: Option<f64>,
+        tas: Option<f64>,
+        vertical_rate: Option<f64>,
+        baro_rate: bool,
+    },
+    /// TC 28 subtype 1: emergency state and the current squawk
+    Emergency { emergency: &'static str, squawk: String },
+    /// DF4/DF20: plain altitude reply, the address is recovered from the parity
+    Altitude { alt: Option<f64> },
+    /// DF5/DF21: identity reply (squawk), the address is recovered from the parity
+    Identity { squawk: String },
+}
+
+/// One half of a CPR encoded position.
+#[derive(Debug, Clone, Copy)]
+pub struct CprFrame {
+    pub odd: bool,
+    pub lat: u32,
+    pub lon: u32,
+    pub time: f64,
+}
+
+/// A decoded frame: who sent it, how it got here and what it said.
+#[derive(Debug)]
+pub struct Decoded {
+    pub icao: String,
+    pub source_type: &'static str,
+    pub message: ModeS,
+}
+
+/// Computes the 24 bit Mode S parity over the given bytes.
+pub fn crc24(data: &[u8]) -> u32 {
+    let mut crc: u32 = 0;
+    for &byte in data {
+        crc ^= (byte as u32) << 16;
+        for _ in 0..8 {
+            crc = if crc & 0x800000 != 0 { (crc << 1) ^ CRC_POLY } else { crc << 1 };
+            crc &= 0xFFFFFF;
+        }
+    }
+    crc
+}
+
+// Last three bytes of a frame are always the parity field:
+fn parity_field(msg: &[u8]) -> u32 {
+    let n = msg.len();
+    ((msg[n - 3] as u32) << 16) | ((msg[n - 2] as u32) << 8) | msg[n - 1] as u32
+}
+
+/// Decodes a raw Mode S frame (7 or 14 bytes).
+/// `frame_time` is in seconds and only used to pair CPR frames later.
+/// DF4/DF20 replies carry the address in the parity, so they come back unverified;
+/// the caller should only trust them for aircraft it already knows.
+pub fn decode(msg: &[u8], frame_time: f64) -> Option<Decoded> {
+    if msg.len() != 7 && msg.len() != 14 {
+        return None;
+    }
+
+    let df = msg[0] >> 3;
+    match df {
+        4 | 20 => {
+            // Address/Parity: parity xor address, so the CRC gives us the address back
+            let address = crc24(&msg[..msg.len() - 3]) ^ parity_field(msg);
+            let ac13 = (((msg[2] as u32) << 8) | msg[3] as u32) & 0x1FFF;
+            Some(Decoded {
+                icao: format!("{:06x}", address),
+                source_type: "mode_s",
+                message: ModeS::Altitude { alt: decode_ac13(ac13) },
+            })
+        }
+        5 | 21 => {
+            // Same layout as DF4/DF20, but the 13 bits are the squawk
+            let address = crc24(&msg[..msg.len() - 3]) ^ parity_field(msg);
+            let id13 = (((msg[2] as u32) << 8) | msg[3] as u32) & 0x1FFF;
+            Some(Decoded {
+                icao: format!("{:06x}", address),
+                source_type: "mode_s",
+                message: ModeS::Identity { squawk: decode_id13(id13) },
+            })
+        }
+        17 | 18 if msg.len() == 14 => {
+            // Extended Squitter: the parity has to match exactly
+            if crc24(&msg[..11]) != parity_field(msg) {
+                return None;
+            }
+
+            let address = ((msg[1] as u32) << 16) | ((msg[2] as u32) << 8) | msg[3] as u32;
+            let (icao, source_type) = if df == 17 {
+                (format!("{:06x}", address), "adsb_icao")
+            } else {
+                // DF18: the control field tells us what kind of address this is
+                match msg[0] & 0x07 {
+                    0 => (format!("{:06x}", address), "adsb_icao_nt"),
+                    1 => (format!("~{:06x}", address), "adsb_other"),
+                    2 => (format!("{:06x}", address), "tisb_icao"),
+                    5 => (format!("~{:06x}", address), "tisb_other"),
+                    6 => (format!("{:06x}", address), "adsr_icao"),
+                    _ => return None, // coarse TIS-B and management messages
+                }
+            };
+
+            let mut me: u64 = 0;
+            for &b in &msg[4..11] {
+                me = (me << 8) | b as u64;
+            }
+
+            Some(Decoded {
+                icao,
+                source_type,
+                message: decode_me(me, frame_time)?,
+            })
+        }
+        _ => None,
+    }
+}
+
+// Reads `len` bits from the 56 bit ME field, `start` counts from 1 like in the specs.
+fn me_bits(me: u64, start: u32, len: u32) -> u64 {
+    (me >> (56 - (start + len - 1))) & ((1u64 << len) - 1)
+}
+
+fn decode_me(me: u64, frame_time: f64) -> Option<ModeS> {
+    let tc = me_bits(me, 1, 5);
+
+    match tc {
+        1..=4 => {
+            let mut callsign = String::new();
+            for i in 0..8 {
+                let c = CALLSIGN_CHARS[me_bits(me, 9 + i * 6, 6) as usize];
+                if c != b'#' {
+                    callsign.push(c as char);
+                }
+            }
+
+            // Category set D..A, same notation as airplanes.live ("A3" etc.)
+            let set = match tc {
+                4 => 'A',
+                3 => 'B',
+                2 => 'C',
+                _ => 'D',
+            };
+
+            Some(ModeS::Identification {
+                category: format!("{}{}", set, me_bits(me, 6, 3)),
+                callsign: callsign.trim().to_string(),
+            })
+        }
+        5..=8 => Some(ModeS::SurfacePosition),
+        9..=18 | 20..=22 => {
+            let alt_field = me_bits(me, 9, 12) as u32;
+            let alt = if tc >= 20 {
+                // GNSS height is a plain number in meters
+                if alt_field == 0 { None } else { Some(alt_field as f64 * 3.28084) }
+            } else {
+                // 12 bit field is the 13 bit AC code without the M bit
+                decode_ac13(((alt_field & 0xFC0) << 1) | (alt_field & 0x3F))
+            };
+
+            Some(ModeS::AirbornePosition {
+                alt,
+                gnss: tc >= 20,
+                cpr: CprFrame {
+                    odd: me_bits(me, 22, 1) == 1,
+                    lat: me_bits(me, 23, 17) as u32,
+                    lon: me_bits(me, 40, 17) as u32,
+                    time: frame_time,
+                },
+            })
+        }
+        19 => decode_velocity(me),
+        28 if me_bits(me, 6, 3) == 1 => {
+            // Same names as readsb uses in the "emergency" field
+            let emergency = match me_bits(me, 9, 3) {
+                0 => "none",
+                1 => "general",
+                2 => "lifeguard",
+                3 => "minfuel",
+                4 => "nordo",
+                5 => "unlawful",
+                6 => "downed",
+                _ => "reserved",
+            };
+            Some(ModeS::Emergency { emergency, squawk: decode_id13(me_bits(me, 12, 13) as u32) })
+        }
+        _ => None,
+    }
+}
+
+fn decode_velocity(me: u64) -> Option<ModeS> {
+    let subtype = me_bits(me, 6, 3);
+
+    // Vertical rate is the same for all subtypes (64 ft/min steps, 0 = unknown)
+    let vr_raw = me_bits(me, 38, 9);
+    let vertical_rate = if vr_raw == 0 {
+        None
+    } else {
+        let sign = if me_bits(me, 37, 1) == 1 { -1.0 } else { 1.0 };
+        Some(sign * (vr_raw as f64 - 1.0) * 64.0)
+    };
+    let baro_rate = me_bits(me, 36, 1) == 1;
+
+    match subtype {
+        1 | 2 => {
+            // Ground speed as east/west and north/south components
+            let factor = if subtype == 2 { 4.0 } else { 1.0 }; // supersonic
+            let v_ew = me_bits(me, 15, 10);
+            let v_ns = me_bits(me, 26, 10);
+
+            let (ground_speed, track) = if v_ew == 0 || v_ns == 0 {
+                (None, None)
+            } else {
+                let mut vx = (v_ew as f64 - 1.0) * factor;
+                let mut vy = (v_ns as f64 - 1.0) * factor;
+                if me_bits(me, 14, 1) == 1 { vx = -vx; } // flying west
+                if me_bits(me, 25, 1) == 1 { vy = -vy; } // flying south
+
+                // rounded to 0.1 like readsb does
+                let speed = ((vx * vx + vy * vy).sqrt() * 10.0).round() / 10.0;
+                let track = ((vx.atan2(vy).to_degrees() + 360.0) % 360.0 * 10.0).round() / 10.0;
+                (Some(speed), Some(track))
+            };
+
+            Some(ModeS::Velocity { ground_speed, track, heading: None, ias: None, tas: None, vertical_rate, baro_rate })
+        }
+        3 | 4 => {
+            // No ground speed here, but (magnetic) heading and IAS or TAS
+            let heading = if me_bits(me, 14, 1) == 1 {
+                Some((me_bits(me, 15, 10) as f64 * 360.0 / 1024.0 * 10.0).round() / 10.0)
+            } else {
+                None
+            };
+
+            let factor = if subtype == 4 { 4.0 } else { 1.0 }; // supersonic
+            let raw = me_bits(me, 26, 10);
+            let airspeed = if raw == 0 { None } else { Some((raw as f64 - 1.0) * factor) };
+            let (ias, tas) = if me_bits(me, 25, 1) == 1 { (None, airspeed) } else { (airspeed, None) };
+
+            Some(ModeS::Velocity { ground_speed: None, track: None, heading, ias, tas, vertical_rate, baro_rate })
+        }
+        _ => None,
+    }
+}
+
+/// Decodes the 13 bit altitude code (AC) used in DF4/DF20 and, without M bit, in ADS-B.
+/// Returns feet, or None if the code is invalid or metric.
+pub fn decode_ac13(ac13: u32) -> Option<f64> {
+    if ac13 == 0 {
+        return None;
+    }
+
+    let m_bit = ac13 & 0x40;
+    let q_bit = ac13 & 0x10;
+
+    if m_bit != 0 {
+        return None; // metric altitude, nobody uses that
+    }
+
+    if q_bit != 0 {
+        // 25 ft steps: drop the M and Q bits and read the rest as a number
+        let n = ((ac13 & 0x1F80) >> 2) | ((ac13 & 0x20) >> 1) | (ac13 & 0x0F);
+        return Some(n as f64 * 25.0 - 1000.0);
+    }
+
+    // 100 ft steps in Gillham (gray) code
+    // Bit order in the field: C1 A1 C2 A2 C4 A4 M B1 D1 B2 D2 B4 D4
+    let bit = |n: u32| (ac13 >> n) & 1;
+    let (c1, a1, c2, a2, c4, a4) = (bit(12), bit(11), bit(10), bit(9), bit(8), bit(7));
+    let (b1, b2, d2, b4, d4) = (bit(5), bit(3), bit(2), bit(1), bit(0));
+
+    let gray500 = (d2 << 7) | (d4 << 6) | (a1 << 5) | (a2 << 4) | (a4 << 3) | (b1 << 2) | (b2 << 1) | b4;
+    let gray100 = (c1 << 2) | (c2 << 1) | c4;
+
+    let n500 = gray_to_binary(gray500);
+    let mut n100 = gray_to_binary(gray100);
+
+    if n100 == 0 || n100 == 5 || n100 == 6 {
+        return None;
+    }
+    if n100 == 7 {
+        n100 = 5;
+    }
+    if n500 % 2 == 1 {
+        n100 = 6 - n100;
+    }
+
+    Some((n500 as f64 * 500.0 + n100 as f64 * 100.0) - 1300.0)
+}
+
+/// Decodes the 13 bit identity code (same bit order as the altitude code) into a 4 digit squawk.
+pub fn decode_id13(id13: u32) -> String {
+    // Bit order in the field: C1 A1 C2 A2 C4 A4 X B1 D1 B2 D2 B4 D4
+    let bit = |n: u32| (id13 >> n) & 1;
+    let a = (bit(7) << 2) | (bit(9) << 1) | bit(11);
+    let b = (bit(1) << 2) | (bit(3) << 1) | bit(5);
+    let c = (bit(8) << 2) | (bit(10) << 1) | bit(12);
+    let d = (bit(0) << 2) | (bit(2) << 1) | bit(4);
+    format!("{}{}{}{}", a, b, c, d)
+}
+
+fn gray_to_binary(gray: u32) -> u32 {
+    let mut num = gray;
+    let mut shift = gray >> 1;
+    while shift != 0 {
+        num ^= shift;
+        shift >>= 1;
+    }
+    num
+}
+
+/// Number of longitude zones at a given latitude.
+fn cpr_nl(lat: f64) -> f64 {
+    if lat == 0.0 {
+        return 59.0;
+    }
+    if lat.abs() == 87.0 {
+        return 2.0;
+    }
+    if lat.abs() > 87.0 {
+        return 1.0;
+    }
+
+    let a = 1.0 - (std::f64::consts::PI / (2.0 * NZ)).cos();
+    let b = lat.to_radians().cos().powi(2);
+    (2.0 * std::f64::consts::PI / (1.0 - a / b).acos()).floor()
+}
+
+// Modulo that is always positive (rem_euclid for floats)
+fn cpr_mod(a: f64, b: f64) -> f64 {
+    a.rem_euclid(b)
+}
+
+/// Global decoding from an even and an odd frame (should be less than 10 s apart).
+/// Uses the newer of the two frames for the final position.
+pub fn cpr_global(even: &CprFrame, odd: &CprFrame) -> Option<(f64, f64)> {
+    const CPR_MAX: f64 = 131072.0; // 2^17
+
+    let lat_e = even.lat as f64 / CPR_MAX;
+    let lon_e = even.lon as f64 / CPR_MAX;
+    let lat_o = odd.lat as f64 / CPR_MAX;
+    let lon_o = odd.lon as f64 / CPR_MAX;
+
+    let d_lat_even = 360.0 / (4.0 * NZ);
+    let d_lat_odd = 360.0 / (4.0 * NZ - 1.0);
+
+    let j = (59.0 * lat_e - 60.0 * lat_o + 0.5).floor();
+
+    let mut lat_even = d_lat_even * (cpr_mod(j, 60.0) + lat_e);
+    let mut lat_odd = d_lat_odd * (cpr_mod(j, 59.0) + lat_o);
+    if lat_even >= 270.0 { lat_even -= 360.0; }
+    if lat_odd >= 270.0 { lat_odd -= 360.0; }
+
+    // Both frames have to be in the same longitude zone, otherwise the pair is useless
+    if cpr_nl(lat_even) != cpr_nl(lat_odd) {
+        return None;
+    }
+
+    let (lat, lon) = if even.time >= odd.time {
+        let nl = cpr_nl(lat_even);
+        let ni = nl.max(1.0);
+        let m = (lon_e * (nl - 1.0) - lon_o * nl + 0.5).floor();
+        (lat_even, (360.0 / ni) * (cpr_mod(m, ni) + lon_e))
+    } else {
+        let nl = cpr_nl(lat_odd);
+        let ni = (nl - 1.0).max(1.0);
+        let m = (lon_e * (nl - 1.0) - lon_o * nl + 0.5).floor();
+        (lat_odd, (360.0 / ni) * (cpr_mod(m, ni) + lon_o))
+    };
+
+    let lon = if lon >= 180.0 { lon - 360.0 } else { lon };
+    Some((lat, lon))
+}
+
+/// Local decoding of a single frame against a reference position
+/// (the receiver or the last known position). Only valid within ~180 nm of the reference.
+pub fn cpr_local(frame: &CprFrame, ref_lat: f64, ref_lon: f64) -> (f64, f64) {
+    const CPR_MAX: f64 = 131072.0;
+
+    let i = if frame.odd { 1.0 } else { 0.0 };
+    let lat_cpr = frame.lat as f64 / CPR_MAX;
+    let lon_cpr = frame.lon as f64 / CPR_MAX;
+
+    let d_lat = 360.0 / (4.0 * NZ - i);
+    let j = (ref_lat / d_lat).floor() + (cpr_mod(ref_lat, d_lat) / d_lat - lat_cpr + 0.5).floor();
+    let lat = d_lat * (j + lat_cpr);
+
+    let d_lon = 360.0 / (cpr_nl(lat) - i).max(1.0);
+    let m = (ref_lon / d_lon).floor() + (cpr_mod(ref_lon, d_lon) / d_lon - lon_cpr + 0.5).floor();
+    let lon = d_lon * (m + lon_cpr);
+
+    (lat, lon)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Example frames from "The 1090MHz Riddle"
+    const POSITION_EVEN: &str = "8D40621D58C382D690C8AC2863A7";
+    const POSITION_ODD: &str = "8D40621D58C386435CC412692AD6";
+    const VELOCITY: &str = "8D485020994409940838175B284F";
+    const IDENTIFICATION: &str = "8D4840D6202CC371C32CE0576098";
+
+    fn bytes(hex: &str) -> Vec<u8> {
+        (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap()).collect()
+    }
+
+    fn cpr(hex: &str, time: f64) -> (Option<f64>, CprFrame) {
+        match decode(&bytes(hex), time).unwrap().message {
+            ModeS::AirbornePosition { alt, cpr, .. } => (alt, cpr),
+            other => panic!("expected a position, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn crc_of_a_valid_frame_matches_its_parity() {
+        let msg = bytes(VELOCITY);
+        assert_eq!(crc24(&msg[..11]), parity_field(&msg));
+    }
+
+    #[test]
+    fn frame_with_a_flipped_bit_is_dropped() {
+        let mut msg = bytes(VELOCITY);
+        msg[6] ^= 0x01;
+        assert!(decode(&msg, 0.0).is_none());
+    }
+
+    #[test]
+    fn wrong_length_is_dropped() {
+        assert!(decode(&bytes(VELOCITY)[..10], 0.0).is_none());
+    }
+
+    #[test]
+    fn identification() {
+        let decoded = decode(&bytes(IDENTIFICATION), 0.0).unwrap();
+        assert_eq!(decoded.icao, "4840d6");
+        assert_eq!(decoded.source_type, "adsb_icao");
+        match decoded.message {
+            ModeS::Identification { callsign, category } => {
+                assert_eq!(callsign, "KLM1023");
+                assert_eq!(category, "A0");
+            }
+            other => panic!("expected an identification, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn global_position_from_an_even_odd_pair() {
+        let (alt, even) = cpr(POSITION_EVEN, 1.0);
+        let (_, odd) = cpr(POSITION_ODD, 0.0);
+        assert_eq!(alt, Some(38000.0));
+        assert!(!even.odd && odd.odd);
+
+        let (lat, lon) = cpr_global(&even, &odd).unwrap();
+        assert!((lat - 52.2572).abs() < 0.0001, "lat {}", lat);
+        assert!((lon - 3.9194).abs() < 0.0001, "lon {}", lon);
+    }
+
+    #[test]
+    fn global_position_uses_the_newer_frame() {
+        let (_, even) = cpr(POSITION_EVEN, 0.0);
+        let (_, odd) = cpr(POSITION_ODD, 1.0);
+
+        // The odd frame was sent a bit later, so the position moves a little
+        let (lat, lon) = cpr_global(&even, &odd).unwrap();
+        assert!((lat - 52.2658).abs() < 0.0001, "lat {}", lat);
+        assert!((lon - 3.9389).abs() < 0.0001, "lon {}", lon);
+    }
+
+    #[test]
+    fn local_position_against_a_reference() {
+        let (_, even) = cpr(POSITION_EVEN, 0.0);
+        let (lat, lon) = cpr_local(&even, 52.258, 3.918);
+        assert!((lat - 52.2572).abs() < 0.0001, "lat {}", lat);
+        assert!((lon - 3.9194).abs() < 0.0001, "lon {}", lon);
+    }
+
+    #[test]
+    fn velocity_over_ground() {
+        let decoded = decode(&bytes(VELOCITY), 0.0).unwrap();
+        assert_eq!(decoded.icao, "485020");
+        match decoded.message {
+            ModeS::Velocity { ground_speed, track, vertical_rate, baro_rate, .. } => {
+                assert!((ground_speed.unwrap() - 159.2).abs() < 0.1);
+                assert!((track.unwrap() - 182.9).abs() < 0.1);
+                assert_eq!(vertical_rate, Some(-832.0));
+                assert!(!baro_rate); // GNSS rate
+            }
+            other => panic!("expected a velocity, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn altitude_code_in_25_ft_steps() {
+        assert_eq!(decode_ac13(0x1838), Some(38000.0));
+    }
+
+    #[test]
+    fn altitude_code_in_gillham() {
+        assert_eq!(decode_ac13(0x400), Some(-1000.0));
+        assert_eq!(decode_ac13(0x422), Some(2000.0));
+    }
+
+    #[test]
+    fn altitude_code_invalid_or_metric() {
+        assert_eq!(decode_ac13(0), None);
+        assert_eq!(decode_ac13(0x1838 | 0x40), None); // M bit
+        assert_eq!(decode_ac13(0x20), None); // 100 ft part is 0
+    }
+
+    #[test]
+    fn identity_code() {
+        assert_eq!(decode_id13(0xAAA), "7700");
+        assert_eq!(decode_id13(0), "0000");
+    }
+
+    #[test]
+    fn identity_reply_recovers_the_address_from_the_parity() {
+        // DF5 with squawk 7700, the parity is the CRC xor'ed with the address
+        let mut msg = vec![5 << 3, 0x00, 0x0A, 0xAA];
+        let parity = crc24(&msg) ^ 0x3C6586;
+        msg.extend_from_slice(&[(parity >> 16) as u8, (parity >> 8) as u8, parity as u8]);
+
+        let decoded = decode(&msg, 0.0).unwrap();
+        assert_eq!(decoded.icao, "3c6586");
+        assert_eq!(decoded.source_type, "mode_s");
+        match decoded.message {
+            ModeS::Identity { squawk } => assert_eq!(squawk, "7700"),
+            other => panic!("expected an identity reply, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,284 @@
+use std::fmt;
+use crate::geo;
+use crate::tracks::{Track, TrackPoint};
+
+/// How long an aircraft has to stay in one area before it counts as a pattern (minutes)
+const MIN_STATION_MINUTES: f64 = 15.0;
+
+/// Largest area still counted as "on station" (half the diagonal of its bounding box, nm).
+/// AWACS and tanker racetracks can have legs of 80nm and more.
+const MAX_PATTERN_RADIUS_NM: f64 = 60.0;
+
+/// A racetrack is this much longer than wide, an orbit is about round
+const RACETRACK_ELONGATION: f64 = 1.6;
+
+/// Loitering means crossing the area a few times, not flying through it once (path / diameter)
+const LOITER_CROSSINGS: f64 = 3.0;
+
+/// Share of all turning that has to go the same way for a circuit (random wandering turns both ways)
+const MIN_TURN_CONSISTENCY: f64 = 0.7;
+
+/// Below this (kts) the aircraft is taxiing or parked, not flying a pattern
+const MIN_PATTERN_SPEED: f64 = 40.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PatternKind {
+    /// Circling around one point
+    Orbit,
+    /// Two long legs joined by turns (AWACS, tankers, holdings)
+    Racetrack,
+    /// Staying in one area without a regular shape
+    Loiter,
+}
+
+impl fmt::Display for PatternKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PatternKind::Orbit => write!(f, "ORBIT"),
+            PatternKind::Racetrack => write!(f, "RACETRACK"),
+            PatternKind::Loiter => write!(f, "LOITER"),
+        }
+    }
+}
+
+/// A pattern found in the recent track of an aircraft.
+#[derive(Debug, Clone, Copy)]
+pub struct Pattern {
+    pub kind: PatternKind,
+    pub center: (f64, f64), // lat, lon
+    pub radius_nm: f64, // furthest point from the center
+    pub minutes: f64, // time on station
+    pub turn: f64, // summed heading change in degrees (negative = left turns)
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {:.0}min r={:.0}nm", self.kind, self.minutes, self.radius_nm)
+    }
+}
+
+/// Looks for orbits, racetracks and loitering at the end of a track:
+/// the newest points that stay inside one bounded area for long enough.
+pub fn detect(track: &Track) -> Option<Pattern> {
+    let points: Vec<&TrackPoint> = track.points.iter().filter(|p| p.position().is_some()).collect();
+    let newest = *points.last()?;
+
+    // On the ground (alt 0): taxiing around an airport is no pattern
+    if newest.alt.is_some_and(|alt| alt <= 0.0) {
+        return None;
+    }
+
+    // Walk back from the newest point as long as everything fits into the area
+    let (lat, lon) = newest.position()?;
+    let (mut min_lat, mut max_lat, mut min_lon, mut max_lon) = (lat, lat, lon, lon);
+    let mut start = points.len() - 1;
+
+    for (i, p) in points.iter().enumerate().rev() {
+        let (lat, lon) = p.position()?;
+        let bounds = (min_lat.min(lat), max_lat.max(lat), min_lon.min(lon), max_lon.max(lon));
+        if half_diagonal_nm(bounds) > MAX_PATTERN_RADIUS_NM {
+            break;
+        }
+        (min_lat, max_lat, min_lon, max_lon) = bounds;
+        start = i;
+    }
+
+    let window = &points[start..];
+    let minutes = (newest.time - window[0].time) / 60.0;
+    if minutes < MIN_STATION_MINUTES {
+        return None;
+    }
+
+    let speeds: Vec<f64> = window.iter().filter_map(|p| p.speed).collect();
+    if !speeds.is_empty() && speeds.iter().sum::<f64>() / (speeds.len() as f64) < MIN_PATTERN_SPEED {
+        return None;
+    }
+
+    let center = ((min_lat + max_lat) / 2.0, (min_lon + max_lon) / 2.0);
+    let positions: Vec<(f64, f64)> = window.iter().filter_map(|p| p.position()).collect();
+
+    let radius_nm = positions.iter()
+        .map(|&(lat, lon)| geo::distance_nm(center.0, center.1, lat, lon))
+        .fold(0.0, f64::max);
+    let path_nm: f64 = positions.windows(2)
+        .map(|w| geo::distance_nm(w[0].0, w[0].1, w[1].0, w[1].1))
+        .sum();
+    let (turn, turned) = heading_change(window);
+
+    let kind = if turn.abs() >= 360.0 && turn.abs() >= MIN_TURN_CONSISTENCY * turned {
+        if elongation(&positions, center) >= RACETRACK_ELONGATION { PatternKind::Racetrack } else { PatternKind::Orbit }
+    } else if path_nm >= LOITER_CROSSINGS * 2.0 * radius_nm.max(1.0) {
+        PatternKind::Loiter
+    } else {
+        return None;
+    };
+
+    Some(Pattern { kind, center, radius_nm, minutes, turn })
+}
+
+// Half the diagonal of a bounding box (min_lat, max_lat, min_lon, max_lon) in nm
+fn half_diagonal_nm((min_lat, max_lat, min_lon, max_lon): (f64, f64, f64, f64)) -> f64 {
+    let height = (max_lat - min_lat) * 60.0;
+    let width = (max_lon - min_lon) * 60.0 * ((min_lat + max_lat) / 2.0).to_radians().cos();
+    (height * height + width * width).sqrt() / 2.0
+}
+
+// Net turn between consecutive points (right turns positive) and all turning in either direction.
+// Uses the reported heading, or the course between the positions if there is none.
+fn heading_change(window: &[&TrackPoint]) -> (f64, f64) {
+    let mut courses = Vec::new();
+    for (i, p) in window.iter().enumerate() {
+        let flown = i.checked_sub(1)
+            .and_then(|j| window[j].position().zip(p.position()))
+            .filter(|(a, b)| a != b)
+            .map(|((lat1, lon1), (lat2, lon2))| geo::bearing(lat1, lon1, lat2, lon2));
+        if let Some(course) = p.heading.or(flown) {
+            courses.push(course);
+        }
+    }
+
+    let turns: Vec<f64> = courses.windows(2)
+        .map(|w| (w[1] - w[0] + 540.0) % 360.0 - 180.0)
+        .collect();
+    (turns.iter().sum(), turns.iter().map(|t| t.abs()).sum())
+}
+
+// How much longer than wide the cloud of positions is (1 = round), from its principal axes
+fn elongation(positions: &[(f64, f64)], center: (f64, f64)) -> f64 {
+    let scale = center.0.to_radians().cos();
+    let n = positions.len() as f64;
+
+    let (mut xx, mut yy, mut xy) = (0.0, 0.0, 0.0);
+    for &(lat, lon) in positions {
+        let x = (lon - center.1) * 60.0 * scale;
+        let y = (lat - center.0) * 60.0;
+        xx += x * x / n;
+        yy += y * y / n;
+        xy += x * y / n;
+    }
+
+    // Eigenvalues of the covariance matrix
+    let mid = (xx + yy) / 2.0;
+    let spread = (mid * mid - (xx * yy - xy * xy)).max(0.0).sqrt();
+    let (major, minor) = (mid + spread, (mid - spread).max(1e-9));
+
+    (major / minor).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::area::Coverage;
+    use crate::models::Aircraft;
+    use crate::tracks::TrackStore;
+
+    const CENTER: (f64, f64) = (52.0, 4.0);
+
+    // Feeds a flight into a track store, one scan every 10 seconds.
+    // `path` gives the position (nm east and north of CENTER) and the heading at a time in seconds.
+    fn fly(minutes: f64, gs: f64, alt: f64, path: impl Fn(f64) -> (f64, f64, f64)) -> Option<Pattern> {
+        let mut store = TrackStore::new(3600.0);
+        let mut t = 0.0;
+        while t <= minutes * 60.0 {
+            let (east, north, heading) = path(t);
+            let ac = Aircraft {
+                icao: "abc123".to_string(),
+                lat: Some(CENTER.0 + north / 60.0),
+                lon: Some(CENTER.1 + east / 60.0 / CENTER.0.to_radians().cos()),
+                alt_baro: Some(alt),
+                ground_speed: Some(gs),
+                track: Some(heading),
+                source_type: "adsb_icao".to_string(),
+                ..Default::default()
+            };
+            store.update(&[ac], t, &Coverage::All);
+            t += 10.0;
+        }
+        detect(store.get("abc123").unwrap())
+    }
+
+    // Clockwise circle of `radius` nm around CENTER
+    fn circle(gs: f64, radius: f64) -> impl Fn(f64) -> (f64, f64, f64) {
+        move |t| {
+            let angle = gs * t / 3600.0 / radius; // radians flown
+            (radius * angle.sin(), radius * angle.cos(), (angle.to_degrees() + 90.0) % 360.0)
+        }
+    }
+
+    #[test]
+    fn orbit() {
+        // 10nm circle at 250 kts is a lap every 15 minutes
+        let pattern = fly(30.0, 250.0, 20000.0, circle(250.0, 10.0)).unwrap();
+        assert_eq!(pattern.kind, PatternKind::Orbit);
+        assert!((pattern.center.0 - CENTER.0).abs() < 0.01 && (pattern.center.1 - CENTER.1).abs() < 0.01);
+        assert!((pattern.radius_nm - 10.0).abs() < 1.0, "radius {}", pattern.radius_nm);
+        assert!(pattern.turn > 0.0, "clockwise is right turns");
+        assert!(pattern.minutes >= 29.0);
+    }
+
+    #[test]
+    fn racetrack() {
+        // 40nm legs north and south, joined by half circles of 5nm
+        const LEG: f64 = 40.0;
+        const TURN: f64 = 5.0;
+        let lap = 2.0 * LEG + 2.0 * std::f64::consts::PI * TURN;
+        let path = |t: f64| {
+            let d = (400.0 * t / 3600.0) % lap;
+            let half_circle = std::f64::consts::PI * TURN;
+            if d < LEG {
+                (0.0, d, 0.0)
+            } else if d < LEG + half_circle {
+                let a = (d - LEG) / TURN;
+                (TURN - TURN * a.cos(), LEG + TURN * a.sin(), a.to_degrees())
+            } else if d < 2.0 * LEG + half_circle {
+                (2.0 * TURN, LEG - (d - LEG - half_circle), 180.0)
+            } else {
+                let a = (d - 2.0 * LEG - half_circle) / TURN;
+                (TURN + TURN * a.cos(), -TURN * a.sin(), 180.0 + a.to_degrees())
+            }
+        };
+
+        let pattern = fly(40.0, 400.0, 30000.0, path).unwrap();
+        assert_eq!(pattern.kind, PatternKind::Racetrack);
+        assert!(pattern.turn >= 360.0);
+    }
+
+    #[test]
+    fn figure_eight_is_loitering() {
+        // Two 3nm circles, one turning right and one left, so the turns cancel out
+        const R: f64 = 3.0;
+        let path = |t: f64| {
+            let d = (200.0 * t / 3600.0) % (4.0 * std::f64::consts::PI * R);
+            let a = d / R;
+            let (east, north, d_east, d_north) = if a < 2.0 * std::f64::consts::PI {
+                (-R + R * a.cos(), -R * a.sin(), -a.sin(), -a.cos())
+            } else {
+                let b = a - 2.0 * std::f64::consts::PI;
+                (R - R * b.cos(), -R * b.sin(), b.sin(), -b.cos())
+            };
+            (east, north, (d_east.atan2(d_north).to_degrees() + 360.0) % 360.0)
+        };
+        let pattern = fly(30.0, 200.0, 5000.0, path).unwrap();
+        assert_eq!(pattern.kind, PatternKind::Loiter);
+    }
+
+    #[test]
+    fn straight_line_is_no_pattern() {
+        assert!(fly(60.0, 300.0, 35000.0, |t| (0.0, 300.0 * t / 3600.0, 0.0)).is_none());
+        // slow enough to stay inside the area, but still just passing through
+        assert!(fly(60.0, 100.0, 3000.0, |t| (0.0, 100.0 * t / 3600.0, 0.0)).is_none());
+    }
+
+    #[test]
+    fn too_short_is_no_pattern() {
+        assert!(fly(10.0, 250.0, 20000.0, circle(250.0, 10.0)).is_none());
+    }
+
+    #[test]
+    fn taxiing_is_no_pattern() {
+        // Round and round the airport at 15 kts
+        assert!(fly(30.0, 15.0, 100.0, circle(15.0, 0.5)).is_none());
+        // On the ground: alt 0, whatever the speed says
+        assert!(fly(30.0, 250.0, 0.0, circle(250.0, 10.0)).is_none());
+    }
+}
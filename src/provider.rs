@@ -0,0 +1,516 @@
+use std::error::Error;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use clap::ValueEnum;
+use reqwest::header::RETRY_AFTER;
+use reqwest::{Client, RequestBuilder};
+use serde::Deserialize;
+use serde_json::Value;
+use crate::health::{Budget, Health};
+use crate::models::{Aircraft, AirplanesLiveResponse};
+use crate::query::Query;
+
+/// The live feeds we know how to talk to.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum ProviderKind {
+    /// api.airplanes.live (default, no key needed)
+    AirplanesLive,
+    /// api.adsb.lol (no key needed)
+    AdsbLol,
+    /// ADSBExchange v2 or any API using the same paths (adsb.fi, self-hosted mirrors)
+    Adsbx,
+    /// OpenSky Network REST API (state vectors)
+    Opensky,
+}
+
+/// A source of live aircraft positions.
+/// Every provider maps its own schema into the common `Aircraft` model,
+/// so the rest of the tool doesn't care where the data came from.
+pub trait FeedProvider: Send + Sync {
+    /// Short name for the scan header
+    fn name(&self) -> &str;
+
+    /// Builds the request for a query, or None if the feed can't answer it
+    fn request(&self, client: &Client, query: &Query) -> Option<RequestBuilder>;
+
+    /// Turns the raw response body into aircraft
+    fn parse(&self, body: &str) -> Result<Vec<Aircraft>, Box<dyn Error + Send + Sync>>;
+}
+
+/// Why a scan didn't produce any aircraft.
+#[derive(Debug)]
+pub enum FetchError {
+    Connection(reqwest::Error),
+    /// The feed answered, but not with 2xx (`retry_after` in seconds, if it told us)
+    Status { code: u16, retry_after: Option<u64> },
+    File(std::io::Error),
+    Json(Box<dyn Error + Send + Sync>),
+    Unsupported(String),
+    /// The feed told us to come back later (Retry-After) and that time isn't up yet
+    Paused { secs: u64 },
+}
+
+impl fmt::Display for FetchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FetchError::Connection(e) => write!(f, "Connection Error: {}", e),
+            FetchError::Status { code, retry_after: Some(secs) } => write!(f, "HTTP Error: {} (retry after {}s)", code, secs),
+            FetchError::Status { code, retry_after: None } => write!(f, "HTTP Error: {}", code),
+            FetchError::File(e) => write!(f, "File Error: {}", e),
+            FetchError::Json(e) => write!(f, "JSON Error: {}", e),
+            FetchError::Unsupported(msg) => write!(f, "Feed Error: {}", msg),
+            FetchError::Paused { secs } => write!(f, "Feed Error: asked us to wait another {}s", secs),
+        }
+    }
+}
+
+/// Sends the query to the provider once and returns the raw body
+/// (so it can be recorded before parsing).
+pub async fn fetch(provider: &dyn FeedProvider, client: &Client, query: &Query) -> Result<String, FetchError> {
+    let Some(request) = provider.request(client, query) else {
+        return Err(FetchError::Unsupported(format!("{} can't answer '{}'", provider.name(), query.path())));
+    };
+
+    let resp = request.send().await.map_err(FetchError::Connection)?;
+    read_body(resp).await
+}
+
+/// Returns the body of a 2xx response. 4xx/5xx are errors, not empty scans
+/// (otherwise an error page would show up as a "JSON Error").
+pub async fn read_body(resp: reqwest::Response) -> Result<String, FetchError> {
+    let status = resp.status();
+    if !status.is_success() {
+        let retry_after = resp.headers().get(RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| parse_retry_after(v, chrono::Utc::now()));
+        return Err(FetchError::Status { code: status.as_u16(), retry_after });
+    }
+
+    resp.text().await.map_err(FetchError::Connection)
+}
+
+// Retry-After is either seconds or an HTTP date
+fn parse_retry_after(value: &str, now: chrono::DateTime<chrono::Utc>) -> Option<u64> {
+    if let Ok(secs) = value.trim().parse::<u64>() {
+        return Some(secs);
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value.trim()).ok()?;
+    Some((date.timestamp() - now.timestamp()).max(0) as u64)
+}
+
+/// Retries of one request after a 429, a 5xx or a connection problem
+const MAX_RETRIES: u32 = 3;
+
+/// How long to wait between retries.
+#[derive(Debug, Clone, Copy)]
+pub struct Backoff {
+    /// First step, doubled for every retry
+    pub base: Duration,
+    /// We don't wait longer than this for one retry (a longer Retry-After means: try the fallback)
+    pub max: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self { base: Duration::from_secs(1), max: Duration::from_secs(30) }
+    }
+}
+
+impl Backoff {
+    // Exponential backoff with jitter, so several clients don't come back at the same moment
+    fn delay(&self, attempt: u32) -> Duration {
+        let backoff = self.base.saturating_mul(2u32.saturating_pow(attempt)).min(self.max);
+        backoff.mul_f64(0.5 + fastrand::f64() * 0.5)
+    }
+
+    // How long to wait before the next try, or None if retrying makes no sense
+    fn retry_delay(&self, error: &FetchError, attempt: u32) -> Option<Duration> {
+        if attempt >= MAX_RETRIES {
+            return None;
+        }
+
+        let retry_after = match error {
+            FetchError::Status { code, retry_after } if *code == 429 || *code >= 500 => *retry_after,
+            FetchError::Connection(_) => None,
+            _ => return None, // other 4xx won't get better by asking again
+        };
+
+        // The feed told us how long to wait: do that (if it's not ages)
+        if let Some(secs) = retry_after {
+            let delay = Duration::from_secs(secs);
+            return (delay <= self.max).then(|| delay + Duration::from_millis(fastrand::u64(0..500)));
+        }
+
+        Some(self.delay(attempt))
+    }
+}
+
+/// A provider plus the bookkeeping around it: request budget, retries and health.
+pub struct Feed {
+    pub kind: ProviderKind,
+    pub provider: Box<dyn FeedProvider>,
+    health: Mutex<Health>,
+    budget: Mutex<Budget>,
+    backoff: Backoff,
+    /// No requests before this (from the last Retry-After)
+    not_before: Mutex<Option<Instant>>,
+}
+
+impl Feed {
+    pub fn new(kind: ProviderKind, provider: Box<dyn FeedProvider>, requests_per_minute: usize) -> Self {
+        Self {
+            kind,
+            provider,
+            health: Mutex::new(Health::default()),
+            budget: Mutex::new(Budget::new(requests_per_minute)),
+            backoff: Backoff::default(),
+            not_before: Mutex::new(None),
+        }
+    }
+
+    /// Replaces the default backoff (tests use a short one)
+    pub fn with_backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        self.provider.name()
+    }
+
+    pub fn is_down(&self) -> bool {
+        self.health.lock().unwrap().is_down()
+    }
+
+    /// How long the feed still wants us to wait, if it sent a Retry-After
+    pub fn paused_for(&self) -> Option<Duration> {
+        self.paused_for_at(Instant::now())
+    }
+
+    fn paused_for_at(&self, now: Instant) -> Option<Duration> {
+        let not_before = (*self.not_before.lock().unwrap())?;
+        (not_before > now).then(|| not_before - now)
+    }
+
+    // Remembers a Retry-After, so the next scans (and the other tiles) leave the feed alone too
+    fn note_retry_after(&self, error: &FetchError, now: Instant) {
+        if let FetchError::Status { retry_after: Some(secs), .. } = error {
+            let until = now + Duration::from_secs(*secs);
+            let mut not_before = self.not_before.lock().unwrap();
+            *not_before = Some(not_before.map_or(until, |t| t.max(until)));
+        }
+    }
+
+    /// Health summary for the scan header
+    pub fn health(&self) -> String {
+        self.health.lock().unwrap().to_string()
+    }
+
+    /// Like `fetch`, but stays within the budget and retries with exponential backoff and jitter.
+    /// The call counts once for the health, however many retries it took.
+    pub async fn fetch(&self, client: &Client, query: &Query) -> Result<String, FetchError> {
+        let mut attempt = 0;
+        loop {
+            // Wait out a Retry-After, unless it's too long: then the fallback can answer instead
+            if let Some(wait) = self.paused_for() {
+                if wait > self.backoff.max {
+                    return Err(FetchError::Paused { secs: wait.as_secs_f64().ceil() as u64 });
+                }
+                tokio::time::sleep(wait).await;
+            }
+
+            // Wait for a free slot in the budget
+            loop {
+                let wait = self.budget.lock().unwrap().reserve();
+                match wait {
+                    Some(delay) => tokio::time::sleep(delay).await,
+                    None => break,
+                }
+            }
+
+            let started = Instant::now();
+            match fetch(self.provider.as_ref(), client, query).await {
+                Ok(body) => {
+                    self.health.lock().unwrap().record_success(started.elapsed());
+                    return Ok(body);
+                }
+                // Not the feed's fault
+                Err(e @ FetchError::Unsupported(_)) => return Err(e),
+                Err(e) => {
+                    self.note_retry_after(&e, Instant::now());
+                    let Some(delay) = self.backoff.retry_delay(&e, attempt) else {
+                        self.health.lock().unwrap().record_failure();
+                        return Err(e);
+                    };
+                    eprintln!("{} from {} (retry {}/{} in {:.1}s)", e, self.name(), attempt + 1, MAX_RETRIES, delay.as_secs_f64());
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+            }
+        }
+    }
+}
+
+/// Creates the provider selected on the command line.
+pub fn build_provider(kind: ProviderKind, base_url: Option<String>, api_key: Option<String>) -> Box<dyn FeedProvider> {
+    match kind {
+        ProviderKind::AirplanesLive => Box::new(AirplanesLive {
+            base_url: base_url.unwrap_or("https://api.airplanes.live".to_string()),
+        }),
+        ProviderKind::AdsbLol => Box::new(AdsbLol {
+            base_url: base_url.unwrap_or("https://api.adsb.lol".to_string()),
+        }),
+        ProviderKind::Adsbx => Box::new(AdsbExchange {
+            base_url: base_url.unwrap_or("https://adsbexchange.com/api/aircraft".to_string()),
+            api_key,
+        }),
+        ProviderKind::Opensky => Box::new(OpenSky {
+            base_url: base_url.unwrap_or("https://opensky-network.org".to_string()),
+            api_key,
+        }),
+    }
+}
+
+// airplanes.live, adsb.lol, ADSBx and readsb's own aircraft.json all speak the readsb JSON dialect:
+pub fn parse_readsb(body: &str) -> Result<Vec<Aircraft>, Box<dyn Error + Send + Sync>> {
+    let data: AirplanesLiveResponse = serde_json::from_str(body)?;
+    Ok(data.ac.unwrap_or_default())
+}
+
+pub struct AirplanesLive {
+    base_url: String,
+}
+
+impl FeedProvider for AirplanesLive {
+    fn name(&self) -> &str {
+        "airplanes.live"
+    }
+
+    fn request(&self, client: &Client, query: &Query) -> Option<RequestBuilder> {
+        Some(client.get(format!("{}/v2/{}", self.base_url, query.path())))
+    }
+
+    fn parse(&self, body: &str) -> Result<Vec<Aircraft>, Box<dyn Error + Send + Sync>> {
+        parse_readsb(body)
+    }
+}
+
+pub struct AdsbLol {
+    base_url: String,
+}
+
+impl FeedProvider for AdsbLol {
+    fn name(&self) -> &str {
+        "adsb.lol"
+    }
+
+    fn request(&self, client: &Client, query: &Query) -> Option<RequestBuilder> {
+        // Same paths as airplanes.live, only the point search is spelled differently
+        let path = match query {
+            Query::Point { lat, lon, radius } => format!("lat/{}/lon/{}/dist/{}", lat, lon, radius),
+            _ => query.path(),
+        };
+        Some(client.get(format!("{}/v2/{}", self.base_url, path)))
+    }
+
+    fn parse(&self, body: &str) -> Result<Vec<Aircraft>, Box<dyn Error + Send + Sync>> {
+        parse_readsb(body)
+    }
+}
+
+pub struct AdsbExchange {
+    base_url: String,
+    api_key: Option<String>,
+}
+
+impl FeedProvider for AdsbExchange {
+    fn name(&self) -> &str {
+        "adsbexchange"
+    }
+
+    fn request(&self, client: &Client, query: &Query) -> Option<RequestBuilder> {
+        let path = match query {
+            Query::Point { lat, lon, radius } => format!("lat/{}/lon/{}/dist/{}", lat, lon, radius),
+            Query::Mil => "mil".to_string(),
+            Query::Hex(hex) => format!("hex/{}", hex),
+            Query::Callsign(cs) => format!("callsign/{}", cs),
+            Query::Reg(reg) => format!("registration/{}", reg),
+            Query::Type(t) => format!("type/{}", t),
+            Query::Squawk(code) => format!("sqk/{}", code),
+            // Areas are split into point queries before they get here
+            Query::Area(_) => return None,
+        };
+        let req = client.get(format!("{}/v2/{}/", self.base_url, path));
+
+        // Mirrors usually don't need a key, the official API does:
+        Some(match &self.api_key {
+            Some(key) => req.header("api-auth", key),
+            None => req,
+        })
+    }
+
+    fn parse(&self, body: &str) -> Result<Vec<Aircraft>, Box<dyn Error + Send + Sync>> {
+        parse_readsb(body)
+    }
+}
+
+pub struct OpenSky {
+    base_url: String,
+    api_key: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenSkyResponse {
+    // OpenSky sends state vectors as plain arrays, not objects
+    states: Option<Vec<Vec<Value>>>,
+}
+
+impl FeedProvider for OpenSky {
+    fn name(&self) -> &str {
+        "opensky"
+    }
+
+    fn request(&self, client: &Client, query: &Query) -> Option<RequestBuilder> {
+        let url = match query {
+            Query::Point { lat, lon, radius } => {
+                // OpenSky only knows bounding boxes, so put a box around the circle (1 nm = 1 arc minute)
+                let d_lat = radius / 60.0;
+                let d_lon = radius / (60.0 * lat.to_radians().cos().max(0.01));
+
+                format!(
+                    "{}/api/states/all?lamin={}&lomin={}&lamax={}&lomax={}",
+                    self.base_url,
+                    (lat - d_lat).max(-90.0),
+                    (lon - d_lon).max(-180.0),
+                    (lat + d_lat).min(90.0),
+                    (lon + d_lon).min(180.0)
+                )
+            }
+            Query::Hex(hex) => {
+                let params: Vec<String> = hex.split(',').map(|h| format!("icao24={}", h.trim().to_lowercase())).collect();
+                format!("{}/api/states/all?{}", self.base_url, params.join("&"))
+            }
+            // State vectors have no type, registration or military flag
+            _ => return None,
+        };
+
+        let req = client.get(url);
+        Some(match &self.api_key {
+            Some(token) => req.bearer_auth(token),
+            None => req,
+        })
+    }
+
+    fn parse(&self, body: &str) -> Result<Vec<Aircraft>, Box<dyn Error + Send + Sync>> {
+        let data: OpenSkyResponse = serde_json::from_str(body)?;
+
+        Ok(data.states.unwrap_or_default()
+            .iter()
+            .filter_map(|s| opensky_to_aircraft(s))
+            .collect())
+    }
+}
+
+/// Maps one OpenSky state vector (see their REST docs for the index layout) to our model.
+/// OpenSky uses meters and m/s, we use feet and knots.
+fn opensky_to_aircraft(state: &[Value]) -> Option<Aircraft> {
+    const M_TO_FT: f64 = 3.28084;
+    const MS_TO_KT: f64 = 1.94384;
+    const MS_TO_FPM: f64 = 196.850;
+
+    let icao = state.first()?.as_str()?.to_lowercase();
+    let num = |i: usize| state.get(i).and_then(|v| v.as_f64());
+    let on_ground = state.get(8).and_then(|v| v.as_bool()).unwrap_or(false);
+
+    let source_type = match state.get(16).and_then(|v| v.as_u64()) {
+        Some(2) => "mlat",
+        Some(1) => "asterix",
+        Some(3) => "flarm",
+        _ => "adsb_icao",
+    };
+
+    Some(Aircraft {
+        icao,
+        callsign: state.get(1).and_then(|v| v.as_str()).map(|s| s.to_string()),
+        squawk: state.get(14).and_then(|v| v.as_str()).map(|s| s.to_string()),
+        ground_speed: num(9).map(|v| v * MS_TO_KT),
+        track: num(10),
+        baro_rate: num(11).map(|v| v * MS_TO_FPM),
+        spi: state.get(15).and_then(|v| v.as_bool()),
+        alt_baro: if on_ground { Some(0.0) } else { num(7).map(|v| v * M_TO_FT) },
+        alt_geom: num(13).map(|v| v * M_TO_FT),
+        source_type: source_type.to_string(),
+        lat: num(6),
+        lon: num(5),
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(code: u16, retry_after: Option<u64>) -> FetchError {
+        FetchError::Status { code, retry_after }
+    }
+
+    #[test]
+    fn retry_after_in_seconds_or_as_a_date() {
+        let now = chrono::DateTime::parse_from_rfc3339("2015-10-21T07:28:00Z").unwrap().to_utc();
+        assert_eq!(parse_retry_after(" 120 ", now), Some(120));
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:29:00 GMT", now), Some(60));
+
+        // a date in the past means now
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:27:00 GMT", now), Some(0));
+        assert_eq!(parse_retry_after("soon", now), None);
+    }
+
+    #[test]
+    fn backoff_doubles_with_jitter_up_to_the_cap() {
+        let backoff = Backoff::default();
+        for _ in 0..100 {
+            for attempt in 0..3 {
+                let full = backoff.base * 2u32.pow(attempt);
+                let delay = backoff.delay(attempt);
+                assert!(delay >= full / 2 && delay <= full, "attempt {}: {:?}", attempt, delay);
+            }
+            assert!(backoff.delay(10) >= backoff.max / 2 && backoff.delay(10) <= backoff.max);
+            assert!(backoff.delay(40) <= backoff.max);
+        }
+    }
+
+    #[test]
+    fn only_429_and_5xx_are_retried() {
+        let backoff = Backoff::default();
+        assert!(backoff.retry_delay(&status(500, None), 0).is_some());
+        assert!(backoff.retry_delay(&status(503, None), 2).is_some());
+        assert!(backoff.retry_delay(&status(500, None), MAX_RETRIES).is_none());
+        assert!(backoff.retry_delay(&status(404, None), 0).is_none());
+        assert!(backoff.retry_delay(&FetchError::Unsupported("no".to_string()), 0).is_none());
+    }
+
+    #[test]
+    fn retry_after_is_followed_unless_it_is_too_long() {
+        let backoff = Backoff::default();
+        let delay = backoff.retry_delay(&status(429, Some(5)), 0).unwrap();
+        assert!(delay >= Duration::from_secs(5) && delay < Duration::from_millis(5500), "{:?}", delay);
+        // better to ask the fallback than to wait that long
+        assert!(backoff.retry_delay(&status(429, Some(120)), 0).is_none());
+    }
+
+    #[test]
+    fn retry_after_pauses_the_feed() {
+        let feed = Feed::new(ProviderKind::AirplanesLive, build_provider(ProviderKind::AirplanesLive, None, None), 10);
+        let start = Instant::now();
+        feed.note_retry_after(&status(500, None), start);
+        assert_eq!(feed.paused_for_at(start), None);
+
+        feed.note_retry_after(&status(429, Some(120)), start);
+        assert_eq!(feed.paused_for_at(start + Duration::from_secs(20)), Some(Duration::from_secs(100)));
+        // a shorter one later doesn't cut the pause short
+        feed.note_retry_after(&status(429, Some(5)), start + Duration::from_secs(20));
+        assert_eq!(feed.paused_for_at(start + Duration::from_secs(30)), Some(Duration::from_secs(90)));
+        assert_eq!(feed.paused_for_at(start + Duration::from_secs(120)), None);
+    }
+}
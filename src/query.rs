@@ -0,0 +1,183 @@
+use std::fmt;
+use crate::area::{self, Polygon};
+use crate::geo;
+use crate::lookups::Lookups;
+use crate::models::Aircraft;
+
+/// What we ask a feed for. Mirrors the airplanes.live v2 endpoints.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Query {
+    /// Everything around a point (radius in nm)
+    Point { lat: f64, lon: f64, radius: f64 },
+    /// All aircraft flagged as military, worldwide
+    Mil,
+    /// One or more hex codes (comma separated)
+    Hex(String),
+    Callsign(String),
+    Reg(String),
+    Type(String),
+    Squawk(String),
+    /// Everything inside a bounding box or polygon, scanned as several point queries
+    Area(Polygon),
+}
+
+impl Query {
+    /// Path below `/v2/` in the airplanes.live API (also used to store the query in captures)
+    pub fn path(&self) -> String {
+        match self {
+            Query::Point { lat, lon, radius } => format!("point/{}/{}/{}", lat, lon, radius),
+            Query::Mil => "mil".to_string(),
+            Query::Hex(hex) => format!("hex/{}", hex),
+            Query::Callsign(cs) => format!("callsign/{}", cs),
+            Query::Reg(reg) => format!("reg/{}", reg),
+            Query::Type(t) => format!("type/{}", t),
+            Query::Squawk(code) => format!("squawk/{}", code),
+            Query::Area(polygon) => format!("area/{}", area::format_polygon(polygon)),
+        }
+    }
+
+    /// Reads a path like `/v2/type/K35R` (the `/v2/` is optional) back into a query.
+    pub fn from_path(path: &str) -> Option<Query> {
+        let path = path.trim_matches('/');
+        let path = path.strip_prefix("v2/").unwrap_or(path);
+        let parts: Vec<&str> = path.split('/').collect();
+        // the HTTP client sends spaces (e.g. in a callsign) as %20
+        let arg = || parts.get(1).filter(|s| !s.is_empty()).map(|s| percent_decode(s));
+
+        match parts[0] {
+            "point" => {
+                let num = |i: usize| parts.get(i).and_then(|s| s.parse::<f64>().ok());
+                Some(Query::Point { lat: num(1)?, lon: num(2)?, radius: num(3)? })
+            }
+            "mil" => Some(Query::Mil),
+            "hex" | "icao" => arg().map(Query::Hex),
+            "callsign" => arg().map(Query::Callsign),
+            "reg" => arg().map(Query::Reg),
+            "type" => arg().map(Query::Type),
+            "squawk" | "sqk" => arg().map(Query::Squawk),
+            "area" => arg().and_then(|p| area::parse_polygon(&p).ok()).map(Query::Area),
+            _ => None,
+        }
+    }
+
+    /// Checks an aircraft against the query locally
+    /// (for sources that can't be asked directly, and for the stand-in server).
+    /// `mil` uses the same test as the checks, Beast/SBS input has no military flag.
+    pub fn matches(&self, ac: &Aircraft, lookups: &Lookups) -> bool {
+        let same = |value: &Option<String>, wanted: &str| {
+            value.as_ref().map(|v| v.trim().eq_ignore_ascii_case(wanted.trim())).unwrap_or(false)
+        };
+
+        match self {
+            Query::Point { lat, lon, radius } => match (ac.lat, ac.lon) {
+                (Some(a_lat), Some(a_lon)) => geo::distance_nm(*lat, *lon, a_lat, a_lon) <= *radius,
+                _ => false,
+            },
+            Query::Mil => ac.military(lookups),
+            Query::Hex(hex) => hex.split(',').any(|h| h.trim().eq_ignore_ascii_case(&ac.icao)),
+            Query::Callsign(cs) => same(&ac.callsign, cs),
+            Query::Reg(reg) => same(&ac.registration, reg),
+            Query::Type(t) => same(&ac.type_code, t),
+            Query::Squawk(code) => same(&ac.squawk, code),
+            Query::Area(polygon) => match (ac.lat, ac.lon) {
+                (Some(lat), Some(lon)) => area::contains(polygon, lat, lon),
+                _ => false,
+            },
+        }
+    }
+
+    /// How far inside the scanned sector a position is (nm, negative outside).
+    /// None for worldwide queries, they have no edge.
+    pub fn edge_distance(&self, lat: f64, lon: f64) -> Option<f64> {
+        match self {
+            Query::Point { lat: c_lat, lon: c_lon, radius } => Some(radius - geo::distance_nm(*c_lat, *c_lon, lat, lon)),
+            Query::Area(polygon) => {
+                let distance = area::distance_to_edge(polygon, lat, lon);
+                Some(if area::contains(polygon, lat, lon) { distance } else { -distance })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Undoes the %XX escapes of a URL path segment (invalid ones are kept as they are).
+fn percent_decode(segment: &str) -> String {
+    let bytes = segment.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| segment.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                out.push(byte);
+                i += 3;
+            }
+            None => {
+                out.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+impl fmt::Display for Query {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Query::Point { lat, lon, radius } => write!(f, "Sector: {:.4}, {:.4} | Radius: {}nm", lat, lon, radius),
+            Query::Mil => write!(f, "Query: all military (worldwide)"),
+            Query::Hex(hex) => write!(f, "Query: hex {}", hex),
+            Query::Callsign(cs) => write!(f, "Query: callsign {}", cs),
+            Query::Reg(reg) => write!(f, "Query: registration {}", reg),
+            Query::Type(t) => write!(f, "Query: type {} (worldwide)", t),
+            Query::Squawk(code) => write!(f, "Query: squawk {} (worldwide)", code),
+            Query::Area(polygon) => write!(f, "Area: {} corners, {}", polygon.len(), area::format_polygon(polygon)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(query: Query) {
+        assert_eq!(Query::from_path(&query.path()), Some(query.clone()), "path {}", query.path());
+        assert_eq!(Query::from_path(&format!("/v2/{}", query.path())), Some(query));
+    }
+
+    #[test]
+    fn every_query_survives_its_path() {
+        round_trip(Query::Point { lat: 52.5, lon: -4.25, radius: 250.0 });
+        round_trip(Query::Mil);
+        round_trip(Query::Hex("3c6586".to_string()));
+        round_trip(Query::Hex("3c6586,ae01ce,43c123".to_string()));
+        round_trip(Query::Callsign("RCH123".to_string()));
+        round_trip(Query::Callsign("DLH 4AB".to_string()));
+        round_trip(Query::Reg("N-12345".to_string()));
+        round_trip(Query::Type("K35R".to_string()));
+        round_trip(Query::Squawk("7700".to_string()));
+        round_trip(Query::Squawk("0021".to_string()));
+    }
+
+    #[test]
+    fn paths_from_the_wire_are_decoded() {
+        // what the server sees when the client escaped the space
+        assert_eq!(Query::from_path("/v2/callsign/DLH%204AB"), Some(Query::Callsign("DLH 4AB".to_string())));
+        assert_eq!(Query::from_path("/v2/icao/3c6586"), Some(Query::Hex("3c6586".to_string())));
+        assert_eq!(Query::from_path("/v2/sqk/7500"), Some(Query::Squawk("7500".to_string())));
+        // a stray % is not an escape
+        assert_eq!(Query::from_path("reg/100%"), Some(Query::Reg("100%".to_string())));
+    }
+
+    #[test]
+    fn incomplete_or_unknown_paths_are_rejected() {
+        assert_eq!(Query::from_path("/v2/point/52/4"), None);
+        assert_eq!(Query::from_path("/v2/point/52/x/250"), None);
+        assert_eq!(Query::from_path("/v2/callsign/"), None);
+        assert_eq!(Query::from_path("/v2/hex"), None);
+        assert_eq!(Query::from_path("/v2/route/abc"), None);
+    }
+}
@@ -0,0 +1,197 @@
+use std::fmt;
+use serde::Deserialize;
+use crate::models::Severity;
+use crate::tracks;
+
+/// Threat score from which a target counts as high (red row, red KML icon)
+pub const SCORE_HIGH: f64 = 50.0;
+
+/// Threat score from which a target counts as medium (yellow)
+pub const SCORE_MEDIUM: f64 = 20.0;
+
+/// Further reasons of a category that already counted add only this share of their score
+/// (MIL FLAG and MIL HEX say the same thing twice)
+const REPEAT_FACTOR: f64 = 0.5;
+
+/// What kind of finding a reason is, sets its default score.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Category {
+    /// Squawks and the transponder's emergency state
+    Emergency,
+    /// Spoofing/jamming: altitude mismatch, position jumps
+    Navigation,
+    /// Military flag, hex ranges, foreign military
+    Military,
+    /// High value types, the type asked for
+    HighValue,
+    /// Stopped transmitting or came back
+    Dark,
+    /// Orbits, formations, fast and low
+    Behavior,
+    /// On one of our watchlists
+    Watchlist,
+    /// Hidden or inconsistent identity: PIA, LADD, non-ICAO, callsign and registration changes
+    Identity,
+    /// How the aircraft is located: MLAT, source changes
+    Source,
+    #[default]
+    Other,
+}
+
+impl Category {
+    /// Score of a reason in this category, unless its rule sets one
+    pub fn weight(&self) -> f64 {
+        match self {
+            Category::Emergency => 50.0,
+            Category::Watchlist => 100.0,
+            Category::Navigation => 25.0,
+            Category::Military => 20.0,
+            Category::HighValue => 30.0,
+            Category::Dark => 30.0,
+            Category::Behavior => 15.0,
+            Category::Identity => 10.0,
+            Category::Source => 10.0,
+            Category::Other => 5.0,
+        }
+    }
+}
+
+impl fmt::Display for Category {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Category::Emergency => "emergency",
+            Category::Navigation => "navigation",
+            Category::Military => "military",
+            Category::HighValue => "high_value",
+            Category::Dark => "dark",
+            Category::Behavior => "behavior",
+            Category::Watchlist => "watchlist",
+            Category::Identity => "identity",
+            Category::Source => "source",
+            Category::Other => "other",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// What found a reason, with what it found. The text in the table is made from it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Finding {
+    /// A detection rule matched (its reason template already filled in)
+    Rule { name: String, text: String },
+    /// One of the watchlist entries
+    Watchlist { label: String },
+    /// Flagged, then stopped transmitting inside the sector
+    WentDark { secs: f64, inside_nm: f64 },
+}
+
+impl Finding {
+    /// Name of the rule, or of the check that isn't a rule
+    pub fn source(&self) -> &str {
+        match self {
+            Finding::Rule { name, .. } => name,
+            Finding::Watchlist { .. } => "watchlist",
+            Finding::WentDark { .. } => "went_dark",
+        }
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Finding::Rule { text, .. } => write!(f, "{}", text),
+            Finding::Watchlist { label } => write!(f, "WATCHLIST: {}", label),
+            Finding::WentDark { secs, inside_nm } => {
+                write!(f, "WENT DARK {} ago, {:.0}nm inside sector", tracks::format_duration(*secs), inside_nm)
+            }
+        }
+    }
+}
+
+/// Why an aircraft is of interest: one matching rule, watchlist entry or the went-dark check.
+#[derive(Debug, Clone)]
+pub struct Reason {
+    pub finding: Finding,
+    pub category: Category,
+    pub severity: Severity,
+    pub score: f64, // before the severity factor
+    pub notes: Option<String>, // more to read, e.g. from the watchlist
+}
+
+impl Reason {
+    pub fn new(finding: Finding, category: Category, severity: Severity) -> Self {
+        Self { finding, category, severity, score: category.weight(), notes: None }
+    }
+
+    /// For the table, e.g. "HVT: C17"
+    pub fn text(&self) -> String {
+        self.finding.to_string()
+    }
+
+    /// Score with the severity added in (warnings count 1.5x, emergencies 2x)
+    pub fn weighted_score(&self) -> f64 {
+        let factor = match self.severity {
+            Severity::Normal => 1.0,
+            Severity::Warning => 1.5,
+            Severity::Emergency => 2.0,
+        };
+        self.score * factor
+    }
+}
+
+/// The threat score of an aircraft: the best reason of each category counts fully, the others less.
+pub fn threat_score(reasons: &[Reason]) -> f64 {
+    let mut scores: Vec<(Category, f64)> = reasons.iter().map(|r| (r.category, r.weighted_score())).collect();
+    scores.sort_by(|a, b| a.0.cmp(&b.0).then(b.1.total_cmp(&a.1)));
+
+    let mut total = 0.0;
+    let mut previous = None;
+    for (category, score) in scores {
+        total += if previous == Some(category) { score * REPEAT_FACTOR } else { score };
+        previous = Some(category);
+    }
+    total
+}
+
+/// Highest severity of the reasons
+pub fn severity(reasons: &[Reason]) -> Severity {
+    reasons.iter().map(|r| r.severity).max().unwrap_or_default()
+}
+
+/// The reasons as one line for the table, e.g. "HVT: C17, MIL FLAG"
+pub fn describe(reasons: &[Reason]) -> String {
+    reasons.iter().map(Reason::text).collect::<Vec<_>>().join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(name: &str, category: Category, severity: Severity) -> Reason {
+        Reason::new(Finding::Rule { name: name.to_string(), text: name.to_uppercase() }, category, severity)
+    }
+
+    #[test]
+    fn text_comes_from_the_finding() {
+        let dark = Reason::new(Finding::WentDark { secs: 270.0, inside_nm: 85.2 }, Category::Dark, Severity::Warning);
+        assert_eq!(dark.text(), "WENT DARK 4m 30s ago, 85nm inside sector");
+        assert_eq!(dark.finding.source(), "went_dark");
+
+        let watch = Finding::Watchlist { label: "SAM fleet".to_string() };
+        assert_eq!((watch.to_string().as_str(), watch.source()), ("WATCHLIST: SAM fleet", "watchlist"));
+        assert_eq!(describe(&[rule("mil_flag", Category::Military, Severity::Normal), dark]), "MIL_FLAG, WENT DARK 4m 30s ago, 85nm inside sector");
+    }
+
+    #[test]
+    fn repeated_categories_count_half() {
+        let reasons = [
+            rule("mil_flag", Category::Military, Severity::Normal),
+            rule("mil_hex", Category::Military, Severity::Normal),
+            rule("nav_anomaly", Category::Navigation, Severity::Warning),
+        ];
+        // 20 + 20 * 0.5 + 25 * 1.5
+        assert_eq!(threat_score(&reasons), 67.5);
+        assert_eq!(severity(&reasons), Severity::Warning);
+    }
+}
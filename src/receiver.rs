@@ -0,0 +1,189 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use crate::geo;
+use crate::models::Aircraft;
+use crate::modes::{self, CprFrame, ModeS};
+
+/// Even and odd CPR frames further apart than this are not paired (seconds)
+const CPR_PAIR_MAX_AGE: f64 = 10.0;
+
+/// Local CPR decoding is only unambiguous within this distance of the reference (km, ~180 nm)
+const CPR_LOCAL_RANGE_KM: f64 = 333.0;
+
+/// Everything we heard from one airframe so far.
+struct Tracked {
+    aircraft: Aircraft,
+    last_seen: f64,
+    last_position: f64,
+    even: Option<CprFrame>,
+    odd: Option<CprFrame>,
+}
+
+/// Live picture built from our own receivers.
+/// Frames (or BaseStation lines) are merged into `Aircraft` records per hex, so the scan loop can treat
+/// them exactly like an API response.
+pub struct ReceiverState {
+    // receiver location, used as reference for local CPR decoding
+    reference: Option<(f64, f64)>,
+    aircraft: HashMap<String, Tracked>,
+}
+
+pub type SharedReceiver = Arc<Mutex<ReceiverState>>;
+
+impl ReceiverState {
+    pub fn new(reference: Option<(f64, f64)>) -> Self {
+        Self {
+            reference,
+            aircraft: HashMap::new(),
+        }
+    }
+
+    pub fn shared(reference: Option<(f64, f64)>) -> SharedReceiver {
+        Arc::new(Mutex::new(Self::new(reference)))
+    }
+
+    /// Decodes one raw Mode S frame and merges it into the picture.
+    pub fn apply_frame(&mut self, data: &[u8], now: f64) {
+        let Some(decoded) = modes::decode(data, now) else {
+            return;
+        };
+
+        // Altitude and identity replies have no real checksum, only trust them for known aircraft
+        match decoded.message {
+            ModeS::Altitude { alt } => {
+                if let Some(t) = self.aircraft.get_mut(&decoded.icao) {
+                    t.aircraft.alt_baro = alt.or(t.aircraft.alt_baro);
+                    t.last_seen = now;
+                }
+                return;
+            }
+            ModeS::Identity { squawk } => {
+                if let Some(t) = self.aircraft.get_mut(&decoded.icao) {
+                    t.aircraft.squawk = Some(squawk);
+                    t.last_seen = now;
+                }
+                return;
+            }
+            _ => {}
+        }
+
+        let reference = self.reference;
+        let t = self.tracked(&decoded.icao, now);
+        t.aircraft.source_type = decoded.source_type.to_string();
+
+        match decoded.message {
+            ModeS::Identification { category, callsign } => {
+                t.aircraft.category = Some(category);
+                if !callsign.is_empty() {
+                    t.aircraft.callsign = Some(callsign);
+                }
+            }
+            ModeS::SurfacePosition => {
+                t.aircraft.alt_baro = Some(0.0); // same as "ground" in the API
+            }
+            ModeS::AirbornePosition { alt, gnss, cpr } => {
+                if gnss {
+                    t.aircraft.alt_geom = alt;
+                } else {
+                    t.aircraft.alt_baro = alt;
+                }
+                t.update_position(cpr, reference);
+            }
+            ModeS::Velocity { ground_speed, track, heading, ias, tas, vertical_rate, baro_rate } => {
+                if ground_speed.is_some() {
+                    t.aircraft.ground_speed = ground_speed;
+                    t.aircraft.track = track;
+                }
+                if heading.is_some() {
+                    t.aircraft.mag_heading = heading;
+                }
+                if ias.is_some() {
+                    t.aircraft.ias = ias;
+                }
+                if tas.is_some() {
+                    t.aircraft.tas = tas;
+                }
+                if baro_rate {
+                    t.aircraft.baro_rate = vertical_rate;
+                } else {
+                    t.aircraft.geom_rate = vertical_rate;
+                }
+            }
+            ModeS::Emergency { emergency, squawk } => {
+                t.aircraft.emergency = Some(emergency.to_string());
+                t.aircraft.squawk = Some(squawk);
+            }
+            ModeS::Altitude { .. } | ModeS::Identity { .. } => {}
+        }
+    }
+
+    /// Returns the record for the hex (creating it if needed) and marks it as seen.
+    pub fn entry(&mut self, icao: &str, now: f64) -> &mut Aircraft {
+        &mut self.tracked(icao, now).aircraft
+    }
+
+    fn tracked(&mut self, icao: &str, now: f64) -> &mut Tracked {
+        let t = self.aircraft.entry(icao.to_string()).or_insert_with(|| Tracked {
+            aircraft: Aircraft {
+                icao: icao.to_string(),
+                ..Default::default()
+            },
+            last_seen: now,
+            last_position: 0.0,
+            even: None,
+            odd: None,
+        });
+        t.last_seen = now;
+        t
+    }
+
+    /// All aircraft heard within the last `max_age` seconds.
+    pub fn snapshot(&self, now: f64, max_age: f64) -> Vec<Aircraft> {
+        self.aircraft.values()
+            .filter(|t| now - t.last_seen <= max_age)
+            .map(|t| t.aircraft.clone())
+            .collect()
+    }
+
+    /// Forgets aircraft we haven't heard from in `max_age` seconds.
+    pub fn expire(&mut self, now: f64, max_age: f64) {
+        self.aircraft.retain(|_, t| now - t.last_seen <= max_age);
+    }
+}
+
+impl Tracked {
+    fn update_position(&mut self, cpr: CprFrame, reference: Option<(f64, f64)>) {
+        if cpr.odd {
+            self.odd = Some(cpr);
+        } else {
+            self.even = Some(cpr);
+        }
+
+        // 1. Global decoding if we have a fresh pair:
+        if let (Some(even), Some(odd)) = (&self.even, &self.odd)
+            && (even.time - odd.time).abs() <= CPR_PAIR_MAX_AGE
+            && let Some((lat, lon)) = modes::cpr_global(even, odd) {
+            self.set_position(lat, lon, cpr.time);
+            return;
+        }
+
+        // 2. Local decoding against our last position or the receiver:
+        let last_known = match (self.aircraft.lat, self.aircraft.lon) {
+            (Some(lat), Some(lon)) if cpr.time - self.last_position < 30.0 => Some((lat, lon)),
+            _ => None,
+        };
+
+        if let Some((ref_lat, ref_lon)) = last_known.or(reference) {
+            let (lat, lon) = modes::cpr_local(&cpr, ref_lat, ref_lon);
+            if geo::haversine_distance(ref_lat, ref_lon, lat, lon) < CPR_LOCAL_RANGE_KM {
+                self.set_position(lat, lon, cpr.time);
+            }
+        }
+    }
+
+    fn set_position(&mut self, lat: f64, lon: f64, time: f64) {
+        self.aircraft.lat = Some(lat);
+        self.aircraft.lon = Some(lon);
+        self.last_position = time;
+    }
+}
@@ -0,0 +1,190 @@
+use std::fmt;
+use clap::ValueEnum;
+use regex::Regex;
+use std::sync::LazyLock;
+use crate::lookups::Lookups;
+use crate::models::Aircraft;
+
+/// What an aircraft is probably doing.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Role {
+    /// Air-to-air refueling
+    Tanker,
+    /// Airborne early warning and control (AWACS)
+    Aew,
+    /// Reconnaissance and signals intelligence
+    Isr,
+    /// Strategic and tactical airlift
+    Airlift,
+    /// Government and VIP transport
+    Vip,
+    Fighter,
+    /// Drones
+    Uav,
+    /// Maritime patrol and anti-submarine
+    Maritime,
+    Bomber,
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Role::Tanker => "TANKER",
+            Role::Aew => "AEW&C",
+            Role::Isr => "ISR/SIGINT",
+            Role::Airlift => "AIRLIFT",
+            Role::Vip => "VIP/GOV",
+            Role::Fighter => "FIGHTER",
+            Role::Uav => "UAV",
+            Role::Maritime => "MARITIME PATROL",
+            Role::Bomber => "BOMBER",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// Callsign families that say more about the mission than the type does
+static CALLSIGNS: LazyLock<Vec<(Regex, Role)>> = LazyLock::new(|| {
+    [
+        (r"^RCH\d{1,4}[A-Z]?$", Role::Airlift), // REACH, USAF Air Mobility Command
+        (r"^FORTE\d{1,2}$", Role::Isr), // RQ-4 Global Hawk over the Black Sea
+        (r"^HOMER\d{1,2}$", Role::Maritime), // P-8 Poseidon
+        (r"^JAKE\d{1,2}$", Role::Isr), // RC-135 / EP-3
+        (r"^LAGR\d{1,3}$", Role::Tanker), // KC-135 from RAF Mildenhall
+        (r"^DUKE\d{1,2}$", Role::Isr), // US Army special electronics mission aircraft
+        (r"^NATO\d{1,2}$", Role::Aew), // NATO E-3A
+        (r"^SAM\d{1,5}$", Role::Vip), // Special Air Mission, US government
+    ]
+    .into_iter()
+    .map(|(pattern, role)| (Regex::new(pattern).expect("built-in callsign pattern"), role))
+    .collect()
+});
+
+// Type codes of one role
+const TYPES: [(&str, Role); 45] = [
+    ("K35R", Role::Tanker), ("K35E", Role::Tanker), ("K46", Role::Tanker),
+    ("E3TF", Role::Aew), ("E3CF", Role::Aew), ("E767", Role::Aew), ("E737", Role::Aew), ("E2", Role::Aew),
+    ("R135", Role::Isr), ("E8", Role::Isr), ("U2", Role::Isr), ("EP3", Role::Isr),
+    ("C17", Role::Airlift), ("C5", Role::Airlift), ("C5M", Role::Airlift), ("A400", Role::Airlift),
+    ("C130", Role::Airlift), ("C30J", Role::Airlift), ("C2", Role::Airlift),
+    ("F35", Role::Fighter), ("F16", Role::Fighter), ("F18", Role::Fighter), ("F18S", Role::Fighter),
+    ("F15", Role::Fighter), ("F22", Role::Fighter), ("EUFI", Role::Fighter), ("TORN", Role::Fighter),
+    ("RFAL", Role::Fighter), ("GRIF", Role::Fighter), ("SU27", Role::Fighter), ("SU30", Role::Fighter),
+    ("Q1", Role::Uav), ("Q4", Role::Uav), ("Q9", Role::Uav), ("HRON", Role::Uav),
+    ("P8", Role::Maritime), ("P3", Role::Maritime), ("P1", Role::Maritime), ("ATL2", Role::Maritime),
+    ("B52", Role::Bomber), ("B1", Role::Bomber), ("B2", Role::Bomber), ("TU95", Role::Bomber),
+    ("T160", Role::Bomber), ("TU22", Role::Bomber),
+];
+
+// Types that only mean something when flown by the military (civil versions are common)
+const MIL_TYPES: [(&str, Role); 14] = [
+    ("A332", Role::Tanker), ("DC10", Role::Tanker), // A330 MRTT, KC-10
+    ("A124", Role::Airlift), ("IL76", Role::Airlift),
+    ("B742", Role::Vip), ("B748", Role::Vip), ("A319", Role::Vip), ("A321", Role::Vip),
+    ("GLF5", Role::Vip), ("GLF6", Role::Vip), ("GLEX", Role::Vip), ("F900", Role::Vip),
+    ("FA7X", Role::Vip), ("CL60", Role::Vip),
+];
+
+// Words in an operator name that give the role away
+const OPERATOR_HINTS: [(&str, Role); 6] = [
+    ("government", Role::Vip),
+    ("presidential", Role::Vip),
+    ("royal flight", Role::Vip),
+    ("flugbereitschaft", Role::Vip),
+    ("executive flight", Role::Vip),
+    ("coast guard", Role::Maritime),
+];
+
+/// The probable role: callsign family first, then the type, then the operator.
+pub fn classify(ac: &Aircraft, lookups: &Lookups) -> Option<Role> {
+    let callsign = ac.callsign.as_deref().unwrap_or("").trim().to_uppercase();
+    if let Some((_, role)) = CALLSIGNS.iter().find(|(pattern, _)| pattern.is_match(&callsign)) {
+        return Some(*role);
+    }
+
+    let type_code = ac.type_code.as_deref().unwrap_or("").trim().to_uppercase();
+    if let Some((_, role)) = TYPES.iter().find(|(t, _)| *t == type_code) {
+        return Some(*role);
+    }
+    if let Some((_, role)) = MIL_TYPES.iter().find(|(t, _)| *t == type_code)
+        && ac.military(lookups) {
+        return Some(*role);
+    }
+
+    let operator = lookups.db.get(&ac.icao)
+        .and_then(|info| info.operator.clone())
+        .or_else(|| ac.airline(lookups).map(|a| a.name.clone()))?
+        .to_lowercase();
+    OPERATOR_HINTS.iter().find(|(hint, _)| operator.contains(hint)).map(|(_, role)| *role)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::AircraftInfo;
+
+    fn role(icao: &str, callsign: &str, type_code: &str, lookups: &Lookups) -> Option<Role> {
+        let ac = Aircraft {
+            icao: icao.to_string(),
+            callsign: Some(callsign.to_string()),
+            type_code: Some(type_code.to_string()),
+            ..Default::default()
+        };
+        classify(&ac, lookups)
+    }
+
+    #[test]
+    fn callsign_families() {
+        let lookups = Lookups::built_in();
+        let of = |callsign: &str| role("4840d6", callsign, "", &lookups);
+
+        assert_eq!(of("RCH1234"), Some(Role::Airlift));
+        assert_eq!(of("rch4a"), Some(Role::Airlift));
+        assert_eq!(of("FORTE11"), Some(Role::Isr));
+        assert_eq!(of("HOMER21"), Some(Role::Maritime));
+        assert_eq!(of("JAKE11"), Some(Role::Isr));
+        assert_eq!(of("LAGR223"), Some(Role::Tanker));
+        assert_eq!(of("DUKE01"), Some(Role::Isr));
+
+        // too many digits for the family
+        assert_eq!(of("RCH12345"), None);
+        assert_eq!(of("FORTE123"), None);
+    }
+
+    #[test]
+    fn callsign_comes_before_the_type() {
+        let lookups = Lookups::built_in();
+        assert_eq!(role("ae1234", "RCH123", "K35R", &lookups), Some(Role::Airlift));
+    }
+
+    #[test]
+    fn type_codes() {
+        let lookups = Lookups::built_in();
+        assert_eq!(role("4840d6", "", "K35R", &lookups), Some(Role::Tanker));
+        assert_eq!(role("4840d6", "", "e3tf", &lookups), Some(Role::Aew));
+        assert_eq!(role("4840d6", "", "Q4", &lookups), Some(Role::Uav));
+        assert_eq!(role("4840d6", "", "B52", &lookups), Some(Role::Bomber));
+        assert_eq!(role("4840d6", "", "B738", &lookups), None);
+    }
+
+    #[test]
+    fn airliner_types_need_a_military_operator() {
+        let lookups = Lookups::built_in();
+        // civil A330 vs one in the US military block
+        assert_eq!(role("4840d6", "KLM601", "A332", &lookups), None);
+        assert_eq!(role("ae1234", "", "A332", &lookups), Some(Role::Tanker));
+        assert_eq!(role("ae1234", "", "GLF5", &lookups), Some(Role::Vip));
+    }
+
+    #[test]
+    fn operator_gives_the_role_away() {
+        let mut lookups = Lookups::built_in();
+        lookups.db.insert("3c4b26".to_string(), AircraftInfo {
+            icao: "3c4b26".to_string(),
+            operator: Some("German Air Force Flugbereitschaft".to_string()),
+            registration: Some("16+01".to_string()),
+        });
+        assert_eq!(role("3c4b26", "", "A20N", &lookups), Some(Role::Vip));
+        assert_eq!(role("3c4b27", "", "A20N", &lookups), None);
+    }
+}
@@ -0,0 +1,517 @@
+use std::error::Error;
+use serde::Deserialize;
+use crate::models::Severity;
+use crate::reason::{Category, Finding, Reason};
+
+/// Rules that ship with the tool (the checks it always had)
+const DEFAULT_RULES: &str = include_str!("default_rules.toml");
+
+/// Picked up from the working directory if it exists, replaces the built-in rules
+const DEFAULT_FILE: &str = "rules.toml";
+
+/// A value a rule can look at: an aircraft field, something derived from it, or a command line setting.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Num(f64),
+    Text(String),
+    Bool(bool),
+    List(Vec<String>),
+}
+
+impl Value {
+    fn as_num(&self) -> Option<f64> {
+        match self {
+            Value::Num(n) => Some(*n),
+            Value::Text(s) => s.trim().parse().ok(),
+            _ => None,
+        }
+    }
+
+    fn as_text(&self) -> String {
+        match self {
+            Value::Num(n) => n.to_string(),
+            Value::Text(s) => s.clone(),
+            Value::Bool(b) => if *b { "yes" } else { "no" }.to_string(),
+            Value::List(items) => items.join(", "),
+        }
+    }
+
+    fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(b) => Some(*b),
+            Value::Text(s) => match s.trim().to_lowercase().as_str() {
+                "yes" | "true" | "1" => Some(true),
+                "no" | "false" | "0" => Some(false),
+                _ => None,
+            },
+            Value::Num(n) => Some(*n != 0.0),
+            Value::List(_) => None,
+        }
+    }
+
+    fn items(&self) -> Vec<String> {
+        match self {
+            Value::List(items) => items.clone(),
+            other => vec![other.as_text()],
+        }
+    }
+
+    // Numbers and flags compare as such, text exactly unless `ignore_case`
+    fn same(&self, other: &Value, ignore_case: bool) -> bool {
+        if let (Value::Bool(_), _) | (_, Value::Bool(_)) = (self, other) {
+            return self.as_bool().is_some() && self.as_bool() == other.as_bool();
+        }
+        if let (Some(a), Some(b)) = (self.as_num(), other.as_num()) {
+            return a == b;
+        }
+        let (a, b) = (self.as_text(), other.as_text());
+        if ignore_case {
+            a.trim().eq_ignore_ascii_case(b.trim())
+        } else {
+            a.trim() == b.trim()
+        }
+    }
+}
+
+impl From<&toml::Value> for Value {
+    fn from(value: &toml::Value) -> Self {
+        match value {
+            toml::Value::Integer(n) => Value::Num(*n as f64),
+            toml::Value::Float(n) => Value::Num(*n),
+            toml::Value::Boolean(b) => Value::Bool(*b),
+            toml::Value::String(s) => Value::Text(s.clone()),
+            toml::Value::Array(items) => Value::List(items.iter().map(|v| Value::from(v).as_text()).collect()),
+            other => Value::Text(other.to_string()),
+        }
+    }
+}
+
+/// Where the rules get their values from.
+pub trait Facts {
+    /// A field of the aircraft (or derived from it), None if unknown
+    fn fact(&self, name: &str) -> Option<Value>;
+    /// A command line setting used as `$name`, None if it isn't set
+    fn variable(&self, name: &str) -> Option<Value>;
+}
+
+/// A test on one field. All given comparisons have to hold.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Test {
+    field: String,
+    eq: Option<toml::Value>,
+    ne: Option<toml::Value>,
+    gt: Option<toml::Value>,
+    ge: Option<toml::Value>,
+    lt: Option<toml::Value>,
+    le: Option<toml::Value>,
+    #[serde(rename = "in")]
+    one_of: Option<toml::Value>,
+    not_in: Option<toml::Value>,
+    contains: Option<toml::Value>,
+    starts_with: Option<toml::Value>,
+    exists: Option<bool>,
+    /// Text comparisons (eq, ne, in, not_in, contains, starts_with) are case-sensitive otherwise
+    #[serde(default)]
+    ignore_case: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum Condition {
+    All { all: Vec<Condition> },
+    Any { any: Vec<Condition> },
+    Not { not: Box<Condition> },
+    Test(Box<Test>),
+}
+
+impl Condition {
+    fn matches(&self, facts: &dyn Facts) -> bool {
+        match self {
+            Condition::All { all } => all.iter().all(|c| c.matches(facts)),
+            Condition::Any { any } => any.iter().any(|c| c.matches(facts)),
+            Condition::Not { not } => !not.matches(facts),
+            Condition::Test(test) => test.matches(facts),
+        }
+    }
+
+    // Field names used anywhere in the condition
+    fn fields(&self) -> Vec<&str> {
+        match self {
+            Condition::All { all: list } | Condition::Any { any: list } => list.iter().flat_map(|c| c.fields()).collect(),
+            Condition::Not { not } => not.fields(),
+            Condition::Test(test) => vec![test.field.as_str()],
+        }
+    }
+}
+
+impl Test {
+    fn matches(&self, facts: &dyn Facts) -> bool {
+        let fact = facts.fact(&self.field);
+
+        if let Some(wanted) = self.exists
+            && fact.is_some() != wanted {
+            return false;
+        }
+
+        // Without a value only "ne" and "not_in" can hold
+        let Some(fact) = fact else {
+            return self.eq.is_none() && self.gt.is_none() && self.ge.is_none() && self.lt.is_none() && self.le.is_none()
+                && self.one_of.is_none() && self.contains.is_none() && self.starts_with.is_none();
+        };
+
+        // A value like "$speed" comes from the command line. Not set means the test fails.
+        let resolve = |v: &Option<toml::Value>| -> Option<Option<Value>> {
+            v.as_ref().map(|v| match v {
+                toml::Value::String(s) if s.starts_with('$') => facts.variable(&s[1..]),
+                other => Some(Value::from(other)),
+            })
+        };
+        let compare = |v: &Option<toml::Value>, check: &dyn Fn(f64, f64) -> bool| match resolve(v) {
+            None => true,
+            Some(limit) => match (fact.as_num(), limit.and_then(|l| l.as_num())) {
+                (Some(a), Some(b)) => check(a, b),
+                _ => false,
+            },
+        };
+        let text = |v: &Option<toml::Value>, check: &dyn Fn(&Value) -> bool| match resolve(v) {
+            None => true,
+            Some(value) => value.is_some_and(|value| check(&value)),
+        };
+
+        let fold = |s: String| if self.ignore_case { s.to_lowercase() } else { s };
+        let same = |v: &Value| fact.same(v, self.ignore_case);
+        let fact_text = fold(fact.as_text());
+
+        text(&self.eq, &same)
+            && text(&self.ne, &|v| !same(v))
+            && compare(&self.gt, &|a, b| a > b)
+            && compare(&self.ge, &|a, b| a >= b)
+            && compare(&self.lt, &|a, b| a < b)
+            && compare(&self.le, &|a, b| a <= b)
+            && text(&self.one_of, &|v| v.items().iter().any(|i| same(&Value::Text(i.clone()))))
+            && text(&self.not_in, &|v| !v.items().iter().any(|i| same(&Value::Text(i.clone()))))
+            && text(&self.contains, &|v| fact_text.contains(&fold(v.as_text())))
+            && text(&self.starts_with, &|v| fact_text.starts_with(&fold(v.as_text())))
+    }
+}
+
+/// One detection: when all conditions hold, the reason goes into the table.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    pub name: String,
+    #[serde(default)]
+    pub when: Vec<Condition>,
+    pub reason: String,
+    #[serde(default)]
+    pub severity: Option<String>,
+    #[serde(default)]
+    pub category: Category,
+    #[serde(default)]
+    pub score: Option<f64>, // instead of the category's weight
+    #[serde(default = "enabled")]
+    pub enabled: bool,
+}
+
+fn enabled() -> bool {
+    true
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleFile {
+    #[serde(default)]
+    rule: Vec<Rule>,
+}
+
+/// The detection rules, from a TOML file or the built-in set.
+pub struct RuleSet {
+    rules: Vec<Rule>,
+}
+
+impl RuleSet {
+    /// Loads `path` (or "rules.toml" if it exists), otherwise the built-in rules.
+    /// `known` are the field names rules can use, to warn about typos.
+    pub fn load(path: Option<&str>, known: &[&str]) -> Result<Self, Box<dyn Error>> {
+        let file_path = path.unwrap_or(DEFAULT_FILE);
+
+        let rules = match std::fs::read_to_string(file_path) {
+            Ok(content) => {
+                let rules = Self::parse(&content).map_err(|e| format!("Rules '{}': {}", file_path, e))?;
+                println!("Loaded {} rules from '{}'.", rules.rules.len(), file_path);
+                rules
+            }
+            Err(e) if path.is_some() => return Err(format!("Rules '{}': {}", file_path, e).into()),
+            Err(_) => Self::built_in(),
+        };
+
+        for warning in rules.check(known) {
+            eprintln!("WARNING: {}", warning);
+        }
+        Ok(rules)
+    }
+
+    // Fields the rules use that aren't in `known`: in a condition they never have a value,
+    // in a reason or severity they are shown as "?"
+    fn check(&self, known: &[&str]) -> Vec<String> {
+        let mut warnings = Vec::new();
+        for rule in &self.rules {
+            for field in rule.when.iter().flat_map(|c| c.fields()) {
+                if !known.contains(&field) {
+                    warnings.push(format!("rule '{}' uses unknown field '{}', it never has a value.", rule.name, field));
+                }
+            }
+            for template in std::iter::once(&rule.reason).chain(&rule.severity) {
+                for field in placeholders(template) {
+                    if !known.contains(&field.as_str()) {
+                        warnings.push(format!("rule '{}' puts unknown field '{}' in its text, it is shown as '?'.", rule.name, field));
+                    }
+                }
+            }
+        }
+        warnings
+    }
+
+    /// The rules that ship with the tool
+    pub fn built_in() -> Self {
+        Self::parse(DEFAULT_RULES).expect("the built-in rules parse")
+    }
+
+    fn parse(content: &str) -> Result<Self, Box<dyn Error>> {
+        let file: RuleFile = toml::from_str(content)?;
+        Ok(Self { rules: file.rule.into_iter().filter(|r| r.enabled).collect() })
+    }
+
+    /// A reason for every matching rule, in the order of the file.
+    pub fn evaluate(&self, facts: &dyn Facts) -> Vec<Reason> {
+        self.rules.iter()
+            .filter(|rule| rule.when.iter().all(|c| c.matches(facts)))
+            .map(|rule| {
+                let severity = rule.severity.as_deref()
+                    .map(|s| Severity::parse(&render(s, facts)))
+                    .unwrap_or_default();
+                let finding = Finding::Rule { name: rule.name.clone(), text: render(&rule.reason, facts) };
+                let mut reason = Reason::new(finding, rule.category, severity);
+                if let Some(score) = rule.score {
+                    reason.score = score;
+                }
+                reason
+            })
+            .collect()
+    }
+}
+
+// A piece of a template: plain text or a field with the number of decimals
+enum Part {
+    Text(char),
+    Field(String, Option<usize>),
+}
+
+fn parts(template: &str) -> Vec<Part> {
+    let mut parts = Vec::new();
+    let mut chars = template.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' | '}' if chars.peek() == Some(&c) => {
+                chars.next();
+                parts.push(Part::Text(c));
+            }
+            '{' => {
+                let spec: String = chars.by_ref().take_while(|c| *c != '}').collect();
+                let (name, decimals) = match spec.split_once(":.") {
+                    Some((name, d)) => (name, d.parse::<usize>().ok()),
+                    None => (spec.as_str(), None),
+                };
+                parts.push(Part::Field(name.trim().to_string(), decimals));
+            }
+            _ => parts.push(Part::Text(c)),
+        }
+    }
+    parts
+}
+
+// Field names a template puts in
+fn placeholders(template: &str) -> Vec<String> {
+    parts(template).into_iter()
+        .filter_map(|part| match part {
+            Part::Field(name, _) => Some(name),
+            Part::Text(_) => None,
+        })
+        .collect()
+}
+
+/// Fills a template: "{field}" is replaced by its value, "{field:.1}" rounds numbers, "{{" is a brace.
+/// Unknown fields become "?".
+pub fn render(template: &str, facts: &dyn Facts) -> String {
+    let mut out = String::new();
+    for part in parts(template) {
+        match part {
+            Part::Text(c) => out.push(c),
+            Part::Field(name, decimals) => {
+                let value = match (facts.fact(&name), decimals) {
+                    (Some(v), Some(d)) => v.as_num().map(|n| format!("{:.*}", d, n)).unwrap_or_else(|| v.as_text()),
+                    (Some(v), None) => v.as_text(),
+                    (None, _) => "?".to_string(),
+                };
+                out.push_str(&value);
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use crate::models::Aircraft;
+
+    // Facts and command line settings from a list
+    #[derive(Default)]
+    struct Fixed {
+        facts: HashMap<&'static str, Value>,
+        variables: HashMap<&'static str, Value>,
+    }
+
+    impl Facts for Fixed {
+        fn fact(&self, name: &str) -> Option<Value> {
+            self.facts.get(name).cloned()
+        }
+
+        fn variable(&self, name: &str) -> Option<Value> {
+            self.variables.get(name).cloned()
+        }
+    }
+
+    fn facts(list: &[(&'static str, Value)]) -> Fixed {
+        Fixed { facts: list.iter().cloned().collect(), ..Default::default() }
+    }
+
+    fn test(toml: &str) -> Test {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn built_in_rules_only_use_known_fields() {
+        let rules = RuleSet::built_in();
+        let names: Vec<&str> = rules.rules.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, [
+            "nav_anomaly", "nav_jump", "reappeared", "callsign_change", "source_change", "adsb_lost",
+            "reg_mismatch", "formation", "pattern", "squawk", "emergency", "speed", "mlat", "hvt",
+            "target_type", "mil_flag", "mil_hex", "mil_callsign", "foreign_mil", "pia", "ladd", "non_icao",
+            "db_interesting",
+        ]);
+        assert_eq!(rules.check(&Aircraft::rule_fields()), Vec::<String>::new());
+    }
+
+    #[test]
+    fn built_in_rules_compare_like_the_old_checks() {
+        let rules = RuleSet::built_in();
+        let found = |list: &[(&'static str, Value)], variables: &[(&'static str, Value)]| {
+            let f = Fixed { facts: list.iter().cloned().collect(), variables: variables.iter().cloned().collect() };
+            rules.evaluate(&f).iter().map(Reason::text).collect::<Vec<_>>()
+        };
+        let text = |s: &str| Value::Text(s.to_string());
+
+        // type lists and --aircraft-type are case-sensitive, as before
+        assert_eq!(found(&[("type", text("K35R"))], &[]), ["HVT: K35R"]);
+        assert!(found(&[("type", text("k35r"))], &[]).is_empty());
+        assert_eq!(found(&[("type", text("B738"))], &[("aircraft_type", text("B73"))]), ["Target Type Match"]);
+        assert!(found(&[("type", text("B738"))], &[("aircraft_type", text("b73"))]).is_empty());
+        assert!(found(&[("source", text("MLAT")), ("type", text("A320"))], &[]).is_empty());
+
+        // --countries-of-interest never cared about case
+        let countries = [("countries_of_interest", Value::List(vec!["russia".to_string()]))];
+        assert_eq!(
+            found(&[("military", Value::Bool(true)), ("country", text("Russia"))], &countries),
+            ["FOREIGN MIL IN SECTOR (Country: Russia)"],
+        );
+    }
+
+    #[test]
+    fn unknown_fields_in_templates_are_reported() {
+        let rules = RuleSet::parse(r#"
+            [[rule]]
+            name = "typo"
+            when = [{ field = "type", exists = true }]
+            reason = "{{literal}} {tpye} {type}"
+            severity = "{sevrity}"
+        "#).unwrap();
+        assert_eq!(rules.check(&["type"]), [
+            "rule 'typo' puts unknown field 'tpye' in its text, it is shown as '?'.",
+            "rule 'typo' puts unknown field 'sevrity' in its text, it is shown as '?'.",
+        ]);
+    }
+
+    #[test]
+    fn missing_facts() {
+        let none = Fixed::default();
+        assert!(test(r#"field = "type""#).matches(&none));
+        assert!(test(r#"field = "type"
+                        exists = false"#).matches(&none));
+        assert!(!test(r#"field = "type"
+                         exists = true"#).matches(&none));
+        // only the negative tests hold without a value
+        assert!(test(r#"field = "type"
+                        not_in = ["C172"]"#).matches(&none));
+        assert!(test(r#"field = "type"
+                        ne = "C172""#).matches(&none));
+        assert!(!test(r#"field = "type"
+                         in = ["C172"]"#).matches(&none));
+        assert!(!test(r#"field = "gs"
+                         lt = 100"#).matches(&none));
+    }
+
+    #[test]
+    fn comparisons() {
+        let c172 = facts(&[("type", Value::Text("C172".to_string())), ("gs", Value::Num(110.0)), ("military", Value::Bool(false))]);
+        assert!(test(r#"field = "type"
+                        not_in = ["C152", "c172"]"#).matches(&c172));
+        assert!(!test(r#"field = "type"
+                         not_in = ["C152", "c172"]
+                         ignore_case = true"#).matches(&c172));
+        assert!(!test(r#"field = "type"
+                         starts_with = "c1""#).matches(&c172));
+        assert!(test(r#"field = "type"
+                        starts_with = "c1"
+                        ignore_case = true"#).matches(&c172));
+        assert!(test(r#"field = "gs"
+                        gt = 100
+                        le = 110"#).matches(&c172));
+        assert!(test(r#"field = "military"
+                        eq = false"#).matches(&c172));
+        assert!(!test(r#"field = "military"
+                         eq = "no match""#).matches(&c172));
+    }
+
+    #[test]
+    fn variables_from_the_command_line() {
+        let mut fast = facts(&[("speed", Value::Num(320.0)), ("country", Value::Text("Russia".to_string()))]);
+        // not set: the test fails instead of comparing with nothing
+        assert!(!test(r#"field = "speed"
+                         gt = "$speed""#).matches(&fast));
+        assert!(!test(r#"field = "country"
+                         contains = "$aircraft_type""#).matches(&fast));
+
+        fast.variables.insert("speed", Value::Num(300.0));
+        fast.variables.insert("countries_of_interest", Value::List(vec!["Belarus".to_string(), "russia".to_string()]));
+        assert!(test(r#"field = "speed"
+                        gt = "$speed""#).matches(&fast));
+        assert!(!test(r#"field = "country"
+                         in = "$countries_of_interest""#).matches(&fast));
+        assert!(test(r#"field = "country"
+                        in = "$countries_of_interest"
+                        ignore_case = true"#).matches(&fast));
+    }
+
+    #[test]
+    fn rendering() {
+        let f = facts(&[("nav_delta", Value::Num(1234.56)), ("type", Value::Text("K35R".to_string()))]);
+        assert_eq!(render("NAV ANOMALY (Δ {nav_delta:.0}ft)", &f), "NAV ANOMALY (Δ 1235ft)");
+        assert_eq!(render("{nav_delta:.1} {type:.0}", &f), "1234.6 K35R");
+        assert_eq!(render("{{type}} = { type }", &f), "{type} = K35R");
+        assert_eq!(render("HVT: {tpye}", &f), "HVT: ?");
+    }
+}
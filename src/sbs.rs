@@ -0,0 +1,189 @@
+use std::error::Error;
+use std::time::Duration;
+use chrono::NaiveDateTime;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::net::TcpStream;
+use crate::beast::unix_now;
+use crate::receiver::{ReceiverState, SharedReceiver};
+use crate::squawk;
+
+// Column layout of a BaseStation line (0 based):
+// 0 "MSG", 1 transmission type, 4 hex, 6/7 date/time generated,
+// 10 callsign, 11 altitude, 12 ground speed, 13 track, 14 lat, 15 lon,
+// 16 vertical rate, 17 squawk, 18 alert, 19 emergency, 20 SPI, 21 on ground
+const COL_TYPE: usize = 1;
+const COL_HEX: usize = 4;
+const COL_DATE: usize = 6;
+const COL_TIME: usize = 7;
+const COL_CALLSIGN: usize = 10;
+const COL_ALT: usize = 11;
+const COL_SPEED: usize = 12;
+const COL_TRACK: usize = 13;
+const COL_LAT: usize = 14;
+const COL_LON: usize = 15;
+const COL_VRATE: usize = 16;
+const COL_SQUAWK: usize = 17;
+const COL_ALERT: usize = 18;
+const COL_EMERGENCY: usize = 19;
+const COL_SPI: usize = 20;
+const COL_GROUND: usize = 21;
+
+/// Merges one BaseStation line into the receiver state.
+/// Every MSG type (1-8) only carries some of the columns, so we only copy what is filled in
+/// and keep the rest from earlier messages of the same hex.
+pub fn apply_line(state: &mut ReceiverState, line: &str, now: f64) {
+    let cols: Vec<&str> = line.trim().split(',').collect();
+    if cols.len() < 11 || cols[0] != "MSG" {
+        return; // SEL, ID, AIR, STA and CLK lines don't carry aircraft data
+    }
+
+    let hex = cols[COL_HEX].trim().to_lowercase();
+    if hex.is_empty() || !matches!(cols[COL_TYPE], "1" | "2" | "3" | "4" | "5" | "6" | "7" | "8") {
+        return;
+    }
+
+    let text = |i: usize| cols.get(i).map(|s| s.trim()).filter(|s| !s.is_empty());
+    let num = |i: usize| text(i).and_then(|s| s.parse::<f64>().ok());
+
+    let ac = state.entry(&hex, now);
+    if ac.source_type.is_empty() {
+        ac.source_type = "sbs".to_string();
+    }
+
+    if let Some(cs) = text(COL_CALLSIGN) {
+        ac.callsign = Some(cs.to_string());
+    }
+    if let Some(alt) = num(COL_ALT) {
+        ac.alt_baro = Some(alt);
+    }
+    if let Some(gs) = num(COL_SPEED) {
+        ac.ground_speed = Some(gs);
+    }
+    if let Some(track) = num(COL_TRACK) {
+        ac.track = Some(track);
+    }
+    if let (Some(lat), Some(lon)) = (num(COL_LAT), num(COL_LON)) {
+        ac.lat = Some(lat);
+        ac.lon = Some(lon);
+    }
+    if let Some(vr) = num(COL_VRATE) {
+        ac.baro_rate = Some(vr);
+    }
+    if let Some(squawk) = text(COL_SQUAWK) {
+        ac.squawk = Some(squawk.to_string());
+    }
+    // Flags are "-1" (or "1") when set, "0" when not and empty when the message doesn't carry them
+    let flag = |i: usize| text(i).map(|s| s == "-1" || s == "1");
+
+    if let Some(alert) = flag(COL_ALERT) {
+        ac.alert = Some(alert);
+    }
+    if let Some(spi) = flag(COL_SPI) {
+        ac.spi = Some(spi);
+    }
+    // BaseStation doesn't say which emergency it is, but the squawk usually does
+    if let Some(emergency) = flag(COL_EMERGENCY) {
+        let state = if emergency { ac.squawk.as_deref().and_then(squawk::implied_emergency).unwrap_or("general") } else { "none" };
+        ac.emergency = Some(state.to_string());
+    }
+    // On the ground, same as "ground" in the API
+    if flag(COL_GROUND) == Some(true) {
+        ac.alt_baro = Some(0.0);
+    }
+}
+
+/// Reads the "generated" date and time of a line as unix seconds (used for recorded files).
+fn line_time(line: &str) -> Option<f64> {
+    let cols: Vec<&str> = line.split(',').collect();
+    let stamp = format!("{} {}", cols.get(COL_DATE)?.trim(), cols.get(COL_TIME)?.trim());
+    let t = NaiveDateTime::parse_from_str(&stamp, "%Y/%m/%d %H:%M:%S%.f").ok()?;
+    Some(t.and_utc().timestamp_millis() as f64 / 1000.0)
+}
+
+/// Keeps a connection to a BaseStation port (usually 30003) open and merges every line.
+pub fn spawn_tcp(addr: String, state: SharedReceiver) {
+    tokio::spawn(async move {
+        loop {
+            match TcpStream::connect(&addr).await {
+                Ok(stream) => {
+                    let mut lines = BufReader::new(stream).lines();
+                    loop {
+                        match lines.next_line().await {
+                            Ok(Some(line)) => apply_line(&mut state.lock().unwrap(), &line, unix_now()),
+                            Ok(None) => {
+                                eprintln!("Receiver Error: {} closed the connection", addr);
+                                break;
+                            }
+                            Err(e) => {
+                                eprintln!("Receiver Error: {}", e);
+                                break;
+                            }
+                        }
+                    }
+                }
+                Err(e) => eprintln!("Receiver Error: could not connect to {}: {}", addr, e),
+            }
+            tokio::time::sleep(Duration::from_secs(5)).await;
+        }
+    });
+}
+
+/// Loads a recorded `.sbs` file into the receiver state.
+pub fn load_file(path: &str, state: &SharedReceiver) -> Result<(), Box<dyn Error>> {
+    let content = std::fs::read_to_string(path)?;
+    let mut state = state.lock().unwrap();
+    let mut clock = 0.0;
+    let mut count = 0;
+
+    for line in content.lines() {
+        clock = line_time(line).unwrap_or(clock);
+        apply_line(&mut state, line, clock);
+        count += 1;
+    }
+
+    println!("Loaded {} BaseStation lines from '{}'.", count, path);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn emergency_after(squawk: &str, flag: &str) -> Option<String> {
+        let mut state = ReceiverState::new(None);
+        let line = format!("MSG,6,1,1,3C6586,1,2026/01/01,12:00:00.000,2026/01/01,12:00:00.000,,,,,,,,{},0,{},0,0", squawk, flag);
+        apply_line(&mut state, &line, 0.0);
+        state.snapshot(0.0, 1.0).first().and_then(|ac| ac.emergency.clone())
+    }
+
+    #[test]
+    fn emergency_state_follows_the_squawk() {
+        assert_eq!(emergency_after("7500", "-1").as_deref(), Some("unlawful"));
+        assert_eq!(emergency_after("7600", "-1").as_deref(), Some("nordo"));
+        assert_eq!(emergency_after("7700", "-1").as_deref(), Some("general"));
+        assert_eq!(emergency_after("", "-1").as_deref(), Some("general"));
+        assert_eq!(emergency_after("7500", "0").as_deref(), Some("none"));
+    }
+
+    #[test]
+    fn messages_of_one_hex_merge_into_one_aircraft() {
+        let mut state = ReceiverState::new(None);
+        let stamp = "2026/01/01,12:00:00.000,2026/01/01,12:00:00.000";
+        for (i, line) in [
+            format!("MSG,1,1,1,3C6586,1,{},DLH4AB  ,,,,,,,,,,,", stamp),
+            format!("MSG,3,1,1,3C6586,1,{},,37000,,,52.1,4.2,,,0,0,0,0", stamp),
+            format!("MSG,4,1,1,3C6586,1,{},,,450,87.5,,,-64,,,,,", stamp),
+            // a later velocity message only updates the speed
+            format!("MSG,4,1,1,3C6586,1,{},,,455,88.0,,,0,,,,,", stamp),
+        ].iter().enumerate() {
+            apply_line(&mut state, line, i as f64);
+        }
+
+        let aircraft = state.snapshot(3.0, 10.0);
+        assert_eq!(aircraft.len(), 1);
+        let ac = &aircraft[0];
+        assert_eq!((ac.icao.as_str(), ac.callsign.as_deref(), ac.source_type.as_str()), ("3c6586", Some("DLH4AB"), "sbs"));
+        assert_eq!((ac.lat, ac.lon, ac.alt_baro), (Some(52.1), Some(4.2), Some(37000.0)));
+        assert_eq!((ac.ground_speed, ac.track, ac.baro_rate), (Some(455.0), Some(88.0), Some(0.0)));
+    }
+}
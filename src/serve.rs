@@ -0,0 +1,274 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use clap::ValueEnum;
+use serde_json::json;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use crate::capture::{self, CaptureRecord};
+use crate::lookups::Lookups;
+use crate::models::Aircraft;
+use crate::query::Query;
+use crate::source;
+
+/// The point endpoint of airplanes.live never goes beyond this radius (nm)
+const MAX_POINT_RADIUS: f64 = 250.0;
+
+/// What the stand-in server should do wrong, to test the error branches of the scan loop.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Fault {
+    /// Answer normally
+    None,
+    /// 500 Internal Server Error
+    Http500,
+    /// 429 Too Many Requests with a Retry-After header
+    Http429,
+    /// 200 OK with a body that is not JSON
+    Malformed,
+    /// 200 OK with an empty `ac` array
+    Empty,
+}
+
+/// Where the served aircraft come from.
+enum Data {
+    /// Recorded scans, one per scan of the client (and then from the start again)
+    Capture(Vec<CaptureRecord>),
+    /// Generated aircraft flying straight lines around a center point
+    Synthetic(Vec<Aircraft>),
+}
+
+struct ServerState {
+    data: Data,
+    started: Instant,
+    requests: u64,
+    // the recorded scan being served, and the paths that already got it
+    scan: usize,
+    scan_paths: HashSet<String>,
+    fault: Fault,
+    fault_every: u64,
+    // for `mil`, which also takes aircraft from the known military ranges
+    lookups: Lookups,
+}
+
+/// Settings for `serve-capture`, straight from the command line.
+pub struct ServeConfig {
+    pub port: u16,
+    pub capture: Option<String>,
+    pub synthetic: usize,
+    pub center: (f64, f64),
+    pub fault: Fault,
+    pub fault_every: u64,
+}
+
+/// Runs a local HTTP server that answers like the airplanes.live v2 API.
+pub async fn run(config: ServeConfig) -> Result<(), Box<dyn Error>> {
+    let listener = TcpListener::bind(("127.0.0.1", config.port)).await?;
+    println!("Listening on http://127.0.0.1:{} (use it with --base-url)", listener.local_addr()?.port());
+    serve(listener, config).await
+}
+
+/// Answers requests on an already bound listener (`config.port` is ignored).
+pub async fn serve(listener: TcpListener, config: ServeConfig) -> Result<(), Box<dyn Error>> {
+    let data = match &config.capture {
+        Some(path) => {
+            let records = capture::load(path)?;
+            println!("Serving {} recorded scans from '{}'.", records.len(), path);
+            Data::Capture(records)
+        }
+        None => {
+            println!("Serving {} synthetic aircraft around {:.4}, {:.4}.", config.synthetic, config.center.0, config.center.1);
+            Data::Synthetic(synthetic_fleet(config.synthetic, config.center))
+        }
+    };
+
+    let state = Arc::new(Mutex::new(ServerState {
+        data,
+        started: Instant::now(),
+        requests: 0,
+        scan: 0,
+        scan_paths: HashSet::new(),
+        fault: config.fault,
+        fault_every: config.fault_every.max(1),
+        lookups: Lookups::built_in(),
+    }));
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let state = state.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle(stream, state).await {
+                eprintln!("Serve Error: {}", e);
+            }
+        });
+    }
+}
+
+async fn handle(mut stream: TcpStream, state: Arc<Mutex<ServerState>>) -> Result<(), Box<dyn Error + Send + Sync>> {
+    // We only need the request line, so read until the end of the headers
+    let mut request = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        request.extend_from_slice(&buf[..n]);
+    }
+
+    let request = String::from_utf8_lossy(&request);
+    let path = request.split_whitespace().nth(1).unwrap_or("/").to_string();
+
+    let response = respond(&path, &mut state.lock().unwrap());
+    println!("GET {} -> {}", path, response.status);
+
+    let mut head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n",
+        response.status,
+        response.body.len()
+    );
+    if let Some(secs) = response.retry_after {
+        head.push_str(&format!("Retry-After: {}\r\n", secs));
+    }
+    head.push_str("\r\n");
+
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(response.body.as_bytes()).await?;
+    Ok(())
+}
+
+struct Response {
+    status: &'static str,
+    body: String,
+    retry_after: Option<u64>,
+}
+
+fn respond(path: &str, state: &mut ServerState) -> Response {
+    state.requests += 1;
+
+    // Faults hit every n-th request, the rest is answered normally
+    let fault = if state.requests.is_multiple_of(state.fault_every) { state.fault } else { Fault::None };
+    match fault {
+        Fault::Http500 => return Response { status: "500 Internal Server Error", body: "{\"msg\":\"Internal Server Error\"}".to_string(), retry_after: None },
+        Fault::Http429 => return Response { status: "429 Too Many Requests", body: "{\"msg\":\"Too Many Requests\"}".to_string(), retry_after: Some(5) },
+        Fault::Malformed => return Response { status: "200 OK", body: "<html>Bad Gateway</html>".to_string(), retry_after: None },
+        _ => {}
+    }
+
+    if !path.trim_start_matches('/').starts_with("v2/") {
+        return Response { status: "404 Not Found", body: "{\"msg\":\"Unknown endpoint\"}".to_string(), retry_after: None };
+    }
+    let Some(mut query) = Query::from_path(path) else {
+        return Response { status: "400 Bad Request", body: "{\"msg\":\"Unknown or incomplete query\"}".to_string(), retry_after: None };
+    };
+    if let Query::Point { radius, .. } = &mut query {
+        *radius = radius.min(MAX_POINT_RADIUS);
+    }
+
+    let selected: Vec<Aircraft> = if fault == Fault::Empty {
+        Vec::new()
+    } else {
+        next_scan(state, path);
+        current_aircraft(state).into_iter()
+            .filter(|ac| query.matches(ac, &state.lookups))
+            .collect()
+    };
+
+    let now = chrono::Utc::now().timestamp_millis();
+
+    // Same envelope as the real API
+    let body = json!({
+        "ac": selected,
+        "msg": "No error",
+        "now": now,
+        "total": selected.len(),
+        "ctime": now,
+        "ptime": 0,
+    });
+
+    Response { status: "200 OK", body: body.to_string(), retry_after: None }
+}
+
+// A scan asks for each path once (an area scan for one per tile), so a path asked for again starts
+// the next scan. Faults don't count: the retry of a failed tile still gets the same scan.
+fn next_scan(state: &mut ServerState, path: &str) {
+    if !state.scan_paths.insert(path.to_string()) {
+        state.scan += 1;
+        state.scan_paths.clear();
+        state.scan_paths.insert(path.to_string());
+    }
+}
+
+/// The aircraft the server knows about right now.
+fn current_aircraft(state: &ServerState) -> Vec<Aircraft> {
+    let elapsed = state.started.elapsed().as_secs_f64();
+
+    match &state.data {
+        Data::Capture(records) => {
+            if records.is_empty() {
+                return Vec::new();
+            }
+            let record = &records[state.scan % records.len()];
+            let Some(query) = record.query() else {
+                return Vec::new(); // `capture::load` already skips these
+            };
+            // parsed for the whole recorded query, the request's own query filters afterwards
+            source::parse_body(&record.feed, &record.body, &query, &state.lookups).unwrap_or_else(|e| {
+                eprintln!("Capture Error: {}", e);
+                Vec::new()
+            })
+        }
+        Data::Synthetic(fleet) => fleet.iter().map(|ac| fly(ac, elapsed)).collect(),
+    }
+}
+
+/// Builds a mixed fleet spread around the center (civil traffic plus some interesting military types).
+fn synthetic_fleet(count: usize, center: (f64, f64)) -> Vec<Aircraft> {
+    let types = [
+        ("A320", false), ("B738", false), ("K35R", true), ("E3TF", true), ("F35", true),
+        ("C172", false), ("A332", false), ("EUFI", true), ("B77W", false), ("C17", true),
+    ];
+
+    (0..count)
+        .map(|i| {
+            let (type_code, mil) = types[i % types.len()];
+            let f = i as f64;
+
+            // Spiral out from the center, up to ~200 nm (golden angle so they don't line up)
+            let dist_nm = 200.0 * ((f + 1.0) / count as f64).sqrt();
+            let bearing = (f * 137.508).to_radians();
+            let lat = center.0 + dist_nm / 60.0 * bearing.cos();
+            let lon = center.1 + dist_nm / 60.0 * bearing.sin() / center.0.to_radians().cos();
+
+            Aircraft {
+                icao: format!("{:06x}", 0xf00000 + i),
+                callsign: Some(format!("SYN{:03}", i)),
+                type_code: Some(type_code.to_string()),
+                registration: Some(format!("SY-{:03}", i)),
+                ground_speed: Some(120.0 + (i * 37 % 400) as f64),
+                alt_baro: Some(1000.0 + (i * 2300 % 39000) as f64),
+                alt_geom: Some(1100.0 + (i * 2300 % 39000) as f64),
+                source_type: if i % 7 == 3 { "mlat" } else { "adsb_icao" }.to_string(),
+                lat: Some(lat),
+                lon: Some(lon),
+                is_military: Some(mil),
+                track: Some((i * 53 % 360) as f64),
+                ..Default::default()
+            }
+        })
+        .collect()
+}
+
+/// Moves a synthetic aircraft along its track for the elapsed time.
+fn fly(ac: &Aircraft, elapsed: f64) -> Aircraft {
+    let mut moved = ac.clone();
+    if let (Some(lat), Some(lon), Some(gs), Some(track)) = (ac.lat, ac.lon, ac.ground_speed, ac.track) {
+        let dist_deg = gs * elapsed / 3600.0 / 60.0; // knots -> nm -> degrees
+        let new_lat = lat + dist_deg * track.to_radians().cos();
+        let new_lon = lon + dist_deg * track.to_radians().sin() / lat.to_radians().cos();
+
+        moved.lat = Some(new_lat.clamp(-89.0, 89.0));
+        moved.lon = Some((new_lon + 540.0) % 360.0 - 180.0); // keep it within -180..180
+    }
+    moved
+}
@@ -0,0 +1,430 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use clap::ValueEnum;
+use reqwest::Client;
+use serde::Deserialize;
+use serde::de::IgnoredAny;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use crate::area::{self, Coverage, Polygon, Tile};
+use crate::beast::{self, FrameFormat};
+use crate::lookups::Lookups;
+use crate::models::{Aircraft, AirplanesLiveResponse, Args};
+use crate::provider::{self, Feed, FetchError, ProviderKind};
+use crate::query::Query;
+use crate::receiver::{ReceiverState, SharedReceiver};
+use crate::sbs;
+
+/// Aircraft not heard from for this long drop out of a live receiver picture (seconds)
+const RECEIVER_MAX_AGE: f64 = 60.0;
+
+/// How an area scan is split up and how hard we may hit the feed while doing it.
+#[derive(Debug, Clone, Copy)]
+pub struct Tiling {
+    pub radius: f64,
+    pub concurrency: usize,
+    pub rate: f64, // requests per second
+}
+
+/// Where the aircraft for each scan come from.
+pub enum Source {
+    /// An HTTP feed that we poll every scan, plus an optional fallback.
+    /// `active` is the one that answered last.
+    Feed { feeds: Vec<Arc<Feed>>, active: AtomicUsize, client: Client, tiling: Tiling },
+    /// Our own receivers, decoded in the background
+    Receiver { state: SharedReceiver, label: String, live: bool },
+    /// A readsb/tar1090 aircraft.json (local path or URL), not filtered by the sector yet
+    AircraftJson { location: String, client: Client },
+}
+
+impl Source {
+    /// Picks the source from the command line: raw receiver feeds win over the HTTP provider.
+    /// `reference` is the receiver location, used to decode raw positions.
+    pub fn from_args(args: &Args, reference: Option<(f64, f64)>) -> Result<Source, Box<dyn Error>> {
+        let raw = [
+            (&args.beast, FrameFormat::Beast, "Beast"),
+            (&args.avr, FrameFormat::Avr, "AVR"),
+        ];
+
+        for (addr, format, name) in raw {
+            if let Some(addr) = addr {
+                let state = ReceiverState::shared(reference);
+                beast::spawn_tcp(addr.clone(), format, state.clone());
+                return Ok(Source::Receiver { state, label: format!("{} {}", name, addr), live: true });
+            }
+        }
+
+        if let Some(addr) = &args.sbs {
+            let state = ReceiverState::shared(reference);
+            sbs::spawn_tcp(addr.clone(), state.clone());
+            return Ok(Source::Receiver { state, label: format!("SBS {}", addr), live: true });
+        }
+
+        if let Some(path) = &args.frames_file {
+            let state = ReceiverState::shared(reference);
+            beast::load_file(path, &state)?;
+            return Ok(Source::Receiver { state, label: format!("file {}", path), live: false });
+        }
+
+        if let Some(path) = &args.sbs_file {
+            let state = ReceiverState::shared(reference);
+            sbs::load_file(path, &state)?;
+            return Ok(Source::Receiver { state, label: format!("file {}", path), live: false });
+        }
+
+        if let Some(location) = &args.aircraft_json {
+            return Ok(Source::AircraftJson { location: location.clone(), client: Client::new() });
+        }
+
+        let mut feeds = vec![Arc::new(Feed::new(
+            args.provider,
+            provider::build_provider(args.provider, args.base_url.clone(), args.api_key.clone()),
+            args.budget,
+        ))];
+        if let Some(kind) = args.fallback_provider {
+            feeds.push(Arc::new(Feed::new(
+                kind,
+                provider::build_provider(kind, args.fallback_base_url.clone(), args.fallback_api_key.clone()),
+                args.budget,
+            )));
+        }
+
+        Ok(Source::Feed {
+            feeds,
+            active: AtomicUsize::new(0),
+            client: Client::new(),
+            tiling: Tiling { radius: args.tile_radius, concurrency: args.concurrency, rate: args.rate },
+        })
+    }
+
+    /// Short name for the scan header
+    pub fn label(&self) -> String {
+        match self {
+            Source::Feed { feeds, active, .. } => {
+                let i = active.load(Ordering::Relaxed);
+                if i == 0 { feeds[0].name().to_string() } else { format!("{} (fallback)", feeds[i].name()) }
+            }
+            Source::Receiver { label, .. } => label.clone(),
+            Source::AircraftJson { location, .. } => location.clone(),
+        }
+    }
+
+    /// Name stored in captures, so a replay knows how to parse the body again
+    pub fn feed_name(&self) -> String {
+        match self {
+            Source::Feed { feeds, active, .. } => feeds[active.load(Ordering::Relaxed)].kind.to_possible_value().map(|v| v.get_name().to_string()).unwrap_or_default(),
+            Source::Receiver { .. } => "receiver".to_string(),
+            Source::AircraftJson { .. } => "aircraft-json".to_string(),
+        }
+    }
+
+    /// Health of the HTTP feeds for the scan header (receivers and files have none)
+    pub fn health(&self) -> Option<String> {
+        let Source::Feed { feeds, .. } = self else {
+            return None;
+        };
+        Some(feeds.iter()
+            .map(|feed| format!("{}: {}", feed.name(), feed.health()))
+            .collect::<Vec<_>>()
+            .join(" | "))
+    }
+
+    /// How long until any feed may be asked again, if all of them sent a Retry-After
+    pub fn paused_for(&self) -> Option<Duration> {
+        let Source::Feed { feeds, .. } = self else {
+            return None;
+        };
+        feeds.iter().map(|feed| feed.paused_for()).collect::<Option<Vec<_>>>()?.into_iter().min()
+    }
+
+    /// Gets the answer to the query as a raw body.
+    /// Receivers hand out their whole picture in the readsb JSON dialect, so every source can be recorded;
+    /// `parse` narrows it down to the query afterwards.
+    pub async fn fetch(&self, query: &Query) -> Result<String, FetchError> {
+        match self {
+            Source::Feed { feeds, active, client, tiling } => {
+                // Feeds that asked us to wait or keep failing go to the back of the line
+                // (stable, so the primary stays first)
+                let mut order: Vec<usize> = (0..feeds.len()).collect();
+                order.sort_by_key(|&i| (feeds[i].paused_for().is_some(), feeds[i].is_down()));
+
+                let mut last_error = None;
+                for i in order {
+                    if let Some(e) = &last_error {
+                        eprintln!("{}, trying {} instead", e, feeds[i].name());
+                    }
+
+                    let result = match query {
+                        Query::Area(polygon) => fetch_area(&feeds[i], client, polygon, tiling).await,
+                        _ => feeds[i].fetch(client, query).await,
+                    };
+                    match result {
+                        Ok(body) => {
+                            active.store(i, Ordering::Relaxed);
+                            return Ok(body);
+                        }
+                        Err(e) => last_error = Some(e),
+                    }
+                }
+                Err(last_error.expect("there is always a primary feed"))
+            }
+            Source::Receiver { state, live, .. } => {
+                let mut state = state.lock().unwrap();
+                let aircraft = if *live {
+                    let now = beast::unix_now();
+                    state.expire(now, RECEIVER_MAX_AGE);
+                    state.snapshot(now, RECEIVER_MAX_AGE)
+                } else {
+                    // A recorded file is one fixed picture, show everything in it
+                    state.snapshot(f64::MAX, f64::MAX)
+                };
+                serde_json::to_string(&AirplanesLiveResponse { ac: Some(aircraft), missing: Vec::new() })
+                    .map_err(|e| FetchError::Json(e.into()))
+            }
+            Source::AircraftJson { location, client } => {
+                if location.starts_with("http://") || location.starts_with("https://") {
+                    let resp = client.get(location).send().await.map_err(FetchError::Connection)?;
+                    provider::read_body(resp).await
+                } else {
+                    tokio::fs::read_to_string(location).await.map_err(FetchError::File)
+                }
+            }
+        }
+    }
+
+    /// Turns a body from `fetch` into the aircraft that answer the query.
+    pub fn parse(&self, body: &str, query: &Query, lookups: &Lookups) -> Result<Vec<Aircraft>, FetchError> {
+        match self {
+            Source::Feed { feeds, active, .. } if !matches!(query, Query::Area(_)) => {
+                let feed = &feeds[active.load(Ordering::Relaxed)];
+                let aircraft = feed.provider.parse(body).map_err(FetchError::Json)?;
+                Ok(cut_to_circle(feed.kind, aircraft, query, lookups))
+            }
+            _ => parse_body(&self.feed_name(), body, query, lookups),
+        }
+    }
+}
+
+/// Parses a body recorded from the given feed (see `Source::feed_name`).
+pub fn parse_body(feed: &str, body: &str, query: &Query, lookups: &Lookups) -> Result<Vec<Aircraft>, FetchError> {
+    match feed {
+        "receiver" | "aircraft-json" => {
+            let mut aircraft = provider::parse_readsb(body).map_err(FetchError::Json)?;
+
+            // These are not filtered at the source. Receivers only hear their surroundings anyway,
+            // so there we keep aircraft without a position in sector scans.
+            let sector_scan = matches!(query, Query::Point { .. });
+            if feed == "aircraft-json" || !sector_scan {
+                aircraft.retain(|ac| query.matches(ac, lookups));
+            }
+            Ok(aircraft)
+        }
+        // Tiled area scans are stored merged, in the readsb dialect (see `fetch_area`)
+        _ if matches!(query, Query::Area(_)) => {
+            let mut aircraft = provider::parse_readsb(body).map_err(FetchError::Json)?;
+            aircraft.retain(|ac| query.matches(ac, lookups));
+            Ok(aircraft)
+        }
+        _ => {
+            // Everything else was one of the HTTP providers
+            // A feed we don't know (renamed, or a newer version) would be read with the wrong schema
+            let kind = ProviderKind::from_str(feed, true)
+                .map_err(|_| FetchError::Unsupported(format!("unknown feed '{}', can't parse its answers", feed)))?;
+            let aircraft = provider::build_provider(kind, None, None).parse(body).map_err(FetchError::Json)?;
+            Ok(cut_to_circle(kind, aircraft, query, lookups))
+        }
+    }
+}
+
+/// What the scan in this body looked at. Only area scans can miss parts: tiles that failed,
+/// or the whole area when the answer is empty.
+pub fn coverage(body: &str, query: &Query) -> Coverage {
+    #[derive(Deserialize)]
+    struct Scanned {
+        #[serde(alias = "aircraft")]
+        ac: Option<Vec<IgnoredAny>>,
+        #[serde(default)]
+        missing: Vec<Tile>,
+    }
+
+    if !matches!(query, Query::Area(_)) {
+        return Coverage::All;
+    }
+    match serde_json::from_str::<Scanned>(body) {
+        Ok(scanned) if scanned.ac.as_ref().is_none_or(|ac| ac.is_empty()) => Coverage::Nothing,
+        Ok(scanned) if !scanned.missing.is_empty() => Coverage::AllBut(scanned.missing),
+        // a body that doesn't parse has no aircraft to show anyway
+        _ => Coverage::All,
+    }
+}
+
+// OpenSky only knows bounding boxes, so a point query gets the corners of the box too
+fn cut_to_circle(kind: ProviderKind, mut aircraft: Vec<Aircraft>, query: &Query, lookups: &Lookups) -> Vec<Aircraft> {
+    if kind == ProviderKind::Opensky && matches!(query, Query::Point { .. }) {
+        aircraft.retain(|ac| query.matches(ac, lookups));
+    }
+    aircraft
+}
+
+/// Scans an area as overlapping point queries (rate limited, a few at a time)
+/// and merges the answers into one readsb style body.
+/// Aircraft seen by several tiles are kept once, with the freshest position.
+async fn fetch_area(feed: &Arc<Feed>, client: &Client, polygon: &Polygon, tiling: &Tiling) -> Result<String, FetchError> {
+    let radius = tiling.radius;
+    let centers = area::tile_centers(polygon, radius);
+    let total = centers.len();
+
+    let permits = Arc::new(Semaphore::new(tiling.concurrency.max(1)));
+    let mut ticker = tokio::time::interval(Duration::from_secs_f64(1.0 / tiling.rate.max(0.01)));
+    let mut tasks = JoinSet::new();
+
+    for &(lat, lon) in &centers {
+        ticker.tick().await; // the first tick is immediate
+        let permit = permits.clone().acquire_owned().await.expect("semaphore is never closed");
+        let feed = feed.clone();
+        let client = client.clone();
+
+        tasks.spawn(async move {
+            let _permit = permit; // held until this tile is done
+            let query = Query::Point { lat, lon, radius };
+            let aircraft = match feed.fetch(&client, &query).await {
+                Ok(body) => feed.provider.parse(&body).map_err(FetchError::Json),
+                Err(e) => Err(e),
+            };
+            (Tile { lat, lon, radius }, aircraft)
+        });
+    }
+
+    let mut merged: HashMap<String, Aircraft> = HashMap::new();
+    let mut answered = Vec::new();
+    let mut last_error = None;
+
+    while let Some(result) = tasks.join_next().await {
+        match result {
+            Ok((tile, Ok(aircraft))) => {
+                merge(&mut merged, aircraft);
+                answered.push(tile);
+            }
+            Ok((_, Err(e))) => last_error = Some(e),
+            Err(e) => last_error = Some(FetchError::Unsupported(format!("tile task failed: {}", e))),
+        }
+    }
+
+    // A few broken tiles still leave a useful picture, all of them don't.
+    // The broken ones go into the body, so the track history (live or replayed) knows what it didn't see.
+    let missing: Vec<Tile> = centers.iter()
+        .map(|&(lat, lon)| Tile { lat, lon, radius })
+        .filter(|tile| !answered.contains(tile))
+        .collect();
+    if let Some(e) = last_error {
+        if missing.len() == total {
+            return Err(e);
+        }
+        eprintln!("{} of {} tiles failed, last one with {}", missing.len(), total, e);
+    }
+
+    let aircraft: Vec<Aircraft> = merged.into_values().collect();
+    serde_json::to_string(&AirplanesLiveResponse { ac: Some(aircraft), missing })
+        .map_err(|e| FetchError::Json(e.into()))
+}
+
+// Adds the aircraft of one tile, keeping the better report of those seen before
+fn merge(merged: &mut HashMap<String, Aircraft>, aircraft: Vec<Aircraft>) {
+    for ac in aircraft {
+        match merged.get(&ac.icao) {
+            Some(known) if !is_fresher(&ac, known) => {}
+            _ => {
+                merged.insert(ac.icao.clone(), ac);
+            }
+        }
+    }
+}
+
+// Is `new` a better report of the same aircraft than `known`? Having a position beats not having one.
+fn is_fresher(new: &Aircraft, known: &Aircraft) -> bool {
+    match (new.lat.is_some(), known.lat.is_some()) {
+        (true, false) => true,
+        (false, true) => false,
+        _ => new.seen_pos.unwrap_or(f64::MAX) < known.seen_pos.unwrap_or(f64::MAX),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tiles_keep_the_freshest_copy() {
+        let seen = |icao: &str, lat: Option<f64>, seen_pos: Option<f64>| Aircraft {
+            icao: icao.to_string(), lat, lon: lat, seen_pos, ..Default::default()
+        };
+        let mut merged = HashMap::new();
+        merge(&mut merged, vec![seen("aaaaaa", Some(52.0), Some(5.0)), seen("bbbbbb", None, None), seen("cccccc", Some(52.0), Some(1.0))]);
+        merge(&mut merged, vec![seen("aaaaaa", Some(52.1), Some(2.0)), seen("bbbbbb", Some(52.2), Some(30.0)), seen("cccccc", Some(52.3), Some(4.0))]);
+        merge(&mut merged, vec![seen("aaaaaa", None, None), seen("cccccc", Some(52.4), None)]);
+
+        assert_eq!(merged.len(), 3);
+        // newer position wins, any position beats none, an unknown age loses
+        assert_eq!(merged["aaaaaa"].lat, Some(52.1));
+        assert_eq!(merged["bbbbbb"].lat, Some(52.2));
+        assert_eq!(merged["cccccc"].lat, Some(52.0));
+    }
+
+    #[test]
+    fn opensky_point_answers_are_cut_to_the_circle() {
+        // one aircraft 30nm north, one in the north-east corner of the box (~135nm out)
+        let body = r#"{"time":0,"states":[
+            ["4840d6","KLM123 ","Netherlands",0,0,4.0,52.5,10000,false,200,0,0,null,10000,"1000",false,0],
+            ["3c6444","DLH4AB ","Germany",0,0,6.6,53.6,10000,false,200,0,0,null,10000,"1000",false,0]
+        ]}"#;
+        let point = Query::Point { lat: 52.0, lon: 4.0, radius: 100.0 };
+        let aircraft = parse_body("opensky", body, &point, &Lookups::built_in()).unwrap();
+        assert_eq!(aircraft.iter().map(|ac| ac.icao.as_str()).collect::<Vec<_>>(), ["4840d6"]);
+
+        // a hex query has no circle
+        let hex = Query::Hex("4840d6,3c6444".to_string());
+        assert_eq!(parse_body("opensky", body, &hex, &Lookups::built_in()).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn unknown_feeds_are_an_error() {
+        let body = r#"{"ac":[{"hex":"4840d6","lat":52.1,"lon":4.1}],"now":0}"#;
+        let point = Query::Point { lat: 52.0, lon: 4.0, radius: 250.0 };
+        assert_eq!(parse_body("airplanes-live", body, &point, &Lookups::built_in()).unwrap().len(), 1);
+        match parse_body("airplanes.live", body, &point, &Lookups::built_in()) {
+            Err(FetchError::Unsupported(msg)) => assert!(msg.contains("'airplanes.live'")),
+            other => panic!("expected an unknown feed, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn mil_takes_the_hex_ranges_without_a_flag() {
+        // a receiver sets no military flag: one in the US military block, one with the dbFlags bit, one airliner
+        let body = r#"{"now":0,"aircraft":[
+            {"hex":"ae1234","lat":52.1,"lon":4.1},
+            {"hex":"3c0001","lat":52.2,"lon":4.2,"dbFlags":1},
+            {"hex":"4840d6","lat":52.3,"lon":4.3}
+        ]}"#;
+        let aircraft = parse_body("receiver", body, &Query::Mil, &Lookups::built_in()).unwrap();
+        assert_eq!(aircraft.iter().map(|ac| ac.icao.as_str()).collect::<Vec<_>>(), ["ae1234", "3c0001"]);
+    }
+
+    #[test]
+    fn area_coverage_comes_with_the_body() {
+        let area = Query::Area(vec![(52.0, 4.0), (52.0, 6.0), (54.0, 6.0), (54.0, 4.0)]);
+        let tile = Tile { lat: 53.0, lon: 5.0, radius: 60.0 };
+        let body = serde_json::to_string(&AirplanesLiveResponse {
+            ac: Some(vec![Aircraft { icao: "4840d6".to_string(), ..Default::default() }]),
+            missing: vec![tile],
+        }).unwrap();
+        assert_eq!(coverage(&body, &area), Coverage::AllBut(vec![tile]));
+        assert_eq!(coverage(r#"{"ac":[{"hex":"4840d6"}],"now":0}"#, &area), Coverage::All);
+
+        // an empty area is no news, an empty point query is
+        assert_eq!(coverage(r#"{"ac":[],"now":0}"#, &area), Coverage::Nothing);
+        assert_eq!(coverage(r#"{"ac":[],"now":0}"#, &Query::Point { lat: 52.0, lon: 4.0, radius: 250.0 }), Coverage::All);
+    }
+}
@@ -0,0 +1,191 @@
+use std::error::Error;
+use crate::lookups;
+use crate::models::Severity;
+
+/// Optional file with more codes, same columns as the built-in table: code,label,severity
+pub const DEFAULT_TABLE: &str = "squawks.csv";
+
+// Codes everybody should know about, plus some national blocks.
+// Code ranges are inclusive and written like squawks ("4400-4477").
+const BUILT_IN: [(&str, &str, Severity); 14] = [
+    ("7500", "HIJACK", Severity::Emergency),
+    ("7600", "RADIO FAILURE", Severity::Emergency),
+    ("7700", "EMERGENCY", Severity::Emergency),
+    ("7400", "UAV lost link", Severity::Warning),
+    ("7777", "Military interceptor (US)", Severity::Warning),
+    ("7004", "Aerobatics and display (UK)", Severity::Normal),
+    ("4400-4477", "Special ops, high altitude (US)", Severity::Warning),
+    ("5000", "NORAD (US)", Severity::Normal),
+    ("1277", "Search and rescue (US)", Severity::Normal),
+    ("7001", "Military low-level (UK)", Severity::Normal),
+    ("7007", "Open Skies observation flight (UK)", Severity::Warning),
+    ("0033", "Parachute dropping (UK)", Severity::Normal),
+    ("7003", "Red Arrows display (UK)", Severity::Normal),
+    ("0100", "NATO, under control of an AEW aircraft", Severity::Warning),
+];
+
+/// One line of the squawk table.
+#[derive(Debug, Clone)]
+pub struct NotableCode {
+    pub code: String, // "7700" or a range "4400-4477"
+    pub label: String,
+    pub severity: Severity,
+}
+
+impl NotableCode {
+    fn matches(&self, squawk: &str) -> bool {
+        // Squawks are always 4 octal digits, so comparing the strings keeps the order right
+        match self.code.split_once('-') {
+            Some((from, to)) => squawk >= from.trim() && squawk <= to.trim(),
+            None => squawk == self.code.trim(),
+        }
+    }
+}
+
+/// The codes we alert on: the user's own file first (so it can override), then the built-in list.
+pub struct SquawkTable {
+    codes: Vec<NotableCode>,
+}
+
+impl SquawkTable {
+    /// Reads the user's codes (--squawk-table or squawks.csv) in front of the built-in ones.
+    pub fn load(path: Option<&str>) -> Result<Self, Box<dyn Error>> {
+        let mut codes = Vec::new();
+
+        let file_path = path.unwrap_or(DEFAULT_TABLE);
+        let file = lookups::open_optional(path, DEFAULT_TABLE).map_err(|e| format!("Squawk table '{}': {}", file_path, e))?;
+        if let Some(file) = file {
+            let mut rdr = csv::ReaderBuilder::new().flexible(true).from_reader(file);
+            for record in rdr.records() {
+                let record = match record {
+                    Ok(r) => r,
+                    Err(e) => {
+                        eprintln!("Skipping broken squawk line: {}", e);
+                        continue;
+                    }
+                };
+                let (Some(code), Some(label)) = (record.get(0), record.get(1)) else {
+                    continue;
+                };
+                // the severity column may be left out
+                let severity = Severity::parse(record.get(2).unwrap_or(""));
+                codes.push(NotableCode { code: code.trim().to_string(), label: label.trim().to_string(), severity });
+            }
+            println!("Loaded {} notable squawk codes from '{}'.", codes.len(), file_path);
+        }
+
+        codes.extend(Self::built_in().codes);
+        Ok(Self { codes })
+    }
+
+    /// Only the codes that ship with the tool
+    pub fn built_in() -> Self {
+        let codes = BUILT_IN.iter().map(|(code, label, severity)| NotableCode {
+            code: code.to_string(),
+            label: label.to_string(),
+            severity: *severity,
+        }).collect();
+        Self { codes }
+    }
+
+    /// Finds the entry for a squawk (as sent by the feed)
+    pub fn lookup(&self, squawk: &str) -> Option<&NotableCode> {
+        let squawk = normalize(squawk)?;
+        self.codes.iter().find(|c| c.matches(&squawk))
+    }
+}
+
+/// Brings a squawk into the "0356" form. Some feeds send it as a number, which drops the leading zeros.
+pub fn normalize(raw: &str) -> Option<String> {
+    let raw = raw.trim();
+    if raw.is_empty() || raw.len() > 4 || !raw.chars().all(|c| ('0'..='7').contains(&c)) {
+        return None; // not a valid transponder code
+    }
+    Some(format!("{:0>4}", raw))
+}
+
+/// The emergency state that 7500, 7600 and 7700 stand for (same names as the `emergency` field)
+pub fn implied_emergency(squawk: &str) -> Option<&'static str> {
+    match normalize(squawk)?.as_str() {
+        "7500" => Some("unlawful"),
+        "7600" => Some("nordo"),
+        "7700" => Some("general"),
+        _ => None,
+    }
+}
+
+/// How bad an `emergency` state from the feed is (None for "none" and unknown values)
+pub fn emergency_severity(state: &str) -> Option<Severity> {
+    match state.trim().to_lowercase().as_str() {
+        "general" | "minfuel" | "nordo" | "unlawful" | "downed" => Some(Severity::Emergency),
+        "lifeguard" => Some(Severity::Warning), // medical flight, priority but no emergency
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    // Loads a table from CSV lines (after the header), the built-in codes come after them
+    fn table(name: &str, lines: &[&str]) -> SquawkTable {
+        let path = std::env::temp_dir().join(format!("{}-{}.csv", name, std::process::id()));
+        fs::write(&path, format!("code,label,severity\n{}", lines.join("\n"))).unwrap();
+        let table = SquawkTable::load(path.to_str()).unwrap();
+        fs::remove_file(&path).unwrap();
+        table
+    }
+
+    fn label<'a>(table: &'a SquawkTable, squawk: &str) -> Option<&'a str> {
+        table.lookup(squawk).map(|c| c.label.as_str())
+    }
+
+    #[test]
+    fn squawks_are_normalized_to_four_octal_digits() {
+        assert_eq!(normalize("356").as_deref(), Some("0356"));
+        assert_eq!(normalize(" 21 ").as_deref(), Some("0021"));
+        assert_eq!(normalize("7700").as_deref(), Some("7700"));
+        assert_eq!(normalize("7800"), None); // 8 is no octal digit
+        assert_eq!(normalize("12a4"), None);
+        assert_eq!(normalize("12345"), None);
+        assert_eq!(normalize(""), None);
+    }
+
+    #[test]
+    fn ranges_include_both_ends() {
+        let table = table("squawk-ranges", &[]);
+        assert_eq!(label(&table, "4400"), Some("Special ops, high altitude (US)"));
+        assert_eq!(label(&table, "4437"), Some("Special ops, high altitude (US)"));
+        assert_eq!(label(&table, "4477"), Some("Special ops, high altitude (US)"));
+        assert_eq!(label(&table, "4377"), None);
+        assert_eq!(label(&table, "4500"), None);
+        // the feed dropped the leading zeros
+        assert_eq!(label(&table, "33"), Some("Parachute dropping (UK)"));
+        assert_eq!(label(&table, "9999"), None);
+    }
+
+    #[test]
+    fn user_codes_win_over_the_built_in_ones() {
+        let table = table("squawk-user", &["7700,Exercise emergency,warning", "4410,Local block,", "1200,VFR,normal"]);
+
+        let own = table.lookup("7700").unwrap();
+        assert_eq!((own.label.as_str(), own.severity), ("Exercise emergency", Severity::Warning));
+        assert_eq!(table.lookup("4410").map(|c| (c.label.as_str(), c.severity)), Some(("Local block", Severity::Normal)));
+        // the rest of the built-in range and codes are still there
+        assert_eq!(label(&table, "4420"), Some("Special ops, high altitude (US)"));
+        assert_eq!(table.lookup("7500").map(|c| c.severity), Some(Severity::Emergency));
+        assert_eq!(label(&table, "1200"), Some("VFR"));
+    }
+
+    #[test]
+    fn emergency_states_have_a_severity() {
+        for state in ["general", "minfuel", "nordo", "unlawful", "downed", "NORDO"] {
+            assert_eq!(emergency_severity(state), Some(Severity::Emergency), "{}", state);
+        }
+        assert_eq!(emergency_severity("lifeguard"), Some(Severity::Warning));
+        assert_eq!(emergency_severity("none"), None);
+        assert_eq!(emergency_severity("reserved"), None);
+        assert_eq!(emergency_severity(""), None);
+    }
+}
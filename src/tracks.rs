@@ -0,0 +1,687 @@
+use std::collections::{HashMap, VecDeque};
+use crate::area::Coverage;
+use crate::events::{EventKind, TrackEvent};
+use crate::geo;
+use crate::models::Aircraft;
+use crate::query::Query;
+
+/// Points kept per aircraft (an hour at one scan every 10 seconds)
+const MAX_POINTS: usize = 360;
+
+/// Position noise (MLAT, rounding) that never counts as a jump (nm)
+const JUMP_SLACK_NM: f64 = 2.0;
+
+/// How much faster than its own ground speed an aircraft may seem to move (timing jitter, turns)
+const JUMP_SPEED_FACTOR: f64 = 1.5;
+
+/// Events kept per aircraft
+const MAX_EVENTS: usize = 50;
+
+/// Scans in a row a new position source has to hold before it counts as a change
+/// (MLAT and ADS-B often take turns for a scan or two)
+const SOURCE_HOLD_SCANS: usize = 3;
+
+/// How long we wait for an aircraft that went dark to show up again (seconds)
+const DARK_MEMORY: f64 = 3600.0;
+
+/// Flagged aircraft that vanish closer than this to the sector edge (nm) just flew out of range
+const DARK_EDGE_MARGIN: f64 = 20.0;
+
+/// How long a reappeared aircraft keeps its REAPPEARED reason (seconds)
+const REAPPEAR_SHOW_SECS: f64 = 600.0;
+
+/// One observation of an aircraft in a scan.
+#[derive(Debug, Clone)]
+pub struct TrackPoint {
+    pub time: f64, // unix seconds, of the position if the feed told us how old it is
+    pub lat: Option<f64>,
+    pub lon: Option<f64>,
+    pub alt: Option<f64>,
+    pub speed: Option<f64>,
+    pub heading: Option<f64>, // track over ground, or heading if there is no track
+    pub source: String,
+}
+
+impl TrackPoint {
+    pub fn position(&self) -> Option<(f64, f64)> {
+        self.lat.zip(self.lon)
+    }
+}
+
+/// A move between two scans, see `Track::position_jump`.
+#[derive(Debug, Clone, Copy)]
+pub struct Jump {
+    pub distance_nm: f64,
+    pub seconds: f64,
+    pub implied_speed: f64, // kts
+    pub reported_speed: Option<f64>, // gs of the newer report (kts)
+    pub standing_still: bool, // gs 0 in both reports
+}
+
+/// Everything we saw of one airframe since it entered the scan.
+#[derive(Debug, Clone)]
+pub struct Track {
+    pub icao: String,
+    pub points: VecDeque<TrackPoint>, // oldest first, bounded
+    pub first_seen: f64,
+    pub last_seen: f64,
+    pub distance_nm: f64, // flown since first seen (also counts points that were dropped already)
+    pub last: Aircraft, // the latest report as the feed sent it
+    pub flagged: bool, // was an aircraft of interest in one of the scans
+    pub reappeared: Option<Reappearance>, // came back after going dark
+    pub events: Vec<TrackEvent>, // callsign, source and registration changes, oldest first
+    source: String, // position source that has held for a while, see `changes`
+    new_source: Option<(String, f64, usize)>, // a different one: since when and for how many scans
+}
+
+/// A flagged aircraft that stopped transmitting well inside the sector.
+#[derive(Debug, Clone)]
+pub struct DarkContact {
+    pub track: Track, // everything up to the last report
+    pub inside_nm: f64, // distance of the last position from the sector edge
+}
+
+/// How long an aircraft was gone and where it showed up again.
+#[derive(Debug, Clone, Copy)]
+pub struct Reappearance {
+    pub gap_secs: f64,
+    pub distance_nm: Option<f64>, // from the last known position, if both have one
+}
+
+/// The part of a track the table and the KML show: the trail plus a few totals.
+#[derive(Debug, Clone)]
+pub struct TrackSummary {
+    pub first_seen: f64,
+    pub time_in_sector: f64,
+    pub distance_nm: f64,
+    pub points: usize,
+    pub sources: String,
+    pub events: Vec<String>, // stamped, oldest first
+    pub trail: Vec<(f64, f64, f64)>, // lon, lat, alt in ft
+}
+
+impl Track {
+    fn new(ac: &Aircraft, now: f64) -> Self {
+        Self {
+            icao: ac.icao.clone(),
+            points: VecDeque::new(),
+            first_seen: now,
+            last_seen: now,
+            distance_nm: 0.0,
+            last: ac.clone(),
+            flagged: false,
+            reappeared: None,
+            events: Vec::new(),
+            source: ac.source_type.clone(),
+            new_source: None,
+        }
+    }
+
+    // What changed since the previous report (call before `push`)
+    fn changes(&mut self, ac: &Aircraft, now: f64) -> Vec<TrackEvent> {
+        let mut events = Vec::new();
+        let event = |kind, from: String, to: String| TrackEvent { time: now, icao: self.icao.clone(), kind, from, to };
+
+        // A report without a callsign is no change
+        let text = |s: &Option<String>| s.as_deref().map(|s| s.trim().to_uppercase()).filter(|s| !s.is_empty());
+        if let (Some(from), Some(to)) = (text(&self.last.callsign), text(&ac.callsign))
+            && from != to {
+            events.push(event(EventKind::Callsign, from, to));
+        }
+
+        // A new source only counts once it held for a few scans, then it changed when it first showed up.
+        // Reports without a source don't count either way.
+        if ac.source_type == self.source {
+            self.new_source = None;
+        } else if self.source.is_empty() {
+            self.source = ac.source_type.clone();
+        } else if !ac.source_type.is_empty() {
+            let (since, scans) = match &self.new_source {
+                Some((source, since, scans)) if *source == ac.source_type => (*since, scans + 1),
+                _ => (now, 1),
+            };
+            if scans >= SOURCE_HOLD_SCANS {
+                let from = std::mem::replace(&mut self.source, ac.source_type.clone());
+                events.push(TrackEvent { time: since, icao: self.icao.clone(), kind: EventKind::Source, from, to: ac.source_type.clone() });
+                self.new_source = None;
+            } else {
+                self.new_source = Some((ac.source_type.clone(), since, scans));
+            }
+        }
+        events
+    }
+
+    fn push(&mut self, ac: &Aircraft, now: f64) {
+        self.last_seen = now;
+        self.last = ac.clone();
+
+        let point = TrackPoint {
+            time: now - ac.seen_pos.unwrap_or(0.0).max(0.0),
+            lat: ac.lat,
+            lon: ac.lon,
+            alt: ac.alt_baro,
+            speed: ac.ground_speed,
+            heading: ac.track.or(ac.true_heading).or(ac.mag_heading),
+            source: ac.source_type.clone(),
+        };
+
+        // The feed didn't get a new position since the last scan: nothing to add
+        if let Some(prev) = self.points.back()
+            && prev.time >= point.time
+            && prev.position() == point.position() {
+            return;
+        }
+
+        if let (Some((lat1, lon1)), Some((lat2, lon2))) = (self.last_position().and_then(TrackPoint::position), point.position()) {
+            self.distance_nm += geo::distance_nm(lat1, lon1, lat2, lon2);
+        }
+
+        if self.points.len() == MAX_POINTS {
+            self.points.pop_front();
+        }
+        self.points.push_back(point);
+    }
+
+    fn add_event(&mut self, event: TrackEvent) {
+        if self.events.len() == MAX_EVENTS {
+            self.events.remove(0);
+        }
+        self.events.push(event);
+    }
+
+    /// The newest point that has a position
+    pub fn last_position(&self) -> Option<&TrackPoint> {
+        self.points.iter().rev().find(|p| p.position().is_some())
+    }
+
+    /// The move between the last two positions, if it is more than the aircraft could have flown.
+    /// `max_speed` (kts) is the limit when the feed sends no ground speed, and the cap otherwise.
+    pub fn position_jump(&self, max_speed: f64) -> Option<Jump> {
+        let jump = self.last_move()?;
+        if jump.distance_nm <= JUMP_SLACK_NM {
+            return None;
+        }
+
+        // Ground speed 0 while the position keeps moving
+        if jump.standing_still {
+            return Some(jump);
+        }
+
+        let limit = jump.reported_speed
+            .map(|gs| (gs * JUMP_SPEED_FACTOR).min(max_speed))
+            .unwrap_or(max_speed);
+        let allowed_nm = limit * jump.seconds / 3600.0 + JUMP_SLACK_NM;
+
+        (jump.distance_nm > allowed_nm).then_some(jump)
+    }
+
+    // Distance and time between the last two points with a position
+    fn last_move(&self) -> Option<Jump> {
+        let mut positioned = self.points.iter().rev().filter(|p| p.position().is_some());
+        let (newer, older) = (positioned.next()?, positioned.next()?);
+        let ((lat1, lon1), (lat2, lon2)) = (older.position()?, newer.position()?);
+
+        let distance_nm = geo::distance_nm(lat1, lon1, lat2, lon2);
+        // Same position age twice: the move still happened, so give it at least a second
+        let seconds = (newer.time - older.time).max(1.0);
+
+        Some(Jump {
+            distance_nm,
+            seconds,
+            implied_speed: distance_nm / seconds * 3600.0,
+            reported_speed: newer.speed,
+            // both reports have to agree the aircraft is standing still
+            standing_still: older.speed.is_some_and(|gs| gs < 1.0) && newer.speed.is_some_and(|gs| gs < 1.0),
+        })
+    }
+
+    /// Position sources in the order they were seen, e.g. "adsb_icao > mlat"
+    pub fn sources(&self) -> String {
+        let mut seen: Vec<&str> = Vec::new();
+        for p in &self.points {
+            if !p.source.is_empty() && seen.last() != Some(&p.source.as_str()) {
+                seen.push(&p.source);
+            }
+        }
+        seen.join(" > ")
+    }
+
+    /// Seconds since the aircraft showed up in our scans
+    pub fn time_in_sector(&self) -> f64 {
+        self.last_seen - self.first_seen
+    }
+
+    /// How it came back after going dark, for the first minutes after it did
+    pub fn recently_reappeared(&self) -> Option<Reappearance> {
+        self.reappeared.filter(|_| self.time_in_sector() <= REAPPEAR_SHOW_SECS)
+    }
+
+    pub fn summary(&self) -> TrackSummary {
+        TrackSummary {
+            first_seen: self.first_seen,
+            time_in_sector: self.time_in_sector(),
+            distance_nm: self.distance_nm,
+            points: self.points.len(),
+            sources: self.sources(),
+            events: self.events.iter().map(|e| e.stamped()).collect(),
+            trail: self.points.iter()
+                .filter_map(|p| p.position().map(|(lat, lon)| (lon, lat, p.alt.unwrap_or(0.0))))
+                .collect(),
+        }
+    }
+}
+
+/// Seconds as "12m 05s"
+pub fn format_duration(secs: f64) -> String {
+    let secs = secs.max(0.0) as u64;
+    format!("{}m {:02}s", secs / 60, secs % 60)
+}
+
+/// Track history of every aircraft across scans, keyed by hex.
+pub struct TrackStore {
+    tracks: HashMap<String, Track>,
+    dark: HashMap<String, DarkContact>,
+    max_age: f64, // seconds without a report before a track is dropped
+    new_events: Vec<TrackEvent>, // not handed out by `take_events` yet
+}
+
+impl TrackStore {
+    pub fn new(max_age: f64) -> Self {
+        Self { tracks: HashMap::new(), dark: HashMap::new(), max_age, new_events: Vec::new() }
+    }
+
+    /// Adds one scan and drops aircraft that haven't been reported for `max_age` seconds.
+    /// Returns the dropped tracks (the aircraft has left, or went quiet). Aircraft last seen
+    /// where the scan didn't look stay until a scan that did.
+    pub fn update(&mut self, aircraft: &[Aircraft], now: f64, coverage: &Coverage) -> Vec<Track> {
+        for ac in aircraft {
+            if !self.tracks.contains_key(&ac.icao) {
+                let mut track = Track::new(ac, now);
+
+                // Back after going dark: how long, and how far from where we lost it
+                if let Some(dark) = self.dark.remove(&ac.icao) {
+                    let distance_nm = dark.track.last_position()
+                        .and_then(TrackPoint::position)
+                        .zip(ac.lat.zip(ac.lon))
+                        .map(|((lat1, lon1), (lat2, lon2))| geo::distance_nm(lat1, lon1, lat2, lon2));
+                    track.reappeared = Some(Reappearance { gap_secs: now - dark.track.last_seen, distance_nm });
+                }
+                self.tracks.insert(ac.icao.clone(), track);
+            }
+            if let Some(track) = self.tracks.get_mut(&ac.icao) {
+                for event in track.changes(ac, now) {
+                    track.add_event(event.clone());
+                    self.new_events.push(event);
+                }
+                track.push(ac, now);
+            }
+        }
+
+        self.dark.retain(|_, d| now - d.track.last_seen <= DARK_MEMORY);
+
+        let expired: Vec<String> = self.tracks.values()
+            .filter(|t| now - t.last_seen > self.max_age && coverage.covers(t.last_position().and_then(TrackPoint::position)))
+            .map(|t| t.icao.clone())
+            .collect();
+
+        expired.iter().filter_map(|icao| self.tracks.remove(icao)).collect()
+    }
+
+    pub fn get(&self, icao: &str) -> Option<&Track> {
+        self.tracks.get(icao)
+    }
+
+    /// Compares the registration the feed sends with the one in the aircraft database
+    /// (once per track, a mismatch doesn't go away by itself).
+    pub fn check_registration(&mut self, ac: &Aircraft, db_registration: &str, now: f64) {
+        let normalize = |reg: &str| reg.trim().to_uppercase().replace('-', "");
+        let Some(feed_registration) = ac.registration.as_deref().filter(|r| !r.trim().is_empty()) else {
+            return;
+        };
+        if db_registration.trim().is_empty() || normalize(feed_registration) == normalize(db_registration) {
+            return;
+        }
+
+        if let Some(track) = self.tracks.get_mut(&ac.icao)
+            && !track.events.iter().any(|e| e.kind == EventKind::Registration) {
+            let event = TrackEvent {
+                time: now,
+                icao: ac.icao.clone(),
+                kind: EventKind::Registration,
+                from: feed_registration.trim().to_uppercase(),
+                to: db_registration.trim().to_uppercase(),
+            };
+            track.add_event(event.clone());
+            self.new_events.push(event);
+        }
+    }
+
+    /// Events since the last call (for the event log)
+    pub fn take_events(&mut self) -> Vec<TrackEvent> {
+        std::mem::take(&mut self.new_events)
+    }
+
+    /// Remembers that this aircraft was of interest (only those are reported when they go dark)
+    pub fn flag(&mut self, icao: &str) {
+        if let Some(track) = self.tracks.get_mut(icao) {
+            track.flagged = true;
+        }
+    }
+
+    /// Goes through the tracks `update` dropped: aircraft of interest that vanished well inside
+    /// the sector went dark (not just out of range or landed). Those are kept around until they
+    /// show up again (or for an hour). Worldwide queries have no sector, leaving feed coverage
+    /// there is not going dark.
+    pub fn check_dark(&mut self, gone: Vec<Track>, query: &Query) {
+        for track in gone {
+            let Some((lat, lon)) = track.last_position().and_then(TrackPoint::position) else {
+                continue;
+            };
+            let landed = track.last.alt_baro.is_some_and(|alt| alt <= 0.0);
+
+            if let Some(inside_nm) = query.edge_distance(lat, lon)
+                && track.flagged && !landed && inside_nm >= DARK_EDGE_MARGIN {
+                self.dark.insert(track.icao.clone(), DarkContact { track, inside_nm });
+            }
+        }
+    }
+
+    /// Flagged aircraft that stopped transmitting and haven't been seen since
+    pub fn dark(&self) -> impl Iterator<Item = &DarkContact> {
+        self.dark.values()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::area::Tile;
+
+    /// Default of --jump-speed
+    const MAX_SPEED: f64 = 1000.0;
+
+    // A track that flew north from 52N 4E: (seconds, nm north, ground speed)
+    fn flown(moves: &[(f64, f64, Option<f64>)]) -> Track {
+        let mut track = Track::new(&Aircraft::default(), 0.0);
+        for &(time, north_nm, speed) in moves {
+            track.points.push_back(TrackPoint {
+                time,
+                lat: Some(52.0 + north_nm / 60.0),
+                lon: Some(4.0),
+                alt: Some(30000.0),
+                speed,
+                heading: Some(0.0),
+                source: "adsb_icao".to_string(),
+            });
+        }
+        track
+    }
+
+    #[test]
+    fn normal_move_is_no_jump() {
+        // 300 kts for a minute is 5nm
+        let track = flown(&[(0.0, 0.0, Some(300.0)), (60.0, 5.0, Some(300.0))]);
+        assert!(track.position_jump(MAX_SPEED).is_none());
+    }
+
+    #[test]
+    fn impossible_move_is_a_jump() {
+        let track = flown(&[(0.0, 0.0, Some(300.0)), (60.0, 50.0, Some(300.0))]);
+        let jump = track.position_jump(MAX_SPEED).unwrap();
+        assert!((jump.distance_nm - 50.0).abs() < 0.1);
+        assert_eq!(jump.seconds, 60.0);
+        assert!((jump.implied_speed - 3000.0).abs() < 5.0);
+        assert!(!jump.standing_still);
+    }
+
+    #[test]
+    fn only_the_last_two_positions_count() {
+        let track = flown(&[(0.0, 0.0, Some(300.0)), (60.0, 50.0, Some(300.0)), (120.0, 55.0, Some(300.0))]);
+        assert!(track.position_jump(MAX_SPEED).is_none());
+    }
+
+    #[test]
+    fn drift_at_ground_speed_zero() {
+        let track = flown(&[(0.0, 0.0, Some(0.0)), (600.0, 3.0, Some(0.0))]);
+        let jump = track.position_jump(MAX_SPEED).unwrap();
+        assert!(jump.standing_still);
+
+        // Taxiing at 15 kts for 10 minutes is fine
+        let track = flown(&[(0.0, 0.0, Some(15.0)), (600.0, 2.5, Some(15.0))]);
+        assert!(track.position_jump(MAX_SPEED).is_none());
+    }
+
+    #[test]
+    fn mlat_noise_below_the_slack() {
+        // 1.5nm in a second, but that is just noise
+        let track = flown(&[(0.0, 0.0, None), (1.0, 1.5, None)]);
+        assert!(track.position_jump(MAX_SPEED).is_none());
+
+        let track = flown(&[(0.0, 0.0, Some(0.0)), (600.0, 1.5, Some(0.0))]);
+        assert!(track.position_jump(MAX_SPEED).is_none());
+    }
+
+    #[test]
+    fn slack_is_added_to_the_allowed_distance() {
+        // 300 kts * 1.5 for a minute = 7.5nm, plus 2nm slack
+        let track = flown(&[(0.0, 0.0, Some(300.0)), (60.0, 9.4, Some(300.0))]);
+        assert!(track.position_jump(MAX_SPEED).is_none());
+
+        let track = flown(&[(0.0, 0.0, Some(300.0)), (60.0, 9.6, Some(300.0))]);
+        assert!(track.position_jump(MAX_SPEED).is_some());
+    }
+
+    #[test]
+    fn own_ground_speed_gets_a_factor_of_one_and_a_half() {
+        // 8nm in a minute is 480 kts: too fast for 300 kts flat out, fine with the factor
+        let track = flown(&[(0.0, 0.0, Some(300.0)), (60.0, 8.0, Some(300.0))]);
+        assert!(track.position_jump(MAX_SPEED).is_none());
+
+        let track = flown(&[(0.0, 0.0, Some(200.0)), (60.0, 8.0, Some(200.0))]);
+        assert!(track.position_jump(MAX_SPEED).is_some());
+    }
+
+    #[test]
+    fn max_speed_caps_the_reported_speed_and_fills_in_for_none() {
+        // Reports 2000 kts, but only 1000 are believable: 16.7 + 2nm allowed in a minute
+        let track = flown(&[(0.0, 0.0, Some(2000.0)), (60.0, 20.0, Some(2000.0))]);
+        assert!(track.position_jump(MAX_SPEED).is_some());
+
+        let track = flown(&[(0.0, 0.0, None), (60.0, 18.0, None)]);
+        assert!(track.position_jump(MAX_SPEED).is_none());
+        let track = flown(&[(0.0, 0.0, None), (60.0, 19.0, None)]);
+        assert!(track.position_jump(MAX_SPEED).is_some());
+    }
+
+    #[test]
+    fn same_position_age_counts_as_one_second() {
+        // 1000 kts for a second is 0.28nm, so 2.1nm is still within the slack
+        let track = flown(&[(10.0, 0.0, None), (10.0, 2.1, None)]);
+        assert!(track.position_jump(MAX_SPEED).is_none());
+
+        let track = flown(&[(10.0, 0.0, None), (10.0, 3.0, None)]);
+        let jump = track.position_jump(MAX_SPEED).unwrap();
+        assert_eq!(jump.seconds, 1.0);
+    }
+
+    // Track store tests: one scan every 10 seconds in a 100nm sector around 52N 4E
+
+    /// Tracks are dropped after this long without a report
+    const MAX_AGE: f64 = 120.0;
+
+    fn sector() -> Query {
+        Query::Point { lat: 52.0, lon: 4.0, radius: 100.0 }
+    }
+
+    fn report(north_nm: f64, alt: f64) -> Aircraft {
+        Aircraft {
+            icao: "abc123".to_string(),
+            lat: Some(52.0 + north_nm / 60.0),
+            lon: Some(4.0),
+            alt_baro: Some(alt),
+            ground_speed: Some(300.0),
+            source_type: "adsb_icao".to_string(),
+            ..Default::default()
+        }
+    }
+
+    // Sees the aircraft from 0 to `until`, flagged or not, then empty scans until it is dropped
+    fn vanish(store: &mut TrackStore, last: &Aircraft, until: f64, flagged: bool) -> f64 {
+        vanish_from(store, last, until, flagged, &sector())
+    }
+
+    fn vanish_from(store: &mut TrackStore, last: &Aircraft, until: f64, flagged: bool, query: &Query) -> f64 {
+        let mut now = 0.0;
+        while now <= until {
+            store.update(std::slice::from_ref(last), now, &Coverage::All);
+            if flagged {
+                store.flag(&last.icao);
+            }
+            now += 10.0;
+        }
+        while store.get(&last.icao).is_some() {
+            let gone = store.update(&[], now, &Coverage::All);
+            store.check_dark(gone, query);
+            now += 10.0;
+        }
+        now
+    }
+
+    #[test]
+    fn flagged_aircraft_vanishing_inside_the_sector_went_dark() {
+        let mut store = TrackStore::new(MAX_AGE);
+        vanish(&mut store, &report(0.0, 20000.0), 300.0, true);
+
+        let dark: Vec<&DarkContact> = store.dark().collect();
+        assert_eq!(dark.len(), 1);
+        assert!((dark[0].inside_nm - 100.0).abs() < 0.5);
+        assert_eq!(dark[0].track.last_seen, 300.0);
+    }
+
+    #[test]
+    fn vanishing_at_the_edge_just_left_the_sector() {
+        let mut store = TrackStore::new(MAX_AGE);
+        vanish(&mut store, &report(85.0, 20000.0), 300.0, true);
+        assert_eq!(store.dark().count(), 0);
+    }
+
+    #[test]
+    fn leaving_a_worldwide_query_is_not_going_dark() {
+        let mut store = TrackStore::new(MAX_AGE);
+        vanish_from(&mut store, &report(0.0, 20000.0), 300.0, true, &Query::Mil);
+        assert_eq!(store.dark().count(), 0);
+    }
+
+    #[test]
+    fn landing_is_not_going_dark() {
+        let mut store = TrackStore::new(MAX_AGE);
+        vanish(&mut store, &report(0.0, 0.0), 300.0, true);
+        assert_eq!(store.dark().count(), 0);
+    }
+
+    #[test]
+    fn only_flagged_aircraft_go_dark() {
+        let mut store = TrackStore::new(MAX_AGE);
+        vanish(&mut store, &report(0.0, 20000.0), 300.0, false);
+        assert_eq!(store.dark().count(), 0);
+    }
+
+    #[test]
+    fn no_news_from_where_the_scan_didnt_look() {
+        let mut store = TrackStore::new(MAX_AGE);
+        let ac = report(0.0, 20000.0);
+        store.update(std::slice::from_ref(&ac), 0.0, &Coverage::All);
+        store.flag(&ac.icao);
+
+        // the tile around it failed, then a scan came back empty: it's overdue, but not gone
+        let failed = Coverage::AllBut(vec![Tile { lat: 52.0, lon: 4.0, radius: 50.0 }]);
+        assert!(store.update(&[], 200.0, &failed).is_empty());
+        assert!(store.update(&[], 210.0, &Coverage::Nothing).is_empty());
+        assert!(store.get(&ac.icao).is_some());
+
+        // the first scan that looked there drops it
+        let gone = store.update(&[], 220.0, &Coverage::All);
+        store.check_dark(gone, &sector());
+        assert_eq!(store.dark().count(), 1);
+    }
+
+    #[test]
+    fn reappearing_after_a_gap() {
+        let mut store = TrackStore::new(MAX_AGE);
+        vanish(&mut store, &report(0.0, 20000.0), 300.0, true);
+
+        // Back 30 minutes after the last report, 12nm further north
+        store.update(&[report(12.0, 20000.0)], 2100.0, &Coverage::All);
+        assert_eq!(store.dark().count(), 0);
+
+        let back = store.get("abc123").unwrap().recently_reappeared().unwrap();
+        assert_eq!(back.gap_secs, 1800.0);
+        assert!((back.distance_nm.unwrap() - 12.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn reappeared_is_shown_for_ten_minutes() {
+        let mut store = TrackStore::new(MAX_AGE);
+        vanish(&mut store, &report(0.0, 20000.0), 300.0, true);
+
+        let mut now = 2100.0;
+        while now <= 2100.0 + REAPPEAR_SHOW_SECS {
+            store.update(&[report(12.0, 20000.0)], now, &Coverage::All);
+            assert!(store.get("abc123").unwrap().recently_reappeared().is_some(), "at {}", now);
+            now += 10.0;
+        }
+        store.update(&[report(12.0, 20000.0)], now, &Coverage::All);
+        let track = store.get("abc123").unwrap();
+        assert!(track.reappeared.is_some());
+        assert!(track.recently_reappeared().is_none());
+    }
+
+    // Source events of an aircraft reported with these sources, one scan every 10 seconds
+    fn source_events(sources: &[&str]) -> Vec<(f64, String, String)> {
+        let mut store = TrackStore::new(MAX_AGE);
+        for (i, source) in sources.iter().enumerate() {
+            let ac = Aircraft { source_type: source.to_string(), ..report(0.0, 20000.0) };
+            store.update(&[ac], i as f64 * 10.0, &Coverage::All);
+        }
+        store.take_events().into_iter()
+            .filter(|e| e.kind == EventKind::Source)
+            .map(|e| (e.time, e.from, e.to))
+            .collect()
+    }
+
+    #[test]
+    fn source_change_counts_once_it_holds() {
+        let events = source_events(&["adsb_icao", "adsb_icao", "mlat", "mlat", "mlat", "mlat"]);
+        assert_eq!(events, [(20.0, "adsb_icao".to_string(), "mlat".to_string())]);
+    }
+
+    #[test]
+    fn flapping_sources_are_no_change() {
+        let events = source_events(&["adsb_icao", "mlat", "adsb_icao", "mlat", "mlat", "adsb_icao", "mlat", "adsb_icao"]);
+        assert!(events.is_empty());
+    }
+
+    #[test]
+    fn reports_without_source_dont_break_the_count() {
+        let events = source_events(&["adsb_icao", "mlat", "", "mlat", "mlat", "adsb_icao", "adsb_icao", "adsb_icao"]);
+        assert_eq!(events, [
+            (10.0, "adsb_icao".to_string(), "mlat".to_string()),
+            (50.0, "mlat".to_string(), "adsb_icao".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn dark_aircraft_are_forgotten_after_an_hour() {
+        let mut store = TrackStore::new(MAX_AGE);
+        vanish(&mut store, &report(0.0, 20000.0), 300.0, true);
+
+        store.update(&[], 300.0 + DARK_MEMORY, &Coverage::All);
+        assert_eq!(store.dark().count(), 1);
+        store.update(&[], 300.0 + DARK_MEMORY + 10.0, &Coverage::All);
+        assert_eq!(store.dark().count(), 0);
+
+        // Coming back after that is a new aircraft
+        store.update(&[report(0.0, 20000.0)], 300.0 + DARK_MEMORY + 20.0, &Coverage::All);
+        assert!(store.get("abc123").unwrap().reappeared.is_none());
+    }
+}
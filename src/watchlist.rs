@@ -0,0 +1,323 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::Read;
+use regex::{Regex, RegexBuilder};
+use serde::Deserialize;
+use crate::models::{Aircraft, Severity};
+use crate::reason::{Category, Finding, Reason};
+
+/// Picked up from the working directory if no --watchlist is given
+pub const DEFAULT_FILES: [&str; 2] = ["watchlist.csv", "watchlist.toml"];
+
+/// Priority of a watchlist entry, sets the score of its reason.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    #[default]
+    High,
+    Medium,
+    Low,
+}
+
+impl Priority {
+    fn parse(text: &str) -> Option<Self> {
+        match text.trim().to_lowercase().as_str() {
+            "high" | "" => Some(Priority::High),
+            "medium" => Some(Priority::Medium),
+            "low" => Some(Priority::Low),
+            _ => None,
+        }
+    }
+
+    fn score(&self) -> f64 {
+        match self {
+            Priority::High => 100.0,
+            Priority::Medium => 60.0,
+            Priority::Low => 30.0,
+        }
+    }
+}
+
+/// A hex code, registration or callsign to look for: a glob ("SAM*", "ae01?e")
+/// or a regex between slashes ("/^RCH\d{3}$/"). Case doesn't matter.
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    regex: Regex,
+}
+
+impl Pattern {
+    pub fn parse(text: &str) -> Result<Self, Box<dyn Error>> {
+        let text = text.trim();
+        let source = match text.strip_prefix('/').and_then(|t| t.strip_suffix('/')) {
+            Some(regex) => regex.to_string(),
+            None => {
+                // glob: * is anything, ? one character, the rest literally
+                let mut regex = String::from("^");
+                for c in text.chars() {
+                    match c {
+                        '*' => regex.push_str(".*"),
+                        '?' => regex.push('.'),
+                        c => regex.push_str(&regex::escape(&c.to_string())),
+                    }
+                }
+                regex.push('$');
+                regex
+            }
+        };
+        let regex = RegexBuilder::new(&source).case_insensitive(true).build()?;
+        Ok(Self { regex })
+    }
+
+    pub fn matches(&self, value: Option<&str>) -> bool {
+        value.map(str::trim).is_some_and(|v| !v.is_empty() && self.regex.is_match(v))
+    }
+}
+
+/// One airframe (or group of them) to follow.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub hex: Option<Pattern>,
+    pub registration: Option<Pattern>,
+    pub callsign: Option<Pattern>,
+    pub label: String,
+    pub priority: Priority,
+    pub notes: String,
+}
+
+impl Entry {
+    // Every pattern the entry has must match
+    fn matches(&self, ac: &Aircraft, registration: Option<&str>) -> bool {
+        self.hex.as_ref().is_none_or(|p| p.matches(Some(&ac.icao)))
+            && self.registration.as_ref().is_none_or(|p| p.matches(registration))
+            && self.callsign.as_ref().is_none_or(|p| p.matches(ac.callsign.as_deref()))
+    }
+
+    /// "WATCHLIST: <label>", high priority entries as a warning
+    pub fn reason(&self) -> Reason {
+        let severity = if self.priority == Priority::High { Severity::Warning } else { Severity::Normal };
+        let mut reason = Reason::new(Finding::Watchlist { label: self.label.clone() }, Category::Watchlist, severity);
+        reason.score = self.priority.score();
+        reason.notes = Some(self.notes.clone()).filter(|n| !n.is_empty());
+        reason
+    }
+}
+
+// A line of a TOML watchlist: [[watch]] hex = "ae01ce", label = "...", ...
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TomlEntry {
+    hex: Option<String>,
+    registration: Option<String>,
+    callsign: Option<String>,
+    label: String,
+    #[serde(default)]
+    priority: Priority,
+    #[serde(default)]
+    notes: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TomlFile {
+    #[serde(default)]
+    watch: Vec<TomlEntry>,
+}
+
+/// The airframes we follow, from all watchlist files.
+#[derive(Default)]
+pub struct Watchlist {
+    entries: Vec<Entry>,
+}
+
+impl Watchlist {
+    /// Loads the given files (TOML if the name ends in .toml, CSV otherwise),
+    /// or watchlist.csv/watchlist.toml if they exist.
+    pub fn load(paths: &[String]) -> Result<Self, Box<dyn Error>> {
+        let mut entries = Vec::new();
+
+        let defaults: Vec<String> = DEFAULT_FILES.iter()
+            .filter(|f| std::path::Path::new(f).exists())
+            .map(|f| f.to_string())
+            .collect();
+        let files = if paths.is_empty() { &defaults } else { paths };
+
+        for file_path in files {
+            let loaded = if file_path.ends_with(".toml") {
+                std::fs::read_to_string(file_path).map_err(Box::from).and_then(|content| Self::parse_toml(&content))
+            } else {
+                File::open(file_path).map_err(Box::from).map(Self::read_csv)
+            }.map_err(|e| format!("Watchlist '{}': {}", file_path, e))?;
+
+            println!("Loaded {} watchlist entries from '{}'.", loaded.len(), file_path);
+            entries.extend(loaded);
+        }
+
+        Ok(Self { entries })
+    }
+
+    // Columns: field,pattern,label,priority,notes (field is hex, registration or callsign)
+    fn read_csv(reader: impl Read) -> Vec<Entry> {
+        let mut entries = Vec::new();
+        let mut rdr = csv::ReaderBuilder::new().flexible(true).from_reader(reader);
+
+        for record in rdr.records() {
+            let record = match record {
+                Ok(r) => r,
+                Err(e) => {
+                    eprintln!("Skipping broken watchlist line: {}", e);
+                    continue;
+                }
+            };
+            let get = |i: usize| record.get(i).map(str::trim).unwrap_or("");
+
+            let pattern = match Pattern::parse(get(1)) {
+                Ok(p) if !get(1).is_empty() => p,
+                Ok(_) => {
+                    eprintln!("Skipping watchlist line without pattern: {:?}", record);
+                    continue;
+                }
+                Err(e) => {
+                    eprintln!("Skipping watchlist line with bad pattern '{}': {}", get(1), e);
+                    continue;
+                }
+            };
+            let Some(priority) = Priority::parse(get(3)) else {
+                eprintln!("Skipping watchlist line with unknown priority '{}' (high, medium, low)", get(3));
+                continue;
+            };
+
+            let mut entry = Entry {
+                hex: None,
+                registration: None,
+                callsign: None,
+                label: if get(2).is_empty() { get(1) } else { get(2) }.to_string(),
+                priority,
+                notes: get(4).to_string(),
+            };
+            match get(0).to_lowercase().as_str() {
+                "hex" | "icao" => entry.hex = Some(pattern),
+                "registration" | "reg" => entry.registration = Some(pattern),
+                "callsign" => entry.callsign = Some(pattern),
+                other => {
+                    eprintln!("Skipping watchlist line with unknown field '{}' (hex, registration, callsign)", other);
+                    continue;
+                }
+            }
+            entries.push(entry);
+        }
+        entries
+    }
+
+    fn parse_toml(content: &str) -> Result<Vec<Entry>, Box<dyn Error>> {
+        let file: TomlFile = toml::from_str(content)?;
+        let pattern = |p: &Option<String>| p.as_deref().map(Pattern::parse).transpose();
+
+        let mut entries = Vec::new();
+        for watch in file.watch {
+            let entry = Entry {
+                hex: pattern(&watch.hex)?,
+                registration: pattern(&watch.registration)?,
+                callsign: pattern(&watch.callsign)?,
+                label: watch.label,
+                priority: watch.priority,
+                notes: watch.notes,
+            };
+            if entry.hex.is_none() && entry.registration.is_none() && entry.callsign.is_none() {
+                return Err(format!("entry '{}' needs a hex, registration or callsign", entry.label).into());
+            }
+            entries.push(entry);
+        }
+        Ok(entries)
+    }
+
+    /// Every entry the aircraft matches. `registration` is the one from the feed or the database.
+    pub fn matches(&self, ac: &Aircraft, registration: Option<&str>) -> Vec<&Entry> {
+        self.entries.iter().filter(|e| e.matches(ac, registration)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn aircraft(icao: &str, callsign: &str) -> Aircraft {
+        Aircraft { icao: icao.to_string(), callsign: Some(callsign.to_string()), ..Default::default() }
+    }
+
+    #[test]
+    fn globs_match_the_whole_value() {
+        let sam = Pattern::parse("SAM*").unwrap();
+        assert!(sam.matches(Some("SAM44")));
+        assert!(sam.matches(Some("sam ")));
+        assert!(!sam.matches(Some("XSAM44")));
+        assert!(!sam.matches(None));
+
+        let hex = Pattern::parse("ae01?e").unwrap();
+        assert!(hex.matches(Some("AE01CE")));
+        assert!(!hex.matches(Some("ae01e")));
+
+        // a dot is a dot, not any character
+        let dotted = Pattern::parse("N1.2").unwrap();
+        assert!(dotted.matches(Some("n1.2")));
+        assert!(!dotted.matches(Some("N1X2")));
+    }
+
+    #[test]
+    fn slashes_make_a_regex() {
+        let reach = Pattern::parse(r"/^RCH\d{3}$/").unwrap();
+        assert!(reach.matches(Some("rch123")));
+        assert!(!reach.matches(Some("RCH1234")));
+        assert!(Pattern::parse("/RCH(/").is_err());
+    }
+
+    #[test]
+    fn csv_lines() {
+        let csv = "field,pattern,label,priority,notes\n\
+                   icao,ae01ce,SAM fleet,medium,VIP transport\n\
+                   reg,N1.2,,low,\n\
+                   callsign,FORTE*,Global Hawk,urgent,\n\
+                   tail,ABC,Unknown field,,\n";
+        let entries = Watchlist::read_csv(csv.as_bytes());
+        assert_eq!(entries.len(), 2);
+
+        assert!(entries[0].hex.as_ref().unwrap().matches(Some("AE01CE")));
+        assert_eq!((entries[0].label.as_str(), entries[0].priority, entries[0].notes.as_str()), ("SAM fleet", Priority::Medium, "VIP transport"));
+
+        // no label: the pattern is the label
+        assert!(entries[1].registration.is_some());
+        assert_eq!((entries[1].label.as_str(), entries[1].priority), ("N1.2", Priority::Low));
+    }
+
+    #[test]
+    fn toml_entries_need_every_pattern() {
+        let list = Watchlist {
+            entries: Watchlist::parse_toml(r#"
+                [[watch]]
+                hex = "ae*"
+                callsign = "SAM*"
+                label = "SAM on a US military hex"
+            "#).unwrap(),
+        };
+        assert_eq!(list.matches(&aircraft("ae01ce", "SAM44"), None).len(), 1);
+        assert!(list.matches(&aircraft("ae01ce", "RCH123"), None).is_empty());
+        assert!(list.matches(&aircraft("4840d6", "SAM44"), None).is_empty());
+    }
+
+    #[test]
+    fn toml_entry_without_pattern_is_an_error() {
+        let err = Watchlist::parse_toml("[[watch]]\nlabel = \"nothing\"\n").unwrap_err();
+        assert!(err.to_string().contains("'nothing'"));
+    }
+
+    #[test]
+    fn priority_sets_score_and_severity() {
+        let entries = Watchlist::read_csv("field,pattern,label,priority,notes\nhex,ae01ce,SAM,high,VIP\nhex,ae01cf,Other,low,\n".as_bytes());
+
+        let high = entries[0].reason();
+        assert_eq!((high.text(), high.score, high.severity), ("WATCHLIST: SAM".to_string(), 100.0, Severity::Warning));
+        assert_eq!(high.notes.as_deref(), Some("VIP"));
+
+        let low = entries[1].reason();
+        assert_eq!((low.score, low.severity, low.notes), (30.0, Severity::Normal, None));
+    }
+}
@@ -0,0 +1,200 @@
+// Runs the stand-in server from `serve-capture` with each fault and checks
+// what the provider code makes of the answers.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::AtomicUsize;
+use std::time::Duration;
+use flight_radar_cli::provider::{self, Backoff, Feed, FeedProvider, FetchError, ProviderKind};
+use flight_radar_cli::query::Query;
+use flight_radar_cli::serve::{self, Fault, ServeConfig};
+use flight_radar_cli::source::{Source, Tiling};
+use reqwest::Client;
+use tokio::net::TcpListener;
+
+const CENTER: (f64, f64) = (52.0, 4.0);
+
+// Starts the server on a free port and returns a provider pointed at it
+async fn start(fault: Fault, fault_every: u64, capture: Option<String>) -> Box<dyn FeedProvider> {
+    let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let config = ServeConfig { port, capture, synthetic: 20, center: CENTER, fault, fault_every };
+    tokio::spawn(async move {
+        let _ = serve::serve(listener, config).await;
+    });
+
+    provider::build_provider(ProviderKind::AirplanesLive, Some(format!("http://127.0.0.1:{}", port)), None)
+}
+
+fn sector() -> Query {
+    Query::Point { lat: CENTER.0, lon: CENTER.1, radius: 250.0 }
+}
+
+// A capture with one aircraft per scan, so we can tell the scans apart
+fn write_capture(name: &str, hexes: &[&str]) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("{}-{}.ndjson", name, std::process::id()));
+    let lines: Vec<String> = hexes.iter()
+        .map(|hex| {
+            let body = format!(r#"{{"ac":[{{"hex":"{}","lat":52.1,"lon":4.1}}],"now":0}}"#, hex);
+            serde_json::json!({
+                "time": "2026-01-01T12:00:00Z",
+                "feed": "airplanes-live",
+                "query": "point/52/4/250",
+                "body": body,
+            }).to_string()
+        })
+        .collect();
+    std::fs::write(&path, lines.join("\n")).unwrap();
+    path
+}
+
+#[tokio::test]
+async fn no_fault_serves_the_synthetic_fleet() {
+    let feed = start(Fault::None, 1, None).await;
+    let body = provider::fetch(feed.as_ref(), &Client::new(), &sector()).await.unwrap();
+    assert!(!feed.parse(&body).unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn http_500_is_a_status_error() {
+    let feed = start(Fault::Http500, 1, None).await;
+    match provider::fetch(feed.as_ref(), &Client::new(), &sector()).await {
+        Err(FetchError::Status { code: 500, retry_after: None }) => {}
+        other => panic!("expected a 500, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn http_429_passes_on_retry_after() {
+    let feed = start(Fault::Http429, 1, None).await;
+    match provider::fetch(feed.as_ref(), &Client::new(), &sector()).await {
+        Err(FetchError::Status { code: 429, retry_after: Some(5) }) => {}
+        other => panic!("expected a 429, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn malformed_body_fails_to_parse() {
+    let feed = start(Fault::Malformed, 1, None).await;
+    let body = provider::fetch(feed.as_ref(), &Client::new(), &sector()).await.unwrap();
+    assert!(body.starts_with("<html>"));
+    assert!(feed.parse(&body).is_err());
+}
+
+#[tokio::test]
+async fn empty_ac_is_an_empty_scan() {
+    let feed = start(Fault::Empty, 1, None).await;
+    let body = provider::fetch(feed.as_ref(), &Client::new(), &sector()).await.unwrap();
+    assert!(feed.parse(&body).unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn faults_dont_skip_recorded_scans() {
+    let path = write_capture("serve-faults", &["aaaaaa", "bbbbbb", "cccccc"]);
+    let feed = start(Fault::Http500, 2, Some(path.to_string_lossy().to_string())).await;
+    let client = Client::new();
+
+    let mut seen = Vec::new();
+    for _ in 0..5 {
+        if let Ok(body) = provider::fetch(feed.as_ref(), &client, &sector()).await {
+            seen.extend(feed.parse(&body).unwrap().into_iter().map(|ac| ac.icao));
+        }
+    }
+    std::fs::remove_file(&path).unwrap();
+
+    // Requests 2 and 4 fail, the other three get the three scans in order
+    assert_eq!(seen, ["aaaaaa", "bbbbbb", "cccccc"]);
+}
+
+#[tokio::test]
+async fn tiles_of_one_scan_get_the_same_recording() {
+    let path = write_capture("serve-tiles", &["aaaaaa", "bbbbbb"]);
+    let feed = start(Fault::None, 1, Some(path.to_string_lossy().to_string())).await;
+    let client = Client::new();
+
+    // two tiles of an area scan, then the first tile of the next scan
+    let tiles = [sector(), Query::Point { lat: 52.5, lon: 4.0, radius: 250.0 }, sector()];
+    let mut seen = Vec::new();
+    for tile in &tiles {
+        let body = provider::fetch(feed.as_ref(), &client, tile).await.unwrap();
+        seen.extend(feed.parse(&body).unwrap().into_iter().map(|ac| ac.icao));
+    }
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(seen, ["aaaaaa", "aaaaaa", "bbbbbb"]);
+}
+
+// The provider wrapped with retries and health, as the scan loop uses it
+// (with a short backoff, so the tests don't sleep through real retries)
+async fn start_feed(fault: Fault, fault_every: u64, requests_per_minute: usize) -> Arc<Feed> {
+    let feed = Feed::new(ProviderKind::AirplanesLive, start(fault, fault_every, None).await, requests_per_minute)
+        .with_backoff(Backoff { base: Duration::from_millis(1), max: Duration::from_millis(10) });
+    Arc::new(feed)
+}
+
+#[tokio::test]
+async fn a_500_is_retried() {
+    let feed = start_feed(Fault::Http500, 2, 100).await;
+    let client = Client::new();
+
+    // request 1 goes through, request 2 fails and its retry (request 3) goes through
+    assert!(feed.fetch(&client, &sector()).await.is_ok());
+    assert!(feed.fetch(&client, &sector()).await.is_ok());
+    // a retry that worked isn't a failed scan
+    assert!(feed.health().ends_with(", 0% errors"), "{}", feed.health());
+    assert!(!feed.is_down());
+}
+
+#[tokio::test]
+async fn failing_primary_hands_over_to_the_fallback() {
+    // room for exactly three scans with all their retries
+    let primary = start_feed(Fault::Http500, 1, 12).await;
+    let fallback = start_feed(Fault::None, 1, 100).await;
+    let source = Source::Feed {
+        feeds: vec![primary.clone(), fallback],
+        active: AtomicUsize::new(0),
+        client: Client::new(),
+        tiling: Tiling { radius: 100.0, concurrency: 1, rate: 1.0 },
+    };
+
+    // the primary gives up after its retries, the fallback answers
+    assert!(source.fetch(&sector()).await.is_ok());
+    assert!(source.label().ends_with("(fallback)"), "{}", source.label());
+    // one scan with all its retries is one failure, not enough to call the primary down
+    assert!(primary.health().ends_with(", 100% errors"), "{}", primary.health());
+    assert!(!primary.is_down());
+
+    // after three failed scans it's down
+    for _ in 0..2 {
+        assert!(source.fetch(&sector()).await.is_ok());
+    }
+    assert!(primary.is_down());
+
+    // and the fallback is asked first: asking the primary would wait a minute for its budget
+    let scan = tokio::time::timeout(Duration::from_secs(5), source.fetch(&sector())).await;
+    assert!(scan.expect("the down primary was asked first").is_ok());
+}
+
+#[tokio::test]
+async fn a_long_retry_after_pauses_the_feed() {
+    // the server asks for 5s, more than this feed waits for a retry
+    let primary = start_feed(Fault::Http429, 1, 100).await;
+    let fallback = start_feed(Fault::None, 1, 100).await;
+    let source = Source::Feed {
+        feeds: vec![primary.clone(), fallback],
+        active: AtomicUsize::new(0),
+        client: Client::new(),
+        tiling: Tiling { radius: 100.0, concurrency: 1, rate: 1.0 },
+    };
+
+    assert!(source.fetch(&sector()).await.is_ok());
+    assert!(primary.paused_for().is_some());
+    // the fallback can still answer, so the scans don't have to wait
+    assert_eq!(source.paused_for(), None);
+
+    // until then the primary isn't asked at all
+    match primary.fetch(&Client::new(), &sector()).await {
+        Err(FetchError::Paused { secs }) => assert!(secs <= 5, "{}", secs),
+        other => panic!("expected a pause, got {:?}", other),
+    }
+}